@@ -0,0 +1,94 @@
+// Compares the linear Morton octree against the previous pointer-based one.
+// Run with `cargo bench --bench octree`.
+
+#![allow(dead_code)]
+
+#[path = "../src/engine/data/mod.rs"]
+mod data;
+mod pointer_octree;
+
+use std::time::{Duration, Instant};
+
+use cgmath::Vector3;
+use noise::{NoiseFn, Perlin};
+
+use data::QuadtreeNode;
+use data::chunk::ChunkMap;
+use pointer_octree::PointerOctree;
+
+const WORLD_SIZES : [u32; 3] = [128, 512, 1024];
+const MAX_HEIGHT : f32 = 200.0;
+const SCALE : f64 = 0.01;
+
+// Same heightmap as the engine terrain, materialised so both trees get identical input
+fn terrain_voxels(size : u32) -> Vec<(Vector3<f32>, u32)> {
+    let perlin = Perlin::new(42);
+    let mut voxels = Vec::new();
+
+    for x in 1..=size {
+        for z in 1..=size {
+            let noise_value = perlin.get([x as f64 * SCALE, z as f64 * SCALE]);
+            let height = ((noise_value + 1.0) / 2.0) as f32 * MAX_HEIGHT / 5.0;
+
+            for y in 1..height as i32 {
+                let material = (y as f32 / (MAX_HEIGHT / 5.0) * 4.0) as u32;
+                voxels.push((Vector3::new(x as f32, y as f32, z as f32), material));
+            }
+        }
+    }
+
+    voxels
+}
+
+fn timed<R>(f : impl FnOnce() -> R) -> (R, Duration) {
+    let start = Instant::now();
+    let result = f();
+    (result, start.elapsed())
+}
+
+fn report(name : &str, insert : Duration, traverse : Duration, leaves : usize, bytes : usize) {
+    println!(
+        "  {:<8} insert {:>10.2?}  traverse {:>10.2?}  leaves {:>9}  memory {:>9.2} MiB",
+        name, insert, traverse, leaves, bytes as f64 / (1024.0 * 1024.0)
+    );
+}
+
+fn main() {
+    for size in WORLD_SIZES {
+        let voxels = terrain_voxels(size);
+        println!("world {}^3, {} voxels", size, voxels.len());
+
+        let (linear, insert) = timed(|| {
+            let mut tree = QuadtreeNode::new(size);
+            voxels.iter().for_each(|(pos, material)| tree.insert_voxel(*pos, *material));
+            tree
+        });
+        let (linear_leaves, traverse) = timed(|| {
+            let mut leaves = 0;
+            linear.visit(|_, _| leaves += 1);
+            leaves
+        });
+        report("linear", insert, traverse, linear_leaves, linear.memory_usage());
+
+        let (chunks, to_chunks) = timed(|| ChunkMap::from_octree(&linear));
+        let (round_trip, from_chunks) = timed(|| chunks.to_octree(size));
+        let mut round_trip_leaves = 0;
+        round_trip.visit(|_, _| round_trip_leaves += 1);
+        println!("  chunks   to {:>14.2?}  from {:>14.2?}", to_chunks, from_chunks);
+        assert_eq!(linear_leaves, round_trip_leaves, "chunk round trip should rebuild the same tree");
+
+        let (pointer, insert) = timed(|| {
+            let mut tree = PointerOctree::new(size);
+            voxels.iter().for_each(|(pos, material)| tree.insert_voxel(*pos, *material));
+            tree
+        });
+        let (pointer_leaves, traverse) = timed(|| {
+            let mut leaves = 0;
+            pointer.visit(|_, _| leaves += 1);
+            leaves
+        });
+        report("pointer", insert, traverse, pointer_leaves, pointer.memory_usage());
+
+        assert_eq!(linear_leaves, pointer_leaves, "both trees should collapse to the same leaves");
+    }
+}
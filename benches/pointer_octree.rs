@@ -0,0 +1,172 @@
+// Pointer-based octree that the linear octree replaced, kept as the benchmark baseline.
+
+use cgmath::{Vector3, Zero};
+
+use crate::data::Aabb;
+
+pub struct PointerOctree<T> {
+    bounds: Aabb, 
+    children: Option<[Box<PointerOctree<T>>; 8]>,
+    data : Option<T>, 
+    remaining_subdivisions : u32, 
+}
+
+fn calc_subdivisions(mut size : f32) -> u32 {
+    let mut subdv : u32 = 0; 
+    while size > 1.0 {
+        size /= 2.0;
+        subdv += 1 
+    }
+
+    subdv 
+}
+
+impl<T : PartialEq + Clone> PointerOctree<T> {
+    pub fn new(cells_per_side : u32) -> PointerOctree<T> {
+
+        let size = cells_per_side as f32; 
+
+        PointerOctree {
+            bounds: Aabb{
+                min : Vector3::zero(), 
+                max : Vector3::new(size, size, size),  
+            }, 
+            children: None, 
+            data: None, 
+            remaining_subdivisions: calc_subdivisions(size), 
+        }
+    }
+
+    fn from_bounds(bounds : Aabb, data : Option<T>) -> PointerOctree<T> {
+        let size = bounds.get_size().x; 
+
+        PointerOctree {
+            bounds, 
+            children: None, 
+            data, 
+            remaining_subdivisions: calc_subdivisions(size), 
+        }
+    }
+
+    pub fn insert_voxel(&mut self, pos: Vector3<f32>, data: T) {
+        // Check if we are at the max LOD (smallest subdivision)
+        if self.remaining_subdivisions == 0 {
+            self.data = Some(data); 
+            return;
+        }
+
+        // A collapsed node already holding the same payload has nothing to change
+        if self.children.is_none() && self.data.as_ref() == Some(&data) {
+            return;
+        }
+
+        // If this node has no children, create them, handing down any collapsed payload
+        if self.children.is_none() {
+            let inherited = self.data.take();
+            let children = std::array::from_fn(|i| {
+                Box::new(PointerOctree::from_bounds(self.subdivide(i), inherited.clone()))
+            });
+            self.children = Some(children);
+        }
+
+        // Forward voxel insertion to the correct child
+        if let Some(children) = &mut self.children {
+            for child in children.iter_mut() {
+                if child.bounds.contains(pos) {
+                    child.insert_voxel(pos, data);
+
+                    self.try_merge();
+                    return;
+                }
+            }
+        }
+    }
+
+    // Collapses the children into this node when they are all leaves with an equal payload
+    fn try_merge(&mut self) {
+        let Some(children) = &self.children else { return; };
+
+        let first = match &children[0].data {
+            Some(data) if children[0].children.is_none() => data,
+            _ => return,
+        };
+
+        let mergeable = children.iter().all(|child| {
+            child.children.is_none() && child.data.as_ref() == Some(first)
+        });
+
+        if mergeable {
+            self.data = Some(first.clone());
+            self.children = None; 
+        }
+    }
+
+    fn subdivide(&self, index: usize) -> Aabb {
+        let center = self.bounds.get_center();
+
+        match index {
+            0 => Aabb { // Front-top-left
+                min: self.bounds.min,
+                max: Vector3::new(center.x, center.y, center.z),
+            },
+            1 => Aabb { // Front-top-right
+                min: Vector3::new(center.x, self.bounds.min.y, self.bounds.min.z),
+                max: Vector3::new(self.bounds.max.x, center.y, center.z),
+            },
+            2 => Aabb { // Front-bottom-left
+                min: Vector3::new(self.bounds.min.x, center.y, self.bounds.min.z),
+                max: Vector3::new(center.x, self.bounds.max.y, center.z),
+            },
+            3 => Aabb { // Front-bottom-right
+                min: Vector3::new(center.x, center.y, self.bounds.min.z),
+                max: Vector3::new(self.bounds.max.x, self.bounds.max.y, center.z),
+            },
+            4 => Aabb { // Back-top-left
+                min: Vector3::new(self.bounds.min.x, self.bounds.min.y, center.z),
+                max: Vector3::new(center.x, center.y, self.bounds.max.z),
+            },
+            5 => Aabb { // Back-top-right
+                min: Vector3::new(center.x, self.bounds.min.y, center.z),
+                max: Vector3::new(self.bounds.max.x, center.y, self.bounds.max.z),
+            },
+            6 => Aabb { // Back-bottom-left
+                min: Vector3::new(self.bounds.min.x, center.y, center.z),
+                max: Vector3::new(center.x, self.bounds.max.y, self.bounds.max.z),
+            },
+            7 => Aabb { // Back-bottom-right
+                min: Vector3::new(center.x, center.y, center.z),
+                max: self.bounds.max,
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    // Calls the visitor once per filled leaf with its bounds and payload
+    pub fn visit<F>(&self, mut visitor: F)
+        where F : FnMut(&Aabb, &T)
+    {
+        self.visit_leaves(&mut visitor);
+    }
+
+    fn visit_leaves<F>(&self, visitor: &mut F)
+        where F : FnMut(&Aabb, &T)
+    {
+        if let Some(children) = &self.children {
+            for child in children.iter() {
+                child.visit_leaves(visitor);
+            }
+        } else if let Some(data) = &self.data {
+            visitor(&self.bounds, data);
+        }
+    }
+
+    // Bytes held by this node and every boxed child below it
+    pub fn memory_usage(&self) -> usize {
+        let children = match &self.children {
+            Some(children) => children.iter().map(|child| child.memory_usage()).sum(),
+            None => 0,
+        };
+        std::mem::size_of::<Self>() + children
+    }
+
+}
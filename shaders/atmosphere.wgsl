@@ -0,0 +1,127 @@
+// Single scattering atmosphere of an Earth-like planet, distances in kilometres. Shared by the
+// sky passes and main.wgsl, which reads the lookup tables for the aerial perspective.
+
+struct AtmosphereData {
+    // Clip space to world space directions from the camera
+    inverse_view_projection: mat4x4<f32>,
+    // xyz: unit direction towards the sun
+    sun_direction: vec4<f32>,
+    // Illuminance of the sun
+    sun_color: vec4<f32>,
+    // x: camera altitude, y: kilometres per world unit, z: aerial perspective strength, w: 1 when enabled
+    params: vec4<f32>,
+    // xyz: world position of the camera, w: far plane distance
+    camera_position: vec4<f32>,
+    // x: fog density, y: height fog density at the base height, z: height fog falloff, w: base height
+    fog: vec4<f32>,
+    // Lit fog colour, w: 1 when the fog is enabled
+    fog_color: vec4<f32>,
+    // xyz: absorption of the water per world unit, w: water level
+    water_absorption: vec4<f32>,
+    // Lit water colour, w: 1 when the camera is underwater
+    water_color: vec4<f32>,
+};
+
+let GROUND_RADIUS = 6360.0;
+let ATMOSPHERE_RADIUS = 6460.0;
+let RAYLEIGH_SCATTERING = vec3<f32>(5.802e-3, 13.558e-3, 33.1e-3);
+let RAYLEIGH_SCALE_HEIGHT = 8.0;
+let MIE_SCATTERING = 3.996e-3;
+let MIE_ABSORPTION = 4.4e-3;
+let MIE_SCALE_HEIGHT = 1.2;
+// Asymmetry of the Mie phase function, positive for forward scattering
+let MIE_G = 0.8;
+// Absorption of the ozone layer, densest 25 km above the ground
+let OZONE_ABSORPTION = vec3<f32>(0.650e-3, 1.881e-3, 0.085e-3);
+
+let TRANSMITTANCE_LUT_SIZE = vec2<f32>(256.0, 64.0);
+let SKY_VIEW_LUT_SIZE = vec2<f32>(192.0, 108.0);
+
+struct Medium {
+    rayleigh_scattering: vec3<f32>,
+    mie_scattering: f32,
+    extinction: vec3<f32>,
+};
+
+// Scattering and extinction coefficients at a height above the ground
+fn atmosphere_medium(height: f32) -> Medium {
+    let rayleigh_density = exp(-height / RAYLEIGH_SCALE_HEIGHT);
+    let mie_density = exp(-height / MIE_SCALE_HEIGHT);
+    let ozone_density = max(0.0, 1.0 - abs(height - 25.0) / 15.0);
+
+    var medium: Medium;
+    medium.rayleigh_scattering = RAYLEIGH_SCATTERING * rayleigh_density;
+    medium.mie_scattering = MIE_SCATTERING * mie_density;
+    medium.extinction = medium.rayleigh_scattering + vec3<f32>((MIE_SCATTERING + MIE_ABSORPTION) * mie_density) + OZONE_ABSORPTION * ozone_density;
+    return medium;
+}
+
+// Distance along the ray to the first intersection ahead with a sphere around the planet
+// centre, negative when there is none
+fn ray_sphere(origin: vec3<f32>, direction: vec3<f32>, radius: f32) -> f32 {
+    let b = dot(origin, direction);
+    let c = dot(origin, origin) - radius * radius;
+    let discriminant = b * b - c;
+    if (discriminant < 0.0) {
+        return -1.0;
+    }
+    let root = sqrt(discriminant);
+    return select(-b + root, -b - root, -b - root > 0.0);
+}
+
+fn rayleigh_phase(cos_theta: f32) -> f32 {
+    // 3 / (16 pi)
+    return 0.0596831 * (1.0 + cos_theta * cos_theta);
+}
+
+// Cornette-Shanks approximation of the Mie phase function
+fn mie_phase(cos_theta: f32) -> f32 {
+    let g2 = MIE_G * MIE_G;
+    // 3 / (8 pi)
+    return 0.1193662 * (1.0 - g2) * (1.0 + cos_theta * cos_theta) / ((2.0 + g2) * pow(1.0 + g2 - 2.0 * MIE_G * cos_theta, 1.5));
+}
+
+// Keeps a lookup coordinate on the texel centres, the samplers repeat across the azimuth
+fn clamp_to_texels(coordinate: f32, size: f32) -> f32 {
+    return clamp(coordinate, 0.5 / size, 1.0 - 0.5 / size);
+}
+
+// The transmittance table covers the cosine of the zenith angle across and the height, with
+// more texels close to the ground, down
+fn transmittance_uv(height: f32, cos_zenith: f32) -> vec2<f32> {
+    let u = cos_zenith * 0.5 + 0.5;
+    let v = sqrt(clamp(height / (ATMOSPHERE_RADIUS - GROUND_RADIUS), 0.0, 1.0));
+    return vec2<f32>(clamp_to_texels(u, TRANSMITTANCE_LUT_SIZE.x), clamp_to_texels(v, TRANSMITTANCE_LUT_SIZE.y));
+}
+
+// The sky view table covers the azimuth across and the elevation down, with more texels
+// close to the horizon
+fn sky_view_uv(direction: vec3<f32>) -> vec2<f32> {
+    let u = atan2(direction.z, direction.x) / 6.2831853 + 0.5;
+    let elevation = asin(clamp(direction.y, -1.0, 1.0));
+    let v = 0.5 - 0.5 * sign(elevation) * sqrt(abs(elevation) / 1.5707963);
+    return vec2<f32>(u, clamp_to_texels(v, SKY_VIEW_LUT_SIZE.y));
+}
+
+// Fog or water between the camera and a point `to_point` away from it. The height fog density
+// falls exponentially above its base height and is integrated exactly along the ray.
+fn apply_fog(data: AtmosphereData, color: vec3<f32>, to_point: vec3<f32>) -> vec3<f32> {
+    let distance = length(to_point);
+    if (data.water_color.w != 0.0) {
+        let transmittance = exp(-data.water_absorption.xyz * distance);
+        return color * transmittance + data.water_color.rgb * (1.0 - transmittance);
+    }
+    if (data.fog_color.w == 0.0) {
+        return color;
+    }
+
+    let falloff = data.fog.z;
+    // Kept within the range of exp
+    let slope = clamp(falloff * to_point.y, -80.0, 80.0);
+    var height_fog = data.fog.y * exp(-falloff * (data.camera_position.y - data.fog.w)) * distance;
+    if (abs(slope) > 0.0001) {
+        height_fog = height_fog * (1.0 - exp(-slope)) / slope;
+    }
+    let fog_amount = 1.0 - exp(-(data.fog.x * distance + height_fog));
+    return mix(color, data.fog_color.rgb, fog_amount);
+}
@@ -0,0 +1,53 @@
+#include "local_lights.wgsl";
+
+// Assigns the lights to the clusters whose view space box their range reaches, one cluster per invocation
+
+@group(0) @binding(0)
+var<uniform> clusters: ClusterData;
+@group(0) @binding(1)
+var<storage, read> lights: array<LocalLight>;
+@group(0) @binding(2)
+var<storage, read_write> cluster_lights: array<u32>;
+
+// View space depth of the near side of a depth slice, the slices grow exponentially with the distance
+fn slice_depth(slice: f32) -> f32 {
+    return clusters.screen.z * pow(clusters.screen.w / clusters.screen.z, slice / clusters.counts.z);
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let counts = vec3<u32>(clusters.counts.xyz);
+    if (id.x >= counts.x * counts.y * counts.z) {
+        return;
+    }
+    let x = id.x % counts.x;
+    let y = (id.x / counts.x) % counts.y;
+    let z = id.x / (counts.x * counts.y);
+
+    // Bounds of the tile in normalized device coordinates, rows counted from the top of the frame
+    let tile_min = vec2<f32>(f32(x) / f32(counts.x) * 2.0 - 1.0, 1.0 - f32(y + 1u) / f32(counts.y) * 2.0);
+    let tile_max = vec2<f32>(f32(x + 1u) / f32(counts.x) * 2.0 - 1.0, 1.0 - f32(y) / f32(counts.y) * 2.0);
+    let near = slice_depth(f32(z));
+    let far = slice_depth(f32(z + 1u));
+
+    // The tile widens with the depth, its box spans the tile at both ends of the slice
+    let min_slope = tile_min / clusters.projection.xy;
+    let max_slope = tile_max / clusters.projection.xy;
+    let box_min = vec3<f32>(min(min_slope * near, min_slope * far), -far);
+    let box_max = vec3<f32>(max(max_slope * near, max_slope * far), -near);
+
+    // The light count of the cluster comes before its light indices
+    let max_lights = u32(clusters.projection.z);
+    let base = id.x * (max_lights + 1u);
+    var count = 0u;
+    for (var i = 0u; i < u32(clusters.counts.w); i = i + 1u) {
+        let light = lights[i];
+        let center = (clusters.view * vec4<f32>(light.position_range.xyz, 1.0)).xyz;
+        let offset = center - clamp(center, box_min, box_max);
+        if (count < max_lights && dot(offset, offset) <= light.position_range.w * light.position_range.w) {
+            cluster_lights[base + 1u + count] = i;
+            count = count + 1u;
+        }
+    }
+    cluster_lights[base] = count;
+}
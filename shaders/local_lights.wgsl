@@ -0,0 +1,22 @@
+// Point and spot lights, sorted into clusters by light_clusters.wgsl and read by main.wgsl
+
+struct LocalLight {
+    // xyz: position, w: range at which the light fades out
+    position_range: vec4<f32>,
+    // Colour scaled by the intensity
+    color: vec4<f32>,
+    // xyz: spot direction, w: 1 for spot lights
+    direction: vec4<f32>,
+    // x: cosine of the inner cone angle, y: cosine of the outer cone angle
+    cone: vec4<f32>,
+};
+
+struct ClusterData {
+    view: mat4x4<f32>,
+    // x: frame width, y: frame height, z: near plane, w: far plane
+    screen: vec4<f32>,
+    // xyz: clusters across, down and in depth, w: number of lights
+    counts: vec4<f32>,
+    // xy: projection scale of view space x and y, z: maximum number of lights per cluster
+    projection: vec4<f32>,
+};
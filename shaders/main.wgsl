@@ -1,12 +1,23 @@
 
 #include "consts.wgsl"; 
+#include "local_lights.wgsl";
+#include "atmosphere.wgsl";
 
 struct InstancedVertexInput {
     @location(0) v_position: vec4<f32>,
+    @location(1) v_normal: vec3<f32>,
+    // Direction of increasing u, w is the sign of the bitangent
+    @location(2) v_tangent: vec4<f32>,
+    @location(3) v_uv: vec2<f32>,
 }
 struct InstanceInput {
-    @location(1) i_position: vec4<f32>,
-    @location(2) i_size: vec4<f32>,
+    @location(4) i_position: vec4<f32>,
+    @location(5) i_size: vec4<f32>,
+    @location(6) i_material: u32,
+    // Baked ambient occlusion of the four face corners
+    @location(7) i_occlusion: vec4<f32>,
+    // Flood-filled sky and block light in front of the face
+    @location(8) i_light: vec4<f32>,
 }
 
 struct TerrainVertexOutput {
@@ -14,6 +25,12 @@ struct TerrainVertexOutput {
     @location(1) original_position: vec3<f32>,
     @location(2) normal: vec4<f32>, 
     @location(3) idx : i32, 
+    @location(4) world_position: vec3<f32>,
+    @location(5) view_depth: f32,
+    @location(6) corner_occlusion: f32,
+    @location(7) voxel_light: vec2<f32>,
+    @location(8) tangent: vec4<f32>,
+    @location(9) uv: vec2<f32>,
 };
 
 struct CameraData {
@@ -27,6 +44,9 @@ var<uniform> camera_data: CameraData;
 struct Light {
     direction: vec4<f32>,
     color: vec4<f32>,
+    // x: shading model, 0 for Phong, 1 for Cook-Torrance GGX and 2 to show the normals
+    params: vec4<f32>,
+    ambient: vec4<f32>,
 };
 @group(1) @binding(0)
 var<uniform> light: Light;
@@ -37,22 +57,299 @@ struct Material {
     shininess: f32,
     metallic: f32,
     roughness: f32,
+    // RGB emitted colour, intensity in w
+    emissive: vec4<f32>,
+    // Texture array layers of the top, side and bottom faces, negative for none
+    texture_layers: vec3<f32>,
+    // Scale of the normal map tilt, 0 for the flat face normal
+    normal_strength: f32,
 };
 
 @group(2) @binding(0)
 var<storage, read> materials: array<Material>;
 
+struct ShadowData {
+    view_projections: array<mat4x4<f32>, 4>,
+    // Far view distance of each cascade
+    splits: vec4<f32>,
+    // Depth bias of each cascade
+    biases: vec4<f32>,
+    // x: cascade count, y: texel size, z: 1 when shadows are enabled, w: 1 to tint the cascades
+    params: vec4<f32>,
+    // x: fraction of a cascade blended with the next one
+    blend: vec4<f32>,
+};
+
+@group(3) @binding(0)
+var<uniform> shadow: ShadowData;
+@group(3) @binding(1)
+var shadow_map: texture_depth_2d_array;
+@group(3) @binding(2)
+var shadow_sampler: sampler_comparison;
+
+// Blurred screen-space ambient occlusion, one texel per pixel of the frame
+@group(4) @binding(0)
+var ambient_occlusion: texture_2d<f32>;
+
+// Colour textures of the voxel faces, multiplied by the diffuse colour of the material, and
+// their tangent-space normal maps
+@group(5) @binding(0)
+var voxel_textures: texture_2d_array<f32>;
+@group(5) @binding(1)
+var voxel_normal_maps: texture_2d_array<f32>;
+@group(5) @binding(2)
+var voxel_sampler: sampler;
+
+// Point and spot lights and the indices of those reaching each cluster
+@group(6) @binding(0)
+var<uniform> clusters: ClusterData;
+@group(6) @binding(1)
+var<storage, read> lights: array<LocalLight>;
+@group(6) @binding(2)
+var<storage, read> cluster_lights: array<u32>;
+
+// Atmosphere and its lookup tables, shared with the sky pass
+@group(7) @binding(0)
+var<uniform> atmosphere: AtmosphereData;
+@group(7) @binding(1)
+var transmittance_lut: texture_2d<f32>;
+@group(7) @binding(2)
+var sky_view_lut: texture_2d<f32>;
+@group(7) @binding(3)
+var lut_sampler: sampler;
+
+let CASCADE_COLORS = array<vec3<f32>, 4>(
+    vec3<f32>(1.0, 0.3, 0.3),
+    vec3<f32>(0.3, 1.0, 0.3),
+    vec3<f32>(0.3, 0.3, 1.0),
+    vec3<f32>(1.0, 1.0, 0.3)
+);
+
+let PI = 3.14159265;
+// Reflectance at normal incidence of dielectrics
+let DIELECTRIC_F0 = vec3<f32>(0.04, 0.04, 0.04);
+
+// Diffuse and specular light reflected towards the viewer, the Phong model
+fn phong(material: Material, albedo: vec3<f32>, normal: vec3<f32>, light_dir: vec3<f32>, view_dir: vec3<f32>, light_color: vec3<f32>) -> vec3<f32> {
+    let diffuse = albedo * max(dot(normal, light_dir), 0.0);
+
+    let reflect_dir = reflect(-light_dir, normal);
+    let specular_intensity = pow(max(dot(view_dir, reflect_dir), 0.0), material.shininess);
+    let specular = material.specular_color.rgb * specular_intensity;
+    return (diffuse + specular) * light_color;
+}
+
+// GGX normal distribution
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Smith masking-shadowing with the Schlick-GGX approximation for direct light
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    return n_dot_v / (n_dot_v * (1.0 - k) + k) * n_dot_l / (n_dot_l * (1.0 - k) + k);
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Light reflected towards the viewer with the Cook-Torrance GGX BRDF. The light colour is the
+// irradiance of a surface facing the light, as in the Phong model.
+fn cook_torrance(material: Material, albedo: vec3<f32>, normal: vec3<f32>, light_dir: vec3<f32>, view_dir: vec3<f32>, light_color: vec3<f32>) -> vec3<f32> {
+    // Very smooth surfaces would reduce the highlight of a directional light to a point
+    let roughness = clamp(material.roughness, 0.05, 1.0);
+    let half_dir = normalize(light_dir + view_dir);
+    let n_dot_l = max(dot(normal, light_dir), 0.0);
+    let n_dot_v = max(dot(normal, view_dir), 0.0001);
+    let n_dot_h = max(dot(normal, half_dir), 0.0);
+
+    let f0 = mix(DIELECTRIC_F0, albedo, material.metallic);
+    let fresnel = fresnel_schlick(max(dot(half_dir, view_dir), 0.0), f0);
+    let specular = distribution_ggx(n_dot_h, roughness) * geometry_smith(n_dot_v, n_dot_l, roughness) * fresnel
+        / (4.0 * n_dot_v * max(n_dot_l, 0.0001));
+    // Metals have no diffuse reflection, and what the specular reflects does not enter the surface
+    let diffuse = (vec3<f32>(1.0) - fresnel) * (1.0 - material.metallic) * albedo / PI;
+
+    return (diffuse + specular) * light_color * PI * n_dot_l;
+}
+
+// Cluster of the frame containing a pixel at the given view space depth
+fn cluster_index(pixel: vec2<f32>, view_depth: f32) -> u32 {
+    let counts = vec3<u32>(clusters.counts.xyz);
+    let x = min(u32(pixel.x / clusters.screen.x * clusters.counts.x), counts.x - 1u);
+    let y = min(u32(pixel.y / clusters.screen.y * clusters.counts.y), counts.y - 1u);
+    let slice = log(view_depth / clusters.screen.z) / log(clusters.screen.w / clusters.screen.z) * clusters.counts.z;
+    let z = u32(clamp(slice, 0.0, clusters.counts.z - 1.0));
+    return (z * counts.y + y) * counts.x + x;
+}
+
+// Light of the point and spot lights reaching the cluster of the pixel
+fn local_lighting(material: Material, albedo: vec3<f32>, normal: vec3<f32>, view_dir: vec3<f32>, world_position: vec3<f32>, pixel: vec2<f32>, view_depth: f32) -> vec3<f32> {
+    // The light count of the cluster comes before its light indices
+    let base = cluster_index(pixel, view_depth) * (u32(clusters.projection.z) + 1u);
+    let count = cluster_lights[base];
+    var total = vec3<f32>(0.0);
+    for (var i = 0u; i < count; i = i + 1u) {
+        let local_light = lights[cluster_lights[base + 1u + i]];
+        let to_light = local_light.position_range.xyz - world_position;
+        let distance = length(to_light);
+        let light_dir = to_light / max(distance, 0.0001);
+
+        // Inverse square falloff, smoothly brought to zero at the range
+        let window = clamp(1.0 - pow(distance / local_light.position_range.w, 4.0), 0.0, 1.0);
+        var radiance = local_light.color.rgb * window * window / (distance * distance + 1.0);
+        if (local_light.direction.w != 0.0) {
+            radiance = radiance * smoothstep(local_light.cone.y, local_light.cone.x, dot(-light_dir, local_light.direction.xyz));
+        }
+
+        if (light.params.x == 0.0) {
+            total = total + phong(material, albedo, normal, light_dir, view_dir, radiance);
+        } else {
+            total = total + cook_torrance(material, albedo, normal, light_dir, view_dir, radiance);
+        }
+    }
+    return total;
+}
+
+// Texture layer of the material for the face direction, negative for none
+fn face_layer(material: Material, normal: vec3<f32>) -> f32 {
+    if (normal.y > 0.5) {
+        return material.texture_layers.x;
+    } else if (normal.y < -0.5) {
+        return material.texture_layers.z;
+    }
+    return material.texture_layers.y;
+}
+
+// Diffuse colour of the material, sampled from the texture of the face direction when it has one
+fn material_albedo(material: Material, normal: vec3<f32>, uv: vec2<f32>) -> vec3<f32> {
+    let layer = face_layer(material, normal);
+    // Sampled outside of the branch, the mip level needs the derivatives of every pixel
+    let texel = textureSample(voxel_textures, voxel_sampler, uv, i32(max(layer, 0.0))).rgb;
+    return material.diffuse_color.rgb * select(texel, vec3<f32>(1.0), layer < 0.0);
+}
+
+// Face normal tilted by the normal map of the face texture, in the tangent, bitangent and normal
+// basis of the face
+fn mapped_normal(material: Material, normal: vec3<f32>, tangent: vec4<f32>, uv: vec2<f32>) -> vec3<f32> {
+    let layer = face_layer(material, normal);
+    let texel = textureSample(voxel_normal_maps, voxel_sampler, uv, i32(max(layer, 0.0))).xyz * 2.0 - 1.0;
+    let strength = select(material.normal_strength, 0.0, layer < 0.0);
+    let tilted = vec3<f32>(texel.xy * strength, texel.z);
+    let bitangent = cross(normal, tangent.xyz) * tangent.w;
+    // Green points up the image, against the bitangent that follows v
+    return normalize(tangent.xyz * tilted.x - bitangent * tilted.y + normal * tilted.z);
+}
+
+// Colour of the light spread by emissive voxels
+let BLOCK_LIGHT_COLOR = vec3<f32>(1.0, 0.6, 0.35);
+
+// Sunlight scattered into and out of the air between the camera and a surface, using the
+// extinction of the air at the ground and the colour of the sky at the horizon for the haze
+fn aerial_perspective(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let to_surface = world_position - camera_data.position.xyz;
+    let distance = length(to_surface) * atmosphere.params.y * atmosphere.params.z * atmosphere.params.w;
+    let extinction = RAYLEIGH_SCATTERING + vec3<f32>(MIE_SCATTERING + MIE_ABSORPTION);
+    let transmittance = exp(-extinction * distance);
+
+    let horizon = normalize(vec3<f32>(to_surface.x, max(to_surface.y, 0.02 * length(to_surface)), to_surface.z));
+    let haze = textureSampleLevel(sky_view_lut, lut_sampler, sky_view_uv(horizon), 0.0).rgb * atmosphere.sun_color.rgb;
+    return color * transmittance + haze * (1.0 - transmittance);
+}
+
+// Brightness of a voxel light level, every level below the maximum dims it by a fifth
+fn light_curve(level: f32) -> f32 {
+    return pow(0.8, (1.0 - level) * 15.0);
+}
+
+// Fraction of light reaching a point in one cascade, averaged over a 3x3 texel neighbourhood (PCF)
+fn cascade_visibility(cascade: i32, world_position: vec3<f32>) -> f32 {
+    let light_space = shadow.view_projections[cascade] * vec4<f32>(world_position, 1.0);
+    let ndc = light_space.xyz / light_space.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0 || ndc.z > 1.0) {
+        return 1.0;
+    }
+
+    let depth = ndc.z - shadow.biases[cascade];
+    var visibility = 0.0;
+    for (var x: i32 = -1; x <= 1; x = x + 1) {
+        for (var y: i32 = -1; y <= 1; y = y + 1) {
+            let offset = vec2<f32>(f32(x), f32(y)) * shadow.params.y;
+            visibility = visibility + textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, cascade, depth);
+        }
+    }
+    return visibility / 9.0;
+}
+
+// Index of the cascade covering a view distance, or the cascade count past the last one
+fn select_cascade(view_depth: f32) -> i32 {
+    let count = i32(shadow.params.x);
+    var cascade = 0;
+    loop {
+        if (cascade >= count || view_depth < shadow.splits[cascade]) {
+            break;
+        }
+        cascade = cascade + 1;
+    }
+    return cascade;
+}
+
+// Visibility of a point, fading into the next cascade near the end of each one
+fn shadow_factor(world_position: vec3<f32>, view_depth: f32) -> f32 {
+    let count = i32(shadow.params.x);
+    let cascade = select_cascade(view_depth);
+    if (shadow.params.z == 0.0 || cascade >= count) {
+        return 1.0;
+    }
+
+    let visibility = cascade_visibility(cascade, world_position);
+
+    var start = 0.0;
+    if (cascade > 0) {
+        start = shadow.splits[cascade - 1];
+    }
+    let end = shadow.splits[cascade];
+    let blend_start = end - (end - start) * shadow.blend.x;
+    if (view_depth <= blend_start) {
+        return visibility;
+    }
+
+    // The last cascade fades out to fully lit
+    var next = 1.0;
+    if (cascade + 1 < count) {
+        next = cascade_visibility(cascade + 1, world_position);
+    }
+    return mix(visibility, next, (view_depth - blend_start) / (end - blend_start));
+}
+
 @vertex
 fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
     vertex_input: InstancedVertexInput,
     instance_input: InstanceInput,
 ) -> TerrainVertexOutput {
 
     var out: TerrainVertexOutput;
-    out.position = camera_data.transform * (IDENTITY_MATRIX * vertex_input.v_position * instance_input.i_size + instance_input.i_position);
+    let world_position = IDENTITY_MATRIX * vertex_input.v_position * instance_input.i_size + instance_input.i_position;
+    out.position = camera_data.transform * world_position;
+    out.world_position = world_position.xyz / world_position.w;
+    // w of a perspective projection is the distance in front of the camera, scaled by the w of the position
+    out.view_depth = out.position.w / world_position.w;
     out.original_position = vertex_input.v_position.xyz + instance_input.i_position.xyz;
-    out.normal = vec4<f32>(normalize(vertex_input.v_position.xyz), 1.0); 
-    out.idx = i32(instance_input.i_position.x) % 8; 
+    out.normal = vec4<f32>(vertex_input.v_normal, 0.0);
+    out.tangent = vertex_input.v_tangent;
+    // One repetition of the texture per voxel of the face
+    let bitangent = cross(vertex_input.v_normal, vertex_input.v_tangent.xyz) * vertex_input.v_tangent.w;
+    let face_size = vec2<f32>(dot(abs(vertex_input.v_tangent.xyz), instance_input.i_size.xyz), dot(abs(bitangent), instance_input.i_size.xyz));
+    out.uv = vertex_input.v_uv * face_size;
+    out.idx = i32(instance_input.i_material); 
+    out.corner_occlusion = instance_input.i_occlusion[vertex_index];
+    out.voxel_light = instance_input.i_light.xy;
     return out;
 }
 
@@ -61,27 +358,51 @@ fn fs_main(in: TerrainVertexOutput) -> @location(0) vec4<f32> {
 
     let material = materials[in.idx];
 
-    let normal = normalize(in.normal.xyz);
-    let light_dir = normalize(light.direction.xyz); 
-    let view_dir = normalize(camera_data.position.xyz - in.position.xyz);
-    let reflect_dir = reflect(-light_dir, normal);
+    let face_normal = normalize(in.normal.xyz);
+    let normal = mapped_normal(material, face_normal, in.tangent, in.uv);
+    if (light.params.x == 2.0) {
+        return vec4<f32>(normal * 0.5 + 0.5, 1.0);
+    }
+    // The light direction points away from the light
+    let light_dir = normalize(-light.direction.xyz); 
+    let view_dir = normalize(camera_data.position.xyz - in.world_position);
 
     // Ambient light
-    let ambient_strength = 0.2;
-    let ambient = material.diffuse_color.rgb * ambient_strength;
+    let occlusion = textureLoad(ambient_occlusion, vec2<i32>(in.position.xy), 0).r;
+    let albedo = material_albedo(material, face_normal, in.uv);
+    let ambient = albedo * light.ambient.rgb * occlusion;
+
+    // Diffuse and specular lighting
+    var direct: vec3<f32>;
+    if (light.params.x == 0.0) {
+        direct = phong(material, albedo, normal, light_dir, view_dir, light.color.rgb);
+    } else {
+        direct = cook_torrance(material, albedo, normal, light_dir, view_dir, light.color.rgb);
+    }
 
-    // Diffuse lighting
-    let diffuse_intensity = max(dot(normal, light_dir), 0.0);
-    let diffuse = material.diffuse_color.rgb * diffuse_intensity;
+    // Final color calculation, only ambient light reaches shadowed surfaces
+    let visibility = shadow_factor(in.world_position, in.view_depth);
+    // Baked corner occlusion darkens the creases of the terrain for all lights, never to black
+    let corner_light = mix(0.35, 1.0, in.corner_occlusion);
+    // The sun and the sky only reach as far into caves as the sky light
+    let sky_light = light_curve(in.voxel_light.x);
+    let block_light = BLOCK_LIGHT_COLOR * light_curve(in.voxel_light.y) * step(0.001, in.voxel_light.y);
+    let local_light = local_lighting(material, albedo, normal, view_dir, in.world_position, in.position.xy, in.view_depth);
+    var final_color = ((ambient + direct * visibility) * sky_light + albedo * block_light + local_light) * corner_light;
 
-    // Specular lighting (Phong)
-    let specular_strength = material.shininess; // Defined per material
-    let shininess = material.shininess;
-    let specular_intensity = pow(max(dot(view_dir, reflect_dir), 0.0), shininess);
-    let specular = material.specular_color.rgb * specular_strength * specular_intensity;
+    // Emitted light is independent of the lighting, and bright enough to bloom
+    final_color = final_color + material.emissive.rgb * material.emissive.w;
+    final_color = aerial_perspective(final_color, in.world_position);
+    final_color = apply_fog(atmosphere, final_color, in.world_position - camera_data.position.xyz);
 
-    // Final color calculation
-    let final_color = ambient + diffuse + specular;
+    if (shadow.params.w != 0.0) {
+        let cascade = select_cascade(in.view_depth);
+        if (cascade < i32(shadow.params.x)) {
+            // Constant arrays can only be indexed dynamically through a variable
+            var colors = CASCADE_COLORS;
+            final_color = final_color * colors[cascade];
+        }
+    }
 
     return vec4<f32>(final_color, 1.0);
 }
\ No newline at end of file
@@ -0,0 +1,15 @@
+#include "fullscreen.wgsl";
+
+// Input: the scene. x: bloom intensity
+
+@group(2) @binding(0)
+var bloom_texture: texture_2d<f32>;
+@group(2) @binding(1)
+var bloom_sampler: sampler;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let scene = sample_input(in.uv);
+    let bloom = textureSample(bloom_texture, bloom_sampler, in.uv).rgb;
+    return vec4<f32>(scene.rgb + bloom * params.x, scene.a);
+}
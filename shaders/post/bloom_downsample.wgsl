@@ -0,0 +1,61 @@
+#include "fullscreen.wgsl";
+
+// x: threshold, y: soft knee, z: 1 for the first pass reading the scene
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// Keeps the part of the colour above the threshold, with a quadratic knee below it
+fn prefilter(color: vec3<f32>) -> vec3<f32> {
+    let brightness = max(color.r, max(color.g, color.b));
+    let knee = params.x * params.y + 0.0001;
+    let soft = clamp(brightness - params.x + knee, 0.0, 2.0 * knee);
+    let contribution = max(soft * soft / (4.0 * knee), brightness - params.x) / max(brightness, 0.0001);
+    return color * contribution;
+}
+
+// Average of a 2x2 box, weighted down by its brightness so single bright texels do not flicker
+fn karis_average(a: vec3<f32>, b: vec3<f32>, c: vec3<f32>, d: vec3<f32>) -> vec4<f32> {
+    let average = (a + b + c + d) * 0.25;
+    let weight = 1.0 / (1.0 + luminance(average));
+    return vec4<f32>(average * weight, weight);
+}
+
+// 13 tap filter of Jimenez's "Next Generation Post Processing in Call of Duty: Advanced Warfare",
+// five overlapping 2x2 boxes sampled with bilinear filtering
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(input_texture));
+    let uv = in.uv;
+
+    let a = sample_input(uv + texel * vec2<f32>(-2.0, -2.0)).rgb;
+    let b = sample_input(uv + texel * vec2<f32>(0.0, -2.0)).rgb;
+    let c = sample_input(uv + texel * vec2<f32>(2.0, -2.0)).rgb;
+    let d = sample_input(uv + texel * vec2<f32>(-2.0, 0.0)).rgb;
+    let e = sample_input(uv).rgb;
+    let f = sample_input(uv + texel * vec2<f32>(2.0, 0.0)).rgb;
+    let g = sample_input(uv + texel * vec2<f32>(-2.0, 2.0)).rgb;
+    let h = sample_input(uv + texel * vec2<f32>(0.0, 2.0)).rgb;
+    let i = sample_input(uv + texel * vec2<f32>(2.0, 2.0)).rgb;
+    let j = sample_input(uv + texel * vec2<f32>(-1.0, -1.0)).rgb;
+    let k = sample_input(uv + texel * vec2<f32>(1.0, -1.0)).rgb;
+    let l = sample_input(uv + texel * vec2<f32>(-1.0, 1.0)).rgb;
+    let m = sample_input(uv + texel * vec2<f32>(1.0, 1.0)).rgb;
+
+    if (params.z == 0.0) {
+        let color = (j + k + l + m) * 0.125
+            + (a + c + g + i) * 0.03125
+            + (b + d + f + h) * 0.0625
+            + e * 0.125;
+        return vec4<f32>(color, 1.0);
+    }
+
+    // The first pass thresholds the scene and weights the boxes against fireflies
+    let boxes = karis_average(j, k, l, m) * 0.5
+        + karis_average(a, b, d, e) * 0.125
+        + karis_average(b, c, e, f) * 0.125
+        + karis_average(d, e, g, h) * 0.125
+        + karis_average(e, f, h, i) * 0.125;
+    return vec4<f32>(prefilter(boxes.rgb / boxes.w), 1.0);
+}
@@ -0,0 +1,29 @@
+#include "fullscreen.wgsl";
+
+// Input: the coarser level of the chain. x: how much of it spreads into this level
+
+@group(2) @binding(0)
+var level_texture: texture_2d<f32>;
+@group(2) @binding(1)
+var level_sampler: sampler;
+
+// 3x3 tent filter over the coarser level
+fn sample_tent(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(input_texture));
+    var color = sample_input(uv).rgb * 4.0;
+    color = color + (sample_input(uv + texel * vec2<f32>(-1.0, 0.0)).rgb
+        + sample_input(uv + texel * vec2<f32>(1.0, 0.0)).rgb
+        + sample_input(uv + texel * vec2<f32>(0.0, -1.0)).rgb
+        + sample_input(uv + texel * vec2<f32>(0.0, 1.0)).rgb) * 2.0;
+    color = color + sample_input(uv + texel * vec2<f32>(-1.0, -1.0)).rgb
+        + sample_input(uv + texel * vec2<f32>(1.0, -1.0)).rgb
+        + sample_input(uv + texel * vec2<f32>(-1.0, 1.0)).rgb
+        + sample_input(uv + texel * vec2<f32>(1.0, 1.0)).rgb;
+    return color / 16.0;
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let level = textureSample(level_texture, level_sampler, in.uv).rgb;
+    return vec4<f32>(mix(level, sample_tent(in.uv), params.x), 1.0);
+}
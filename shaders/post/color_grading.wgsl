@@ -0,0 +1,20 @@
+#include "fullscreen.wgsl";
+
+// x: strength of the grading, y: size of the LUT
+
+@group(2) @binding(0)
+var lut_texture: texture_3d<f32>;
+@group(2) @binding(1)
+var lut_sampler: sampler;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = clamp(sample_input(in.uv).rgb, vec3<f32>(0.0), vec3<f32>(1.0));
+
+    // LUTs are authored on sRGB values, lookups go through the centers of the edge texels
+    let size = params.y;
+    let coordinates = linear_to_srgb(color) * ((size - 1.0) / size) + 0.5 / size;
+    let graded = srgb_to_linear(textureSample(lut_texture, lut_sampler, coordinates).rgb);
+
+    return vec4<f32>(mix(color, graded, params.x), 1.0);
+}
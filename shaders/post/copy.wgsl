@@ -0,0 +1,8 @@
+#include "fullscreen.wgsl";
+
+// Writes the result of the chain to the output target
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(sample_input(in.uv).rgb, 1.0);
+}
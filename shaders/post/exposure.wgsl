@@ -0,0 +1,9 @@
+#include "fullscreen.wgsl";
+
+// x: exposure in stops
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = sample_input(in.uv);
+    return vec4<f32>(color.rgb * exp2(params.x), color.a);
+}
@@ -0,0 +1,11 @@
+#include "fullscreen.wgsl";
+
+// x: display gamma
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = max(sample_input(in.uv).rgb, vec3<f32>(0.0));
+    let encoded = pow(color, vec3<f32>(1.0 / params.x));
+    // The output target applies the sRGB curve when written, undo it so the power curve is kept
+    return vec4<f32>(srgb_to_linear(encoded), 1.0);
+}
@@ -0,0 +1,26 @@
+#include "fullscreen.wgsl";
+
+// x: 0 for Reinhard, 1 for ACES
+
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (vec3<f32>(1.0) + color);
+}
+
+// Narkowicz's fit of the ACES filmic curve
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((color * (a * color + b)) / (color * (c * color + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = max(sample_input(in.uv).rgb, vec3<f32>(0.0));
+    if (params.x == 0.0) {
+        return vec4<f32>(reinhard(color), 1.0);
+    }
+    return vec4<f32>(aces(color), 1.0);
+}
@@ -0,0 +1,26 @@
+#include "consts.wgsl"; 
+
+// Depth only pass rendering the voxel faces into one cascade of the directional light
+
+struct InstancedVertexInput {
+    @location(0) v_position: vec4<f32>,
+}
+struct InstanceInput {
+    @location(4) i_position: vec4<f32>,
+    @location(5) i_size: vec4<f32>,
+}
+
+struct CascadeData {
+    view_projection: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> cascade: CascadeData;
+
+@vertex
+fn vs_main(
+    vertex_input: InstancedVertexInput,
+    instance_input: InstanceInput,
+) -> @builtin(position) vec4<f32> {
+    return cascade.view_projection * (IDENTITY_MATRIX * vertex_input.v_position * instance_input.i_size + instance_input.i_position);
+}
@@ -0,0 +1,55 @@
+#include "atmosphere.wgsl";
+
+// Sky behind the terrain: a triangle covering the screen on the far plane, drawn where no
+// terrain was
+
+// Angular radius of the sun disk in radians, larger than the real sun to be seen at all
+let SUN_DISK_RADIUS = 0.02;
+// Radiance of the sun disk relative to the illuminance of the sun
+let SUN_DISK_INTENSITY = 20.0;
+
+struct SkyOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) clip_position: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> atmosphere: AtmosphereData;
+@group(0) @binding(1)
+var transmittance_lut: texture_2d<f32>;
+@group(0) @binding(2)
+var sky_view_lut: texture_2d<f32>;
+@group(0) @binding(3)
+var lut_sampler: sampler;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> SkyOutput {
+    var out: SkyOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    out.position = vec4<f32>(out.clip_position, 1.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: SkyOutput) -> @location(0) vec4<f32> {
+    let far = atmosphere.inverse_view_projection * vec4<f32>(in.clip_position, 1.0, 1.0);
+    let direction = normalize(far.xyz / far.w);
+
+    let sky = textureSampleLevel(sky_view_lut, lut_sampler, sky_view_uv(direction), 0.0).rgb;
+
+    // The sun disk, dimmed by the air in front of it and hidden below the horizon
+    let sun_direction = atmosphere.sun_direction.xyz;
+    let sun_angle = acos(clamp(dot(direction, sun_direction), -1.0, 1.0));
+    let disk = 1.0 - smoothstep(SUN_DISK_RADIUS * 0.8, SUN_DISK_RADIUS, sun_angle);
+    let sun_uv = transmittance_uv(atmosphere.params.x, direction.y);
+    let sun_transmittance = textureSampleLevel(transmittance_lut, lut_sampler, sun_uv, 0.0).rgb;
+    let sun = sun_transmittance * disk * SUN_DISK_INTENSITY;
+
+    // Fogged like terrain on the far plane at the horizon, so that the terrain blends into it,
+    // and clearing above
+    let color = (sky + sun) * atmosphere.sun_color.rgb;
+    let fogged = apply_fog(atmosphere, color, direction * atmosphere.camera_position.w);
+    let clearing = select(smoothstep(0.0, 0.1, direction.y), 0.0, atmosphere.water_color.w != 0.0);
+    return vec4<f32>(mix(fogged, color, clearing), 1.0);
+}
@@ -0,0 +1,41 @@
+#include "atmosphere.wgsl";
+
+// Fraction of the sunlight reaching each height through the atmosphere, for each angle of the sun
+
+let TRANSMITTANCE_STEPS = 40;
+
+struct FullscreenOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> FullscreenOutput {
+    var out: FullscreenOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    // Inverse of transmittance_uv
+    let cos_zenith = in.uv.x * 2.0 - 1.0;
+    let height = in.uv.y * in.uv.y * (ATMOSPHERE_RADIUS - GROUND_RADIUS);
+
+    let origin = vec3<f32>(0.0, GROUND_RADIUS + height, 0.0);
+    let direction = vec3<f32>(sqrt(max(1.0 - cos_zenith * cos_zenith, 0.0)), cos_zenith, 0.0);
+    // The planet itself blocks the sun below the horizon
+    if (ray_sphere(origin, direction, GROUND_RADIUS) > 0.0) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+
+    let step_length = max(ray_sphere(origin, direction, ATMOSPHERE_RADIUS), 0.0) / f32(TRANSMITTANCE_STEPS);
+    var optical_depth = vec3<f32>(0.0);
+    for (var i = 0; i < TRANSMITTANCE_STEPS; i = i + 1) {
+        let position = origin + direction * (f32(i) + 0.5) * step_length;
+        optical_depth = optical_depth + atmosphere_medium(length(position) - GROUND_RADIUS).extinction * step_length;
+    }
+    return vec4<f32>(exp(-optical_depth), 1.0);
+}
@@ -0,0 +1,63 @@
+#include "atmosphere.wgsl";
+
+// Light scattered towards the camera from every direction of the sky, for a sun of unit
+// illuminance, recomputed whenever the sun or the camera altitude change
+
+let SKY_VIEW_STEPS = 32;
+
+struct FullscreenOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> atmosphere: AtmosphereData;
+@group(1) @binding(0)
+var transmittance_lut: texture_2d<f32>;
+@group(1) @binding(1)
+var lut_sampler: sampler;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> FullscreenOutput {
+    var out: FullscreenOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    // Inverse of sky_view_uv
+    let azimuth = (in.uv.x - 0.5) * 6.2831853;
+    let side = 1.0 - 2.0 * in.uv.y;
+    let elevation = sign(side) * side * side * 1.5707963;
+    let direction = vec3<f32>(cos(elevation) * cos(azimuth), sin(elevation), cos(elevation) * sin(azimuth));
+
+    let origin = vec3<f32>(0.0, GROUND_RADIUS + atmosphere.params.x, 0.0);
+    let ground = ray_sphere(origin, direction, GROUND_RADIUS);
+    let ray_length = select(max(ray_sphere(origin, direction, ATMOSPHERE_RADIUS), 0.0), ground, ground > 0.0);
+    let step_length = ray_length / f32(SKY_VIEW_STEPS);
+
+    let sun_direction = atmosphere.sun_direction.xyz;
+    let cos_theta = dot(direction, sun_direction);
+    let rayleigh = rayleigh_phase(cos_theta);
+    let mie = mie_phase(cos_theta);
+
+    var radiance = vec3<f32>(0.0);
+    var transmittance = vec3<f32>(1.0);
+    for (var i = 0; i < SKY_VIEW_STEPS; i = i + 1) {
+        let position = origin + direction * (f32(i) + 0.5) * step_length;
+        let radius = length(position);
+        let medium = atmosphere_medium(radius - GROUND_RADIUS);
+        let sun_uv = transmittance_uv(radius - GROUND_RADIUS, dot(position / radius, sun_direction));
+        let sunlight = textureSampleLevel(transmittance_lut, lut_sampler, sun_uv, 0.0).rgb;
+
+        let scattering = (medium.rayleigh_scattering * rayleigh + vec3<f32>(medium.mie_scattering * mie)) * sunlight;
+        // Scattering integrated over the step, as the light scattered early in it is dimmed further
+        let step_transmittance = exp(-medium.extinction * step_length);
+        radiance = radiance + transmittance * (scattering - scattering * step_transmittance) / max(medium.extinction, vec3<f32>(1e-7));
+        transmittance = transmittance * step_transmittance;
+    }
+    return vec4<f32>(radiance, 1.0);
+}
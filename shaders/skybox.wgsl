@@ -0,0 +1,37 @@
+// Cubemap behind the terrain: a triangle covering the screen on the far plane, drawn where no
+// terrain was
+
+struct SkyboxData {
+    // Clip space to world space directions from the camera
+    inverse_view_projection: mat4x4<f32>,
+    // x: intensity
+    params: vec4<f32>,
+};
+
+struct SkyboxOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) clip_position: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> skybox: SkyboxData;
+@group(1) @binding(0)
+var sky_texture: texture_cube<f32>;
+@group(1) @binding(1)
+var sky_sampler: sampler;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> SkyboxOutput {
+    var out: SkyboxOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    out.position = vec4<f32>(out.clip_position, 1.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: SkyboxOutput) -> @location(0) vec4<f32> {
+    let far = skybox.inverse_view_projection * vec4<f32>(in.clip_position, 1.0, 1.0);
+    let direction = normalize(far.xyz / far.w);
+    return vec4<f32>(textureSample(sky_texture, sky_sampler, direction).rgb * skybox.params.x, 1.0);
+}
@@ -0,0 +1,99 @@
+// Screen-space ambient occlusion from the depth prepass: samples of a hemisphere oriented
+// along the reconstructed normal and rotated per pixel by a 4x4 noise tile
+
+struct FullscreenOutput {
+    @builtin(position) position: vec4<f32>,
+};
+
+struct SsaoData {
+    projection: mat4x4<f32>,
+    inverse_projection: mat4x4<f32>,
+    kernel: array<vec4<f32>, 64>,
+    noise: array<vec4<f32>, 16>,
+    // x: radius, y: strength, z: sample count, w: bias
+    params: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var depth_texture: texture_2d<f32>;
+
+@group(1) @binding(0)
+var<uniform> ssao: SsaoData;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> FullscreenOutput {
+    var out: FullscreenOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    return out;
+}
+
+// Linear depth in front of the camera, 0 where no surface was drawn
+fn load_depth(pixel: vec2<i32>) -> f32 {
+    let size = vec2<i32>(textureDimensions(depth_texture));
+    return textureLoad(depth_texture, clamp(pixel, vec2<i32>(0), size - 1), 0).r;
+}
+
+// View space position of the surface seen through a pixel
+fn view_position(pixel: vec2<i32>) -> vec3<f32> {
+    let size = vec2<f32>(textureDimensions(depth_texture));
+    let uv = (vec2<f32>(pixel) + 0.5) / size;
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 1.0, 1.0);
+    let ray = ssao.inverse_projection * ndc;
+    let direction = ray.xyz / ray.w;
+    return direction * (load_depth(pixel) / -direction.z);
+}
+
+// Difference to the neighbour on the same surface, the one closest in depth
+fn closest_difference(center: vec3<f32>, a: vec3<f32>, b: vec3<f32>) -> vec3<f32> {
+    if (abs(a.z - center.z) < abs(b.z - center.z)) {
+        return center - a;
+    }
+    return b - center;
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.position.xy);
+    if (load_depth(pixel) <= 0.0) {
+        return vec4<f32>(1.0);
+    }
+
+    let position = view_position(pixel);
+    let dx = closest_difference(position, view_position(pixel - vec2<i32>(1, 0)), view_position(pixel + vec2<i32>(1, 0)));
+    let dy = closest_difference(position, view_position(pixel - vec2<i32>(0, 1)), view_position(pixel + vec2<i32>(0, 1)));
+    var normal = normalize(cross(dx, dy));
+    if (dot(normal, position) > 0.0) {
+        normal = -normal;
+    }
+
+    // Gram-Schmidt basis around the normal, rotated by the noise vector of the pixel
+    let random = ssao.noise[(pixel.x % 4) + (pixel.y % 4) * 4].xyz;
+    let tangent = normalize(random - normal * dot(random, normal));
+    let tbn = mat3x3<f32>(tangent, cross(normal, tangent), normal);
+
+    let radius = ssao.params.x;
+    let count = i32(ssao.params.z);
+    let size = vec2<f32>(textureDimensions(depth_texture));
+    var occlusion = 0.0;
+    for (var i: i32 = 0; i < count; i = i + 1) {
+        let sample_position = position + tbn * ssao.kernel[i].xyz * radius;
+
+        let clip = ssao.projection * vec4<f32>(sample_position, 1.0);
+        let ndc = clip.xy / clip.w;
+        let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0) {
+            continue;
+        }
+
+        let scene_depth = view_position(vec2<i32>(uv * size)).z;
+        // Surfaces much closer to the camera than the sample do not occlude it
+        let range = smoothstep(0.0, 1.0, radius / abs(position.z - scene_depth));
+        if (scene_depth >= sample_position.z + ssao.params.w) {
+            occlusion = occlusion + range;
+        }
+    }
+
+    let visibility = clamp(1.0 - occlusion / f32(count) * ssao.params.y, 0.0, 1.0);
+    return vec4<f32>(visibility, visibility, visibility, 1.0);
+}
@@ -0,0 +1,51 @@
+// 4x4 blur of the raw occlusion, matching the noise tile, weighted down across depth discontinuities
+
+struct FullscreenOutput {
+    @builtin(position) position: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var occlusion_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var depth_texture: texture_2d<f32>;
+
+// x: relative depth difference treated as an edge
+@group(1) @binding(0)
+var<uniform> params: vec4<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> FullscreenOutput {
+    var out: FullscreenOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    return out;
+}
+
+fn linear_depth(pixel: vec2<i32>) -> f32 {
+    return textureLoad(depth_texture, pixel, 0).r;
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let size = vec2<i32>(textureDimensions(occlusion_texture));
+    let pixel = vec2<i32>(in.position.xy);
+    let center = linear_depth(pixel);
+    if (center <= 0.0) {
+        return vec4<f32>(1.0);
+    }
+
+    var total = 0.0;
+    var weights = 0.0;
+    for (var x: i32 = -2; x < 2; x = x + 1) {
+        for (var y: i32 = -2; y < 2; y = y + 1) {
+            let neighbour = clamp(pixel + vec2<i32>(x, y), vec2<i32>(0), size - 1);
+            let difference = abs(linear_depth(neighbour) - center) / (center * params.x);
+            let weight = max(1.0 - difference, 0.0);
+            total = total + textureLoad(occlusion_texture, neighbour, 0).r * weight;
+            weights = weights + weight;
+        }
+    }
+
+    let visibility = total / max(weights, 0.0001);
+    return vec4<f32>(visibility, visibility, visibility, 1.0);
+}
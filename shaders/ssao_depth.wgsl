@@ -0,0 +1,37 @@
+#include "consts.wgsl"; 
+
+// Depth prepass of the camera for SSAO, writing the linear view space depth of the voxel faces
+
+struct InstancedVertexInput {
+    @location(0) v_position: vec4<f32>,
+}
+struct InstanceInput {
+    @location(4) i_position: vec4<f32>,
+    @location(5) i_size: vec4<f32>,
+}
+
+struct DepthOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) view_depth: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> view_projection: mat4x4<f32>;
+
+@vertex
+fn vs_main(
+    vertex_input: InstancedVertexInput,
+    instance_input: InstanceInput,
+) -> DepthOutput {
+    var out: DepthOutput;
+    let world_position = IDENTITY_MATRIX * vertex_input.v_position * instance_input.i_size + instance_input.i_position;
+    out.position = view_projection * world_position;
+    // w of a perspective projection is the distance in front of the camera, scaled by the w of the position
+    out.view_depth = out.position.w / world_position.w;
+    return out;
+}
+
+@fragment
+fn fs_main(in: DepthOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(in.view_depth, 0.0, 0.0, 0.0);
+}
@@ -1,11 +1,21 @@
 pub enum LayoutEntryType {
     UniformBuffer,
     StorageBuffer,
+    WritableStorageBuffer,
+    DepthTextureArray,
+    ComparisonSampler,
+    FloatTexture2D,
+    UnfilterableFloatTexture2D,
+    FloatTexture3D,
+    FloatTexture2DArray,
+    FloatTextureCube,
+    FilteringSampler,
 }
 
 pub enum EntryVisibility {
     Vertex,
     Fragment,
+    Compute,
     All,
 }
 
@@ -27,6 +37,7 @@ impl BindGroupLayoutBuilder {
         let visibility = match visibility {
             EntryVisibility::Vertex => wgpu::ShaderStages::VERTEX,
             EntryVisibility::Fragment => wgpu::ShaderStages::FRAGMENT,
+            EntryVisibility::Compute => wgpu::ShaderStages::COMPUTE,
             EntryVisibility::All => wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
         };
 
@@ -51,6 +62,88 @@ impl BindGroupLayoutBuilder {
                 },
                 count: None,
             },
+            LayoutEntryType::WritableStorageBuffer => wgpu::BindGroupLayoutEntry {
+                binding: self.binding_count,
+                visibility,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: wgpu::BufferSize::new(size),
+                },
+                count: None,
+            },
+            LayoutEntryType::DepthTextureArray => wgpu::BindGroupLayoutEntry {
+                binding: self.binding_count,
+                visibility,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            LayoutEntryType::ComparisonSampler => wgpu::BindGroupLayoutEntry {
+                binding: self.binding_count,
+                visibility,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                count: None,
+            },
+            LayoutEntryType::FloatTexture2D => wgpu::BindGroupLayoutEntry {
+                binding: self.binding_count,
+                visibility,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            LayoutEntryType::UnfilterableFloatTexture2D => wgpu::BindGroupLayoutEntry {
+                binding: self.binding_count,
+                visibility,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            LayoutEntryType::FloatTexture3D => wgpu::BindGroupLayoutEntry {
+                binding: self.binding_count,
+                visibility,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D3,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            LayoutEntryType::FloatTexture2DArray => wgpu::BindGroupLayoutEntry {
+                binding: self.binding_count,
+                visibility,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            LayoutEntryType::FloatTextureCube => wgpu::BindGroupLayoutEntry {
+                binding: self.binding_count,
+                visibility,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::Cube,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            LayoutEntryType::FilteringSampler => wgpu::BindGroupLayoutEntry {
+                binding: self.binding_count,
+                visibility,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
         };
 
         self.binding_count += 1;
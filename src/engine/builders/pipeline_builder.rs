@@ -8,6 +8,9 @@ pub struct PipelineBuilder<'a> {
     fragment_code : std::option::Option<wgpu::ShaderModule>,
     primitive_state_cull_mode : std::option::Option<wgpu::Face>,
     wireframe_mode : bool,
+    sample_count : u32,
+    depth_test : bool,
+    depth_compare : wgpu::CompareFunction,
     pipeline_layout : std::option::Option<wgpu::PipelineLayout>,
     vertex_buffer_layouts : Vec<wgpu::VertexBufferLayout<'a>>,
 }
@@ -22,6 +25,9 @@ impl<'a> PipelineBuilder<'a> {
             fragment_code : None,
             primitive_state_cull_mode : None,
             wireframe_mode : false,
+            sample_count : 1,
+            depth_test : true,
+            depth_compare : wgpu::CompareFunction::Less,
             pipeline_layout : None,
             vertex_buffer_layouts : Vec::new(),
         }
@@ -63,6 +69,23 @@ impl<'a> PipelineBuilder<'a> {
         self
     }
 
+    pub fn set_sample_count(mut self, sample_count : u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+
+    // Fullscreen passes render without a depth attachment
+    pub fn set_depth_test(mut self, depth_test : bool) -> Self {
+        self.depth_test = depth_test;
+        self
+    }
+
+    // Backgrounds drawn on the far plane pass the test with LessEqual where nothing was drawn
+    pub fn set_depth_compare(mut self, depth_compare : wgpu::CompareFunction) -> Self {
+        self.depth_compare = depth_compare;
+        self
+    }
+
     pub fn add_vertex_buffer_layout(mut self, layouts : wgpu::VertexBufferLayout<'a>) -> Self {
         self.vertex_buffer_layouts.push(layouts);
         self
@@ -76,11 +99,13 @@ impl<'a> PipelineBuilder<'a> {
             buffers,
         };
 
-        let fragment_state : wgpu::FragmentState = wgpu::FragmentState {
-            module: &self.fragment_code.unwrap(),
+        // Pipelines without a fragment shader only write depth
+        let targets = self.fragment_configs.map(|format| [Some(format.into())]);
+        let fragment_state = self.fragment_code.as_ref().map(|module| wgpu::FragmentState {
+            module,
             entry_point: "fs_main",
-            targets: &[Some(self.fragment_configs.unwrap().into())],
-        };
+            targets: targets.as_ref().expect("Fragment shader set without a target format"),
+        });
 
         let topology = if self.wireframe_mode {
             wgpu::PrimitiveTopology::LineList
@@ -92,7 +117,7 @@ impl<'a> PipelineBuilder<'a> {
             label: None,
             layout: self.pipeline_layout.as_ref(),
             vertex: vertex_state,
-            fragment: Some(fragment_state),
+            fragment: fragment_state,
             primitive: wgpu::PrimitiveState {
                 topology, 
                 strip_index_format: None,
@@ -102,15 +127,15 @@ impl<'a> PipelineBuilder<'a> {
                 unclipped_depth: false,
                 conservative: false,
             },        
-            depth_stencil: Some(wgpu::DepthStencilState {
+            depth_stencil: self.depth_test.then(|| wgpu::DepthStencilState {
                 format: wgpu::TextureFormat::Depth32Float,
                 depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less, 
+                depth_compare: self.depth_compare, 
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
             }), 
             multisample: wgpu::MultisampleState {
-                count: 1, 
+                count: self.sample_count, 
                 mask: !0, 
                 alpha_to_coverage_enabled: false,
             },
@@ -119,8 +144,10 @@ impl<'a> PipelineBuilder<'a> {
     }
 }
 
+// With multisampling `view` is the multisampled target and `resolve_target` the view presented
 pub fn create_render_pass<'a>(
     view : &'a wgpu::TextureView,
+    resolve_target : Option<&'a wgpu::TextureView>,
     depth_texture_view : &'a wgpu::TextureView,
     encoder : &'a mut wgpu::CommandEncoder,
     [r, g, b, a] : [f32; 4]
@@ -130,12 +157,13 @@ pub fn create_render_pass<'a>(
         color_attachments: &[
             Some(wgpu::RenderPassColorAttachment {
                 view,
-                resolve_target: None,
+                resolve_target,
                 ops: wgpu::Operations {
                     load: wgpu::LoadOp::Clear(
                         wgpu::Color { r: r as f64, g: g as f64, b: b as f64, a: a as f64 }
                     ),
-                    store: true,
+                    // The samples are not needed once resolved
+                    store: resolve_target.is_none(),
                 }
             })
         ],
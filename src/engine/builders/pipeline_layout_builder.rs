@@ -17,6 +17,14 @@ impl<'a> PipelineLayoutBuilder<'a> {
     }
 
     pub fn build(self, device : &wgpu::Device) -> wgpu::PipelineLayout {
+        // Fail with the counts rather than a validation error on adapters with fewer groups
+        let max_bind_groups = device.limits().max_bind_groups;
+        assert!(
+            self.bind_group_layouts.len() as u32 <= max_bind_groups,
+            "pipeline needs {} bind groups but the device allows only {}",
+            self.bind_group_layouts.len(), max_bind_groups,
+        );
+
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: None,
             bind_group_layouts: self.bind_group_layouts.as_slice(),
@@ -1,37 +1,122 @@
 
-use image::{RgbaImage, GenericImageView};
+use image::{DynamicImage, GenericImageView, Rgba32FImage};
+use image::imageops::FilterType;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TextureType {
     Texture2D,
     Texture3D,
     TextureDepth,
+    Texture2DArray,
+    // Six square layers in the order +X, -X, +Y, -Y, +Z, -Z
+    Cubemap,
 }
 
+// Directions through the centre of the cube faces, and the directions of increasing u and v on them
+const CUBE_FACES : [([f32; 3], [f32; 3], [f32; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, -1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+];
+
 pub struct TextureBuilder {
     texture_type : TextureType,
     texture_dimensions : wgpu::TextureDimension,
     texture_size : wgpu::Extent3d,
     texture_format : wgpu::TextureFormat,
     texture_usage : wgpu::TextureUsages,
-    pixel_data : RgbaImage,
-    image_layout : wgpu::ImageDataLayout,
+    sample_count : u32,
+    mip_level_count : u32,
+    // One image per layer, all of the texture size
+    pixel_data : Vec<DynamicImage>,
+    // Pixels of every mip level, when converted ahead of the build
+    encoded_levels : Vec<EncodedLevel>,
+}
+
+// Pixels of one mip level of one layer in the format of the texture
+struct EncodedLevel {
+    layer : u32,
+    mip_level : u32,
+    width : u32,
+    height : u32,
+    bytes_per_pixel : u32,
+    pixels : Vec<u8>,
+}
+
+fn load_image(tex_path : &str) -> DynamicImage {
+    let diffuse_bytes = std::fs::read(tex_path).expect("Failed to read texture file"); 
+    image::load_from_memory(diffuse_bytes.as_slice()).unwrap()
+}
+
+// Same as load_image for images picked at runtime, which may well be missing or broken
+fn try_load_image(tex_path : &str) -> Result<DynamicImage, String> {
+    image::open(tex_path).map_err(|e| format!("{}: {}", tex_path, e))
+}
+
+// Scales the images to the size of the first one
+fn resize_layers(layers : &mut [DynamicImage]) -> (u32, u32) {
+    let (width, height) = layers[0].dimensions();
+    for layer in layers.iter_mut() {
+        if layer.dimensions() != (width, height) {
+            *layer = layer.resize_exact(width, height, FilterType::Triangle);
+        }
+    }
+    (width, height)
+}
+
+// Nearest half float, tiny values flush to zero and huge ones clamp to the largest half
+fn f32_to_f16(value : f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent <= 0 {
+        sign
+    } else if exponent >= 31 {
+        sign | 0x7bff
+    } else {
+        let half = ((exponent as u32) << 10 | mantissa >> 13) + ((mantissa >> 12) & 1);
+        sign | half.min(0x7bff) as u16
+    }
+}
+
+// Bilinear sample of an equirectangular image in a direction, the top row looking up
+fn sample_equirectangular(image : &Rgba32FImage, [x, y, z] : [f32; 3]) -> image::Rgba<f32> {
+    let (width, height) = image.dimensions();
+    let u = (z.atan2(x) / std::f32::consts::TAU + 0.5) * width as f32 - 0.5;
+    let v = (y.clamp(-1.0, 1.0).acos() / std::f32::consts::PI) * height as f32 - 0.5;
+
+    let (x0, y0) = (u.floor(), v.floor());
+    let (fx, fy) = (u - x0, v - y0);
+    // Wraps around the horizon and stops at the poles
+    let texel = |x : f32, y : f32| image.get_pixel((x as i32).rem_euclid(width as i32) as u32, (y as i32).clamp(0, height as i32 - 1) as u32).0;
+    let [a, b, c, d] = [texel(x0, y0), texel(x0 + 1.0, y0), texel(x0, y0 + 1.0), texel(x0 + 1.0, y0 + 1.0)];
+    image::Rgba(std::array::from_fn(|i| {
+        let top = a[i] + (b[i] - a[i]) * fx;
+        let bottom = c[i] + (d[i] - c[i]) * fx;
+        top + (bottom - top) * fy
+    }))
+}
+
+// World direction through the centre of a texel of a cube face
+fn cube_texel_direction(face : usize, x : u32, y : u32, size : u32) -> [f32; 3] {
+    let (forward, right, down) = CUBE_FACES[face];
+    let u = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+    let v = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+    let direction : [f32; 3] = std::array::from_fn(|i| forward[i] + right[i] * u + down[i] * v);
+    let length = direction.iter().map(|c| c * c).sum::<f32>().sqrt();
+    direction.map(|c| c / length)
 }
 
 impl TextureBuilder {
     pub fn new(tex_path : &str, texture_type : TextureType) -> Self {
 
         if texture_type != TextureType::TextureDepth{
-            let diffuse_bytes = std::fs::read(tex_path).expect("Failed to read texture file"); 
-            let diffuse_image = image::load_from_memory(diffuse_bytes.as_slice()).unwrap();
-            let diffuse_rgba = diffuse_image.to_rgba8();
-            let dimensions = diffuse_image.dimensions();
-
-            let image_layout = wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: std::num::NonZeroU32::new(4 * dimensions.0),
-                rows_per_image: std::num::NonZeroU32::new(dimensions.1),
-            };
+            let diffuse_rgba = load_image(tex_path);
+            let dimensions = diffuse_rgba.dimensions();
     
             let texture_size = wgpu::Extent3d {
                 width: dimensions.0,
@@ -44,8 +129,10 @@ impl TextureBuilder {
                 texture_dimensions : wgpu::TextureDimension::D2,
                 texture_format : wgpu::TextureFormat::Rgba8UnormSrgb,
                 texture_usage : wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-                pixel_data : diffuse_rgba,
-                image_layout,
+                sample_count : 1,
+                mip_level_count : 1,
+                pixel_data : vec![diffuse_rgba],
+                encoded_levels : Vec::new(),
                 texture_size,
             }
         } else {
@@ -60,17 +147,86 @@ impl TextureBuilder {
                 texture_dimensions : wgpu::TextureDimension::D2,
                 texture_format : wgpu::TextureFormat::Rgba8UnormSrgb,
                 texture_usage : wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-                pixel_data : RgbaImage::new(1, 1),
-                image_layout : wgpu::ImageDataLayout {
-                    offset: 0,
-                    bytes_per_row: std::num::NonZeroU32::new(4),
-                    rows_per_image: std::num::NonZeroU32::new(1),
-                },
+                sample_count : 1,
+                mip_level_count : 1,
+                pixel_data : Vec::new(),
+                encoded_levels : Vec::new(),
                 texture_size : size,
             }
         }
     }
 
+    // Texture array with one layer per image, the images are scaled to the size of the first one
+    pub fn new_array(tex_paths : &[&str]) -> Self {
+        let mut layers : Vec<DynamicImage> = tex_paths.iter().map(|path| load_image(path)).collect();
+        let (width, height) = resize_layers(&mut layers);
+
+        Self {
+            texture_type : TextureType::Texture2DArray,
+            texture_dimensions : wgpu::TextureDimension::D2,
+            texture_format : wgpu::TextureFormat::Rgba8UnormSrgb,
+            texture_usage : wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            sample_count : 1,
+            mip_level_count : 1,
+            texture_size : wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: layers.len() as u32,
+            },
+            pixel_data : layers,
+            encoded_levels : Vec::new(),
+        }
+    }
+
+    // Cubemap from six square images in the order +X, -X, +Y, -Y, +Z, -Z, scaled to the size of the first one
+    pub fn new_cubemap(face_paths : &[&str; 6]) -> Result<Self, String> {
+        let mut faces = face_paths.iter().map(|path| try_load_image(path)).collect::<Result<Vec<DynamicImage>, String>>()?;
+        let (width, _) = faces[0].dimensions();
+        faces[0] = faces[0].resize_exact(width, width, FilterType::Triangle);
+        resize_layers(&mut faces);
+        Ok(Self::cubemap(faces, width, wgpu::TextureFormat::Rgba8UnormSrgb))
+    }
+
+    // Cubemap projected from an equirectangular image, usually a .hdr panorama, each face a
+    // quarter of its width across. HDR images keep their range in a half float texture.
+    pub fn new_cubemap_from_equirectangular(tex_path : &str) -> Result<Self, String> {
+        let panorama = try_load_image(tex_path)?;
+        let face_size = (panorama.width() / 4).max(1);
+        let format = match panorama {
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => wgpu::TextureFormat::Rgba16Float,
+            _ => wgpu::TextureFormat::Rgba8UnormSrgb,
+        };
+        let panorama = panorama.to_rgba32f();
+
+        let faces = (0..6)
+            .map(|face| {
+                let image = Rgba32FImage::from_fn(face_size, face_size, |x, y| {
+                    sample_equirectangular(&panorama, cube_texel_direction(face, x, y, face_size))
+                });
+                DynamicImage::ImageRgba32F(image)
+            })
+            .collect();
+        Ok(Self::cubemap(faces, face_size, format))
+    }
+
+    fn cubemap(faces : Vec<DynamicImage>, size : u32, texture_format : wgpu::TextureFormat) -> Self {
+        Self {
+            texture_type : TextureType::Cubemap,
+            texture_dimensions : wgpu::TextureDimension::D2,
+            texture_format,
+            texture_usage : wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            sample_count : 1,
+            mip_level_count : 1,
+            texture_size : wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            pixel_data : faces,
+            encoded_levels : Vec::new(),
+        }
+    }
+
     pub fn set_extent(mut self, width : u32, height : u32, depth : u32) -> Self {
         self.texture_size = wgpu::Extent3d {
             width,
@@ -102,16 +258,78 @@ impl TextureBuilder {
         self
     }
 
+    pub fn set_sample_count(mut self, sample_count : u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+
+    // Full mip chain down to 1x1, each level downscaled from the one above on the CPU. The layers
+    // of an array are scaled separately so they never bleed into each other.
+    pub fn generate_mipmaps(mut self) -> Self {
+        let size = self.texture_size.width.max(self.texture_size.height);
+        self.mip_level_count = 32 - size.leading_zeros();
+        self
+    }
+
+    // Scales and converts the pixels of every mip level now rather than in build, so that it can
+    // be done away from the render thread
+    pub fn encode(mut self) -> Self {
+        self.encoded_levels = self.encode_levels();
+        self
+    }
+
+    fn encode_levels(&self) -> Vec<EncodedLevel> {
+        let mut levels = Vec::new();
+        for (layer, image) in self.pixel_data.iter().enumerate() {
+            let mut level_image = image.clone();
+            for mip_level in 0..self.mip_level_count {
+                if mip_level > 0 {
+                    let width = (level_image.width() / 2).max(1);
+                    let height = (level_image.height() / 2).max(1);
+                    level_image = level_image.resize_exact(width, height, FilterType::Triangle);
+                }
+
+                let (width, height) = level_image.dimensions();
+                // Half float textures hold HDR images, every other format takes 8 bits per channel
+                let (pixels, bytes_per_pixel) = if self.texture_format == wgpu::TextureFormat::Rgba16Float {
+                    let halves : Vec<u16> = level_image.to_rgba32f().into_raw().into_iter().map(f32_to_f16).collect();
+                    (bytemuck::cast_slice(&halves).to_vec(), 8)
+                } else {
+                    (level_image.to_rgba8().into_raw(), 4)
+                };
+                levels.push(EncodedLevel { layer : layer as u32, mip_level, width, height, bytes_per_pixel, pixels });
+            }
+        }
+        levels
+    }
+
     pub fn build(
         &self,
         device : &wgpu::Device,
         queue : &wgpu::Queue,
     ) -> wgpu::TextureView {
+        let dimension = match self.texture_type {
+            TextureType::Texture2DArray => Some(wgpu::TextureViewDimension::D2Array),
+            TextureType::Cubemap => Some(wgpu::TextureViewDimension::Cube),
+            _ => None,
+        };
+        self.build_texture(device, queue).create_view(&wgpu::TextureViewDescriptor {
+            dimension,
+            ..Default::default()
+        })
+    }
+
+    // Same as build, for callers that need views of single layers
+    pub fn build_texture(
+        &self,
+        device : &wgpu::Device,
+        queue : &wgpu::Queue,
+    ) -> wgpu::Texture {
         let diffuse_texture = device.create_texture(
             &wgpu::TextureDescriptor {
                 size: self.texture_size,
-                mip_level_count: 1, 
-                sample_count: 1,
+                mip_level_count: self.mip_level_count, 
+                sample_count: self.sample_count,
                 dimension: self.texture_dimensions,
                 format: self.texture_format,
                 usage: self.texture_usage,
@@ -119,21 +337,126 @@ impl TextureBuilder {
             }
         );
 
-        if self.texture_type != TextureType::TextureDepth {
+        let encoded_levels;
+        let levels = if self.encoded_levels.is_empty() {
+            encoded_levels = self.encode_levels();
+            &encoded_levels
+        } else {
+            &self.encoded_levels
+        };
+        for level in levels {
             queue.write_texture(
                 wgpu::ImageCopyTexture {
                     texture: &diffuse_texture,
-                    mip_level: 0,
-                    origin: wgpu::Origin3d::ZERO,
+                    mip_level: level.mip_level,
+                    origin: wgpu::Origin3d { x: 0, y: 0, z: level.layer },
                     aspect: wgpu::TextureAspect::All,
                 },
-                &self.pixel_data,
-                self.image_layout,
-                self.texture_size,
+                &level.pixels,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(level.bytes_per_pixel * level.width),
+                    rows_per_image: std::num::NonZeroU32::new(level.height),
+                },
+                wgpu::Extent3d {
+                    width: level.width,
+                    height: level.height,
+                    depth_or_array_layers: 1,
+                },
             );
         }
 
-        diffuse_texture.create_view(&wgpu::TextureViewDescriptor::default())
+        diffuse_texture
     }
 }
 
+
+// Linear sampler for skyboxes, without seams at the edges of the faces
+pub fn create_cube_sampler(device : &wgpu::Device) -> wgpu::Sampler {
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("Cubemap Sampler"),
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cross(a : [f32; 3], b : [f32; 3]) -> [f32; 3] {
+        [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+    }
+
+    fn dot(a : [f32; 3], b : [f32; 3]) -> f32 {
+        (0..3).map(|i| a[i] * b[i]).sum()
+    }
+
+    fn distance(a : [f32; 3], b : [f32; 3]) -> f32 {
+        (0..3).map(|i| (a[i] - b[i]) * (a[i] - b[i])).sum::<f32>().sqrt()
+    }
+
+    #[test]
+    fn halves_round_to_the_nearest_bit_pattern() {
+        assert_eq!(f32_to_f16(0.0), 0x0000);
+        assert_eq!(f32_to_f16(-0.0), 0x8000);
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(0.5), 0x3800);
+        assert_eq!(f32_to_f16(-2.0), 0xc000);
+        assert_eq!(f32_to_f16(1.0 / 3.0), 0x3555);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+        // Rounding up carries into the exponent
+        assert_eq!(f32_to_f16(2047.9), 0x6800);
+        // Smallest normal half
+        assert_eq!(f32_to_f16(6.1035156e-5), 0x0400);
+    }
+
+    #[test]
+    fn halves_flush_tiny_values_and_clamp_huge_ones() {
+        assert_eq!(f32_to_f16(1e-8), 0x0000);
+        assert_eq!(f32_to_f16(-1e-8), 0x8000);
+        assert_eq!(f32_to_f16(1e6), 0x7bff);
+        assert_eq!(f32_to_f16(-1e6), 0xfbff);
+        assert_eq!(f32_to_f16(f32::INFINITY), 0x7bff);
+        assert_eq!(f32_to_f16(65520.0), 0x7bff);
+    }
+
+    #[test]
+    fn cube_faces_centre_on_their_axis() {
+        for (face, (forward, right, down)) in CUBE_FACES.iter().enumerate() {
+            assert_eq!(cube_texel_direction(face, 1, 1, 3), *forward);
+            // Every face is seen from inside the cube with the same handedness
+            assert_eq!(cross(*right, *down).map(|c| -c), *forward, "face {}", face);
+            // The first texel is at the top left of the face
+            let corner = cube_texel_direction(face, 0, 0, 3);
+            assert!(dot(corner, *right) < 0.0 && dot(corner, *down) < 0.0, "face {}", face);
+        }
+    }
+
+    #[test]
+    fn cube_faces_sample_the_panorama_in_their_direction() {
+        // Panorama whose texels hold the direction they look towards
+        let (width, height) = (256, 128);
+        let panorama = Rgba32FImage::from_fn(width, height, |x, y| {
+            let azimuth = ((x as f32 + 0.5) / width as f32 - 0.5) * std::f32::consts::TAU;
+            let polar = (y as f32 + 0.5) / height as f32 * std::f32::consts::PI;
+            image::Rgba([polar.sin() * azimuth.cos(), polar.cos(), polar.sin() * azimuth.sin(), 1.0])
+        });
+
+        let size = 16;
+        for face in 0..6 {
+            for y in 0..size {
+                for x in 0..size {
+                    let direction = cube_texel_direction(face, x, y, size);
+                    let [r, g, b, _] = sample_equirectangular(&panorama, direction).0;
+                    assert!(distance([r, g, b], direction) < 0.03, "face {} texel ({}, {})", face, x, y);
+                }
+            }
+        }
+    }
+}
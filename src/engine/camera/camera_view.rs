@@ -0,0 +1,48 @@
+use cgmath::{Matrix4, SquareMatrix, Vector3, Vector4};
+use crate::engine::{buffers::{traits::AsUniformBuffer, uniform_buffer::UniformBuffer}, utils::vector_extensions::ToPoint3};
+
+use super::OPENGL_TO_WGPU_MATRIX;
+
+pub const NEAR_PLANE : f32 = 0.1;
+pub const FAR_PLANE : f32 = 1000.0;
+
+// A single point of view to render from: any view and projection, not tied to a window
+pub struct CameraView {
+    pub position : Vector3<f32>,
+    pub forward : Vector3<f32>,
+    pub view : Matrix4<f32>,
+    pub projection : Matrix4<f32>,
+}
+
+impl CameraView {
+    pub fn look_to(position : Vector3<f32>, forward : Vector3<f32>, up : Vector3<f32>, fov_degrees : f32, aspect_ratio : f32) -> Self {
+        Self {
+            position,
+            forward,
+            view : Matrix4::look_at_rh(position.to_point3(), (position + forward).to_point3(), up),
+            projection : cgmath::perspective(cgmath::Deg(fov_degrees), aspect_ratio, NEAR_PLANE, FAR_PLANE),
+        }
+    }
+
+    pub fn get_view_projection_matrix(&self) -> Matrix4<f32> {
+        OPENGL_TO_WGPU_MATRIX * self.projection * self.view
+    }
+
+    // Clip space to world space directions from the camera, for backgrounds at an infinite distance
+    pub fn get_inverse_sky_matrix(&self) -> Matrix4<f32> {
+        let mut view = self.view;
+        view.w = Vector4::new(0.0, 0.0, 0.0, 1.0);
+        (OPENGL_TO_WGPU_MATRIX * self.projection * view).invert().unwrap_or_else(Matrix4::identity)
+    }
+}
+
+impl AsUniformBuffer for CameraView {
+    fn as_uniform_buffer(&self, device : &wgpu::Device) -> UniformBuffer {
+        let view_projection : [[f32; 4]; 4] = self.get_view_projection_matrix().into();
+        let mut camera_data = view_projection.to_vec();
+        camera_data.push([self.position.x, self.position.y, self.position.z, 0.0]);
+        
+        let buffer_size = std::mem::size_of::<[f32; 4]>() * camera_data.len();
+        UniformBuffer::new(device, &camera_data, buffer_size as u64)
+    }
+}
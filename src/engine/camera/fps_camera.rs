@@ -1,12 +1,13 @@
 
 use cgmath::{Vector3, InnerSpace};
 use winit::event::VirtualKeyCode;
-use crate::engine::{buffers::{traits::AsUniformBuffer, uniform_buffer::UniformBuffer}, renderer::EngineData, utils::vector_extensions::ToPoint3};
+use crate::engine::{buffers::{traits::AsUniformBuffer, uniform_buffer::UniformBuffer}, renderer::EngineData};
 
-use super::OPENGL_TO_WGPU_MATRIX;
+use super::camera_view::CameraView;
 
 pub const SENSITIVITY:f32 = 0.05;
 const SPEED : f32 = 50.0; 
+const FOV : f32 = 45.0;
 
 pub struct FpsCamera {
     pub position : Vector3<f32>,
@@ -37,6 +38,20 @@ impl FpsCamera {
         }
     }
 
+    // Camera that does not depend on a window, looking along the given yaw and pitch in degrees
+    pub fn with_pose(position : Vector3<f32>, yaw : f32, pitch : f32, aspect_ratio : f32) -> Self {
+        let mut camera = Self {
+            position,
+            forward : Vector3::new(0.0, 0.0, -1.0),
+            yaw,
+            pitch,
+            aspect_ratio,
+            momentum : Vector3::new(0.0, 0.0, 0.0),
+        };
+        camera.update_position(position);
+        camera
+    }
+
     pub fn update_aspect_ratio(&mut self, engine : &EngineData) {
         let window_size = engine.get_window_size();
         self.aspect_ratio = window_size.0 as f32 / window_size.1 as f32;
@@ -95,33 +110,14 @@ impl FpsCamera {
         }
     } 
 
-    fn get_view_projection_matrix(&self) -> Vec<[f32; 4]> {
-        let view_matrix = cgmath::Matrix4::look_at_rh(
-            self.position.to_point3(),
-            (self.position + self.forward).to_point3(),
-            Vector3::unit_y(),
-        );
-
-        let projection_matrix = cgmath::perspective(
-            cgmath::Deg(45.0), 
-            self.aspect_ratio, 
-            0.1,
-            1000.0
-        );
-
-        let mat = OPENGL_TO_WGPU_MATRIX * projection_matrix * view_matrix; 
-        let mx_ref : [[f32; 4]; 4] = mat.into();
-        mx_ref.to_vec()
+    pub fn get_camera_view(&self) -> CameraView {
+        CameraView::look_to(self.position, self.forward, Vector3::unit_y(), FOV, self.aspect_ratio)
     }
 
 }
 
 impl AsUniformBuffer for FpsCamera {
     fn as_uniform_buffer(&self, device : &wgpu::Device) -> UniformBuffer {
-        let mut camera_data = self.get_view_projection_matrix();
-        camera_data.push([self.position.x, self.position.y, self.position.z, 0.0]);
-        
-        let buffer_size = std::mem::size_of::<[f32; 4]>() * camera_data.len();
-        UniformBuffer::new(device, &camera_data, buffer_size as u64)
+        self.get_camera_view().as_uniform_buffer(device)
     }
-}
\ No newline at end of file
+}
@@ -1,5 +1,6 @@
 
 pub mod fps_camera; 
+pub mod camera_view;
 
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
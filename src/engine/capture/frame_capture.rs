@@ -0,0 +1,118 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use image::RgbaImage;
+
+use super::panorama::PanoramaFormat;
+
+const CAPTURE_DIR : &str = "captures";
+const DEFAULT_TIMESTEP : f32 = 1.0 / 60.0;
+const DEFAULT_PANORAMA_FACE_SIZE : u32 = 1024;
+
+struct Recording {
+    directory : PathBuf,
+    frame : u32,
+}
+
+// Screenshot and image-sequence state of the surface engine. Frames are read back at
+// the end of a frame, either before or after the imgui overlay is drawn.
+pub struct FrameCapture {
+    screenshot_requested : bool,
+    panorama_requested : bool,
+    recording : Option<Recording>,
+    pub include_ui : bool,
+    // Simulated time between two recorded frames, independent of how long they take to render
+    pub timestep : f32,
+    pub panorama_format : PanoramaFormat,
+    pub panorama_face_size : u32,
+}
+
+fn timestamp() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_millis())
+}
+
+// Encoding a full frame takes longer than rendering one, so screenshots are saved off the render thread
+fn save_in_background(image : RgbaImage, path : PathBuf) {
+    std::thread::spawn(move || {
+        if let Err(e) = image.save(&path) {
+            eprintln!("failed to save capture {:?}: {}", path, e);
+        }
+    });
+}
+
+impl FrameCapture {
+    pub fn new() -> Self {
+        Self {
+            screenshot_requested : false,
+            panorama_requested : false,
+            recording : None,
+            include_ui : false,
+            timestep : DEFAULT_TIMESTEP,
+            panorama_format : PanoramaFormat::Equirectangular,
+            panorama_face_size : DEFAULT_PANORAMA_FACE_SIZE,
+        }
+    }
+
+    pub fn request_screenshot(&mut self) {
+        self.screenshot_requested = true;
+    }
+
+    pub fn request_panorama(&mut self) {
+        self.panorama_requested = true;
+    }
+
+    // Output path of the pending panorama request, which the caller is expected to capture
+    pub fn take_panorama_request(&mut self) -> Option<PathBuf> {
+        if !std::mem::take(&mut self.panorama_requested) {
+            return None;
+        }
+        let name = match self.panorama_format {
+            PanoramaFormat::CubeFaces => format!("panorama-{}", timestamp()),
+            PanoramaFormat::Equirectangular => format!("panorama-{}.png", timestamp()),
+        };
+        Some(Path::new(CAPTURE_DIR).join(name))
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub fn recorded_frames(&self) -> u32 {
+        self.recording.as_ref().map_or(0, |recording| recording.frame)
+    }
+
+    pub fn toggle_recording(&mut self) {
+        if self.recording.take().is_none() {
+            let directory = Path::new(CAPTURE_DIR).join(format!("recording-{}", timestamp()));
+            match std::fs::create_dir_all(&directory) {
+                Ok(()) => self.recording = Some(Recording { directory, frame : 0 }),
+                Err(e) => eprintln!("failed to create {:?}: {}", directory, e),
+            }
+        }
+    }
+
+    // Whether the frame has to be read back at the given stage of the frame
+    pub fn wants_frame(&self, with_ui : bool) -> bool {
+        (self.screenshot_requested || self.recording.is_some()) && self.include_ui == with_ui
+    }
+
+    pub fn store_frame(&mut self, image : RgbaImage) {
+        if self.screenshot_requested {
+            self.screenshot_requested = false;
+            match std::fs::create_dir_all(CAPTURE_DIR) {
+                Ok(()) => save_in_background(image.clone(), Path::new(CAPTURE_DIR).join(format!("screenshot-{}.png", timestamp()))),
+                Err(e) => eprintln!("failed to create {}: {}", CAPTURE_DIR, e),
+            }
+        }
+
+        // Recorded frames are written synchronously: the simulation advances by a fixed
+        // timestep, so stalling only slows the recording down instead of piling up frames
+        if let Some(recording) = self.recording.as_mut() {
+            let path = recording.directory.join(format!("frame-{:05}.png", recording.frame));
+            recording.frame += 1;
+            if let Err(e) = image.save(&path) {
+                eprintln!("failed to save capture {:?}: {}", path, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use image::RgbaImage;
+
+pub mod frame_capture;
+pub mod panorama;
+
+// Target the final frame is also drawn into while capturing. Swapchain textures are not
+// guaranteed to allow copies out of them, and wgpu cannot tell which ones do.
+pub fn create_capture_texture(device : &wgpu::Device, format : wgpu::TextureFormat, width : u32, height : u32) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Capture Target"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+    })
+}
+
+// Copies a 4 bytes per pixel colour texture back to the CPU as an RGBA image
+pub fn read_texture(
+    device : &wgpu::Device,
+    queue : &wgpu::Queue,
+    texture : &wgpu::Texture,
+    format : wgpu::TextureFormat,
+    width : u32,
+    height : u32,
+) -> RgbaImage {
+    let unpadded_bytes_per_row = 4 * width;
+    let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(alignment) * alignment;
+
+    let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback Buffer"),
+        size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &staging_buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row),
+                rows_per_image: std::num::NonZeroU32::new(height),
+            },
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+
+    queue.submit(Some(encoder.finish()));
+
+    let buffer_slice = staging_buffer.slice(..);
+    let (sender, receiver) = futures_intrusive::channel::shared::oneshot_channel();
+    buffer_slice.map_async(wgpu::MapMode::Read, move |v| sender.send(v).unwrap());
+
+    device.poll(wgpu::Maintain::Wait);
+
+    let mut pixels = Vec::<u8>::with_capacity((unpadded_bytes_per_row * height) as usize);
+    if let Some(Ok(())) = pollster::block_on(receiver.receive()) {
+        let data = buffer_slice.get_mapped_range();
+        for row in data.chunks(padded_bytes_per_row as usize) {
+            pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+    } else {
+        panic!("failed to read back texture from gpu!")
+    }
+    staging_buffer.unmap();
+
+    let is_bgra = matches!(format, wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb);
+    if is_bgra {
+        pixels.chunks_mut(4).for_each(|pixel| pixel.swap(0, 2));
+    }
+
+    RgbaImage::from_raw(width, height, pixels).expect("Readback size does not match the image size")
+}
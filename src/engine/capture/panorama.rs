@@ -0,0 +1,172 @@
+use std::path::Path;
+
+use cgmath::Vector3;
+use image::{Rgba, RgbaImage};
+
+use crate::engine::builders::texture_builder;
+use crate::engine::camera::camera_view::CameraView;
+use crate::engine::light::DirectionalLight;
+use crate::engine::post::{self, PostProcessor};
+use crate::engine::voxel_engine::VoxelEngine;
+
+use super::read_texture;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanoramaFormat {
+    CubeFaces,
+    Equirectangular,
+}
+
+// Layer order and names of the cube faces, as expected when loading them back as a cubemap
+pub const CUBE_FACE_NAMES : [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+
+// Forward and up vectors of the camera for each face
+const CUBE_FACE_CAMERAS : [([f32; 3], [f32; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ([0.0, 1.0, 0.0], [0.0, 0.0, -1.0]),
+    ([0.0, -1.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+    ([0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+];
+
+// Horizontal resolution of the equirectangular image relative to a cube face
+const EQUIRECTANGULAR_WIDTH_PER_FACE : u32 = 4;
+
+// Renders the six 90 degree views around `position` and reads them back in cubemap layer order.
+// Cubemaps are sampled from the inside, so every face is mirrored relative to what a right
+// handed camera sees. The faces go through a single 2D target since the GL backend cannot copy
+// out of six layer textures.
+pub fn capture_cubemap(
+    device : &wgpu::Device,
+    queue : &wgpu::Queue,
+    voxel_engine : &mut VoxelEngine,
+    post : &mut PostProcessor,
+    light : &DirectionalLight,
+    position : Vector3<f32>,
+    face_size : u32,
+) -> Vec<RgbaImage> {
+    let format = post.get_output_format();
+    let face_texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Panorama Face"),
+        size: wgpu::Extent3d {
+            width: face_size,
+            height: face_size,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+    });
+    let face_view = face_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    // The scene goes through the post-processing chain into the face. With MSAA enabled the
+    // engine renders into a multisampled target resolved into the HDR one first.
+    let sample_count = voxel_engine.get_sample_count();
+    let scene_view = post::create_hdr_texture(device, face_size, face_size, 1);
+    let multisampled_view = (sample_count > 1).then(|| post::create_hdr_texture(device, face_size, face_size, sample_count));
+
+    let depth_texture = texture_builder::TextureBuilder::new("", texture_builder::TextureType::TextureDepth)
+        .set_dimensions(2)
+        .set_extent(face_size, face_size, 1)
+        .set_sample_count(sample_count)
+        .set_format(wgpu::TextureFormat::Depth32Float)
+        .set_usage(wgpu::TextureUsages::RENDER_ATTACHMENT)
+        .build(device, queue);
+
+    CUBE_FACE_CAMERAS.iter()
+        .map(|(forward, up)| {
+            let camera = CameraView::look_to(position, (*forward).into(), (*up).into(), 90.0, 1.0);
+            voxel_engine.update(device, queue, &camera, light, (face_size, face_size));
+
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Panorama Encoder"),
+            });
+            match &multisampled_view {
+                Some(multisampled_view) => voxel_engine.render(multisampled_view, Some(&scene_view), &depth_texture, &mut encoder, &camera),
+                None => voxel_engine.render(&scene_view, None, &depth_texture, &mut encoder, &camera),
+            }
+            post.render(device, queue, &mut encoder, &scene_view, &[&face_view], (face_size, face_size));
+            queue.submit(Some(encoder.finish()));
+
+            let face = read_texture(device, queue, &face_texture, format, face_size, face_size);
+            image::imageops::flip_horizontal(&face)
+        })
+        .collect()
+}
+
+// Face and coordinates in [0, 1] of the texel of a cubemap seen along a direction
+fn cube_face_coordinates(direction : Vector3<f32>) -> (usize, f32, f32) {
+    let Vector3 { x, y, z } = direction;
+    let (face, sc, tc, major) = if x.abs() >= y.abs() && x.abs() >= z.abs() {
+        if x > 0.0 { (0, -z, -y, x) } else { (1, z, -y, -x) }
+    } else if y.abs() >= z.abs() {
+        if y > 0.0 { (2, x, z, y) } else { (3, x, -z, -y) }
+    } else if z > 0.0 {
+        (4, x, -y, z)
+    } else {
+        (5, -x, -y, -z)
+    };
+    (face, (sc / major + 1.0) * 0.5, (tc / major + 1.0) * 0.5)
+}
+
+// Bilinear lookup clamped to the edges of the face
+fn sample_bilinear(image : &RgbaImage, u : f32, v : f32) -> Rgba<u8> {
+    let max_x = image.width() as f32 - 1.0;
+    let max_y = image.height() as f32 - 1.0;
+    let x = (u * image.width() as f32 - 0.5).clamp(0.0, max_x);
+    let y = (v * image.height() as f32 - 0.5).clamp(0.0, max_y);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (x1, y1) = ((x0 + 1.0).min(max_x), (y0 + 1.0).min(max_y));
+    let (fx, fy) = (x - x0, y - y0);
+
+    let texel = |tx : f32, ty : f32| image.get_pixel(tx as u32, ty as u32).0;
+    let (a, b, c, d) = (texel(x0, y0), texel(x1, y0), texel(x0, y1), texel(x1, y1));
+
+    let mut result = [0u8; 4];
+    for i in 0..4 {
+        let top = a[i] as f32 * (1.0 - fx) + b[i] as f32 * fx;
+        let bottom = c[i] as f32 * (1.0 - fx) + d[i] as f32 * fx;
+        result[i] = (top * (1.0 - fy) + bottom * fy).round() as u8;
+    }
+    Rgba(result)
+}
+
+// Reprojects the cube faces onto a 2:1 longitude/latitude image centered on -z
+pub fn equirectangular_from_cubemap(faces : &[RgbaImage], width : u32) -> RgbaImage {
+    let height = width / 2;
+    RgbaImage::from_fn(width, height, |x, y| {
+        let longitude = ((x as f32 + 0.5) / width as f32 - 0.5) * std::f32::consts::TAU;
+        let latitude = (0.5 - (y as f32 + 0.5) / height as f32) * std::f32::consts::PI;
+        let direction = Vector3::new(
+            latitude.cos() * longitude.sin(),
+            latitude.sin(),
+            -latitude.cos() * longitude.cos(),
+        );
+
+        let (face, u, v) = cube_face_coordinates(direction);
+        sample_bilinear(&faces[face], u, v)
+    })
+}
+
+// Writes captured cube faces to `output`: a directory of six PNGs named after the faces,
+// or a single equirectangular PNG
+pub fn save_panorama(faces : &[RgbaImage], format : PanoramaFormat, output : &Path) -> image::ImageResult<()> {
+    match format {
+        PanoramaFormat::CubeFaces => {
+            std::fs::create_dir_all(output)?;
+            for (face, name) in faces.iter().zip(CUBE_FACE_NAMES.iter()) {
+                face.save(output.join(format!("{}.png", name)))?;
+            }
+            Ok(())
+        }
+        PanoramaFormat::Equirectangular => {
+            if let Some(parent) = output.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            equirectangular_from_cubemap(faces, faces[0].width() * EQUIRECTANGULAR_WIDTH_PER_FACE).save(output)
+        }
+    }
+}
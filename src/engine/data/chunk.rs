@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+
+use super::{Aabb, QuadtreeNode};
+
+pub const CHUNK_SIZE : i32 = 32;
+const CHUNK_CELLS : usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+fn row_index(y : i32, z : i32) -> usize {
+    (z * CHUNK_SIZE + y) as usize
+}
+
+fn cell_index(x : i32, y : i32, z : i32) -> usize {
+    row_index(y, z) * CHUNK_SIZE as usize + x as usize
+}
+
+// Mask with the bits [from, to) set
+fn span_mask(from : i32, to : i32) -> u32 {
+    let width = (to - from) as u32;
+    if width >= 32 { u32::MAX } else { ((1u32 << width) - 1) << from }
+}
+
+// Dense 32^3 block of the world: one u32 per (y, z) row holds the occupancy of the
+// 32 cells along x, and materials are stored as indices into a per-chunk palette.
+pub struct VoxelChunk<T> {
+    rows : Vec<u32>,
+    palette : Vec<T>,
+    materials : Vec<u16>,
+}
+
+impl<T : PartialEq + Clone> VoxelChunk<T> {
+    pub fn new() -> Self {
+        Self {
+            rows : vec![0; (CHUNK_SIZE * CHUNK_SIZE) as usize],
+            palette : Vec::new(),
+            materials : vec![0; CHUNK_CELLS],
+        }
+    }
+
+    fn palette_index(&mut self, data : &T) -> u16 {
+        match self.palette.iter().position(|entry| entry == data) {
+            Some(index) => index as u16,
+            None => {
+                // Entries of overwritten and removed cells pile up, a chunk only ever holds
+                // CHUNK_CELLS distinct payloads at once
+                if self.palette.len() > u16::MAX as usize {
+                    self.compact_palette();
+                }
+                self.palette.push(data.clone());
+                (self.palette.len() - 1) as u16
+            }
+        }
+    }
+
+    // Drops the palette entries no solid cell refers to
+    fn compact_palette(&mut self) {
+        let mut remap = vec![u16::MAX; self.palette.len()];
+        let mut palette = Vec::new();
+        for index in 0..CHUNK_CELLS {
+            let (x, row) = ((index % CHUNK_SIZE as usize) as i32, index / CHUNK_SIZE as usize);
+            if self.rows[row] >> x & 1 == 0 {
+                continue;
+            }
+            let material = self.materials[index] as usize;
+            if remap[material] == u16::MAX {
+                remap[material] = palette.len() as u16;
+                palette.push(self.palette[material].clone());
+            }
+            self.materials[index] = remap[material];
+        }
+        self.palette = palette;
+    }
+
+    // Fills the x range [from, to) of a row with the same payload
+    fn fill_row(&mut self, from : i32, to : i32, y : i32, z : i32, data : &T) {
+        let material = self.palette_index(data);
+        self.rows[row_index(y, z)] |= span_mask(from, to);
+        let row_start = cell_index(0, y, z);
+        self.materials[row_start + from as usize..row_start + to as usize].fill(material);
+    }
+
+    fn clear(&mut self, x : i32, y : i32, z : i32) {
+        self.rows[row_index(y, z)] &= !(1 << x);
+    }
+
+    pub fn is_solid(&self, x : i32, y : i32, z : i32) -> bool {
+        self.rows[row_index(y, z)] >> x & 1 == 1
+    }
+
+    #[cfg(test)]
+    pub fn get(&self, x : i32, y : i32, z : i32) -> Option<&T> {
+        if self.is_solid(x, y, z) {
+            Some(&self.palette[self.materials[cell_index(x, y, z)] as usize])
+        } else {
+            None
+        }
+    }
+
+    pub fn row(&self, y : i32, z : i32) -> u32 {
+        self.rows[row_index(y, z)]
+    }
+}
+
+// Sparse grid of dense chunks keyed by chunk coordinate, in the same cell space as the octree
+pub struct ChunkMap<T> {
+    chunks : HashMap<[i32; 3], VoxelChunk<T>>,
+}
+
+fn split_coordinate(cell : i32) -> (i32, i32) {
+    (cell.div_euclid(CHUNK_SIZE), cell.rem_euclid(CHUNK_SIZE))
+}
+
+impl<T : PartialEq + Clone> ChunkMap<T> {
+    pub fn new() -> Self {
+        Self {
+            chunks : HashMap::new(),
+        }
+    }
+
+    pub fn from_octree(octree : &QuadtreeNode<T>) -> Self {
+        let mut map = Self::new();
+        octree.visit(|bounds, data| map.fill_bounds(bounds, data));
+        map
+    }
+
+    // Only used to check the conversion, in the tests and the octree bench
+    #[cfg(test)]
+    pub fn to_octree(&self, cells_per_side : u32) -> QuadtreeNode<T> {
+        let mut octree = QuadtreeNode::new(cells_per_side);
+        for (key, chunk) in self.chunks.iter() {
+            for z in 0..CHUNK_SIZE {
+                for y in 0..CHUNK_SIZE {
+                    let mut row = chunk.row(y, z);
+                    while row != 0 {
+                        let x = row.trailing_zeros() as i32;
+                        row &= row - 1;
+
+                        let data = chunk.get(x, y, z).unwrap().clone();
+                        let [cx, cy, cz] = [
+                            key[0] * CHUNK_SIZE + x,
+                            key[1] * CHUNK_SIZE + y,
+                            key[2] * CHUNK_SIZE + z,
+                        ];
+                        if cx >= 0 && cy >= 0 && cz >= 0 {
+                            octree.insert_cell(cx as u32, cy as u32, cz as u32, data);
+                        }
+                    }
+                }
+            }
+        }
+        octree
+    }
+
+    // True when any cell of the box side facing along `normal` has an empty neighbour
+    pub fn is_face_exposed(&self, bounds : &Aabb, normal : [i32; 3]) -> bool {
+        let min = [bounds.min.x as i32, bounds.min.y as i32, bounds.min.z as i32];
+        let max = [bounds.max.x as i32, bounds.max.y as i32, bounds.max.z as i32];
+
+        // The layer of cells just outside the box on that side
+        let mut from = min;
+        let mut to = max;
+        for axis in 0..3 {
+            if normal[axis] > 0 {
+                from[axis] = max[axis];
+                to[axis] = max[axis] + 1;
+            } else if normal[axis] < 0 {
+                from[axis] = min[axis] - 1;
+                to[axis] = min[axis];
+            }
+        }
+
+        for z in from[2]..to[2] {
+            for y in from[1]..to[1] {
+                if !self.is_row_span_solid(from[0], to[0], y, z) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    pub fn insert_cell(&mut self, x : i32, y : i32, z : i32, data : &T) {
+        let ((kx, lx), (ky, ly), (kz, lz)) = (split_coordinate(x), split_coordinate(y), split_coordinate(z));
+        self.chunks.entry([kx, ky, kz]).or_insert_with(VoxelChunk::new).fill_row(lx, lx + 1, ly, lz, data);
+    }
+
+    // Emptied chunks are kept, their palette entries are not reclaimed
+    pub fn remove_cell(&mut self, x : i32, y : i32, z : i32) {
+        let ((kx, lx), (ky, ly), (kz, lz)) = (split_coordinate(x), split_coordinate(y), split_coordinate(z));
+        if let Some(chunk) = self.chunks.get_mut(&[kx, ky, kz]) {
+            chunk.clear(lx, ly, lz);
+        }
+    }
+
+    pub fn is_cell_solid(&self, x : i32, y : i32, z : i32) -> bool {
+        let ((kx, lx), (ky, ly), (kz, lz)) = (split_coordinate(x), split_coordinate(y), split_coordinate(z));
+        self.chunks.get(&[kx, ky, kz]).is_some_and(|chunk| chunk.is_solid(lx, ly, lz))
+    }
+
+    // Light reaching a corner of the box side facing along `normal`, from 0 when the corner is
+    // enclosed to 3 when open. `corner` holds the sign of the corner along each axis. Only the two
+    // side cells and the diagonal cell next to the corner in the layer outside the side count.
+    pub fn corner_light(&self, bounds : &Aabb, normal : [i32; 3], corner : [i32; 3]) -> u8 {
+        let min = [bounds.min.x as i32, bounds.min.y as i32, bounds.min.z as i32];
+        let max = [bounds.max.x as i32, bounds.max.y as i32, bounds.max.z as i32];
+
+        // Cell of the outside layer touching the corner, and the steps away from the box along the side
+        let mut cell = [0; 3];
+        let mut steps = Vec::with_capacity(2);
+        for axis in 0..3 {
+            if normal[axis] != 0 {
+                cell[axis] = if normal[axis] > 0 { max[axis] } else { min[axis] - 1 };
+            } else {
+                cell[axis] = if corner[axis] > 0 { max[axis] - 1 } else { min[axis] };
+                let mut step = [0; 3];
+                step[axis] = corner[axis].signum();
+                steps.push(step);
+            }
+        }
+
+        let solid = |offset : [i32; 3]| self.is_cell_solid(cell[0] + offset[0], cell[1] + offset[1], cell[2] + offset[2]);
+        let side_a = solid(steps[0]);
+        let side_b = solid(steps[1]);
+        if side_a && side_b {
+            return 0;
+        }
+        let diagonal = solid([steps[0][0] + steps[1][0], steps[0][1] + steps[1][1], steps[0][2] + steps[1][2]]);
+        3 - side_a as u8 - side_b as u8 - diagonal as u8
+    }
+
+    // Tests the x range [from, to) of a world row one chunk-sized word at a time
+    fn is_row_span_solid(&self, from : i32, to : i32, y : i32, z : i32) -> bool {
+        let ((ky, ly), (kz, lz)) = (split_coordinate(y), split_coordinate(z));
+        let mut x = from;
+        while x < to {
+            let (kx, lx) = split_coordinate(x);
+            let end = (to - kx * CHUNK_SIZE).min(CHUNK_SIZE);
+            let mask = span_mask(lx, end);
+            let row = self.chunks.get(&[kx, ky, kz]).map_or(0, |chunk| chunk.row(ly, lz));
+            if row & mask != mask {
+                return false;
+            }
+            x = kx * CHUNK_SIZE + end;
+        }
+        true
+    }
+
+    fn fill_bounds(&mut self, bounds : &Aabb, data : &T) {
+        let min = [bounds.min.x as i32, bounds.min.y as i32, bounds.min.z as i32];
+        let max = [bounds.max.x as i32, bounds.max.y as i32, bounds.max.z as i32];
+
+        for z in min[2]..max[2] {
+            for y in min[1]..max[1] {
+                let ((ky, ly), (kz, lz)) = (split_coordinate(y), split_coordinate(z));
+                let mut x = min[0];
+                while x < max[0] {
+                    let (kx, lx) = split_coordinate(x);
+                    let end = (max[0] - kx * CHUNK_SIZE).min(CHUNK_SIZE);
+                    self.chunks.entry([kx, ky, kz]).or_insert_with(VoxelChunk::new).fill_row(lx, end, ly, lz, data);
+                    x = kx * CHUNK_SIZE + end;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+
+    use super::*;
+
+    fn leaves(octree : &QuadtreeNode<u32>) -> Vec<([f32; 3], [f32; 3], u32)> {
+        let mut leaves = Vec::new();
+        octree.visit(|bounds, data| leaves.push((bounds.min.into(), bounds.max.into(), *data)));
+        leaves
+    }
+
+    fn cell_box(min : [i32; 3], max : [i32; 3]) -> Aabb {
+        Aabb {
+            min : Vector3::new(min[0] as f32, min[1] as f32, min[2] as f32),
+            max : Vector3::new(max[0] as f32, max[1] as f32, max[2] as f32),
+        }
+    }
+
+    #[test]
+    fn octree_round_trips_through_chunks() {
+        let mut octree = QuadtreeNode::new(64);
+        // A collapsed 16^3 block straddling no chunk, a slab across four chunks and loose cells
+        for z in 0..16 {
+            for y in 0..16 {
+                for x in 0..16 {
+                    octree.insert_cell(x, y, z, 1);
+                }
+            }
+        }
+        for z in 20..44 {
+            for x in 20..44 {
+                octree.insert_cell(x, 40, z, 2);
+            }
+        }
+        octree.insert_cell(31, 31, 31, 3);
+        octree.insert_cell(32, 31, 31, 4);
+        octree.insert_cell(63, 63, 63, 5);
+
+        let chunks = ChunkMap::from_octree(&octree);
+        assert!(chunks.is_cell_solid(15, 15, 15));
+        assert!(!chunks.is_cell_solid(16, 15, 15));
+        assert_eq!(leaves(&chunks.to_octree(64)), leaves(&octree));
+    }
+
+    #[test]
+    fn faces_are_exposed_only_towards_empty_cells() {
+        let mut chunks = ChunkMap::new();
+        // A 34 cell wide floor crossing the chunk boundary at x = 32, with one gap on top
+        for z in 0..4 {
+            for x in 0..34 {
+                chunks.insert_cell(x, 0, z, &1);
+                chunks.insert_cell(x, 1, z, &1);
+            }
+        }
+        chunks.remove_cell(33, 1, 2);
+
+        let bottom = cell_box([0, 0, 0], [34, 1, 4]);
+        assert!(chunks.is_face_exposed(&bottom, [0, -1, 0]));
+        // Covered by the top layer except for the removed cell past the chunk boundary
+        assert!(chunks.is_face_exposed(&bottom, [0, 1, 0]));
+        assert!(!chunks.is_face_exposed(&cell_box([0, 0, 0], [33, 1, 4]), [0, 1, 0]));
+        assert!(!chunks.is_face_exposed(&cell_box([0, 0, 0], [34, 1, 2]), [0, 1, 0]));
+        // Sides inside the floor are hidden, the outer ones are not
+        assert!(!chunks.is_face_exposed(&cell_box([10, 0, 1], [12, 2, 3]), [1, 0, 0]));
+        assert!(!chunks.is_face_exposed(&cell_box([10, 0, 1], [12, 2, 3]), [0, 0, -1]));
+        assert!(chunks.is_face_exposed(&cell_box([0, 0, 0], [1, 1, 1]), [-1, 0, 0]));
+        assert!(chunks.is_face_exposed(&cell_box([0, 0, 3], [1, 1, 4]), [0, 0, 1]));
+    }
+
+    #[test]
+    fn chunks_hold_more_payloads_than_a_byte() {
+        let mut chunks = ChunkMap::new();
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                chunks.insert_cell(x, 0, z, &((z * CHUNK_SIZE + x) as u32));
+            }
+        }
+        let chunk = &chunks.chunks[&[0, 0, 0]];
+        assert_eq!(chunk.get(5, 0, 20), Some(&(20 * CHUNK_SIZE as u32 + 5)));
+        assert_eq!(chunk.get(31, 0, 31), Some(&1023));
+    }
+
+    #[test]
+    fn palette_drops_payloads_of_overwritten_cells() {
+        let mut chunk = VoxelChunk::new();
+        chunk.fill_row(0, 1, 0, 0, &7);
+        for data in 0..3 {
+            chunk.fill_row(1, 2, 0, 0, &data);
+        }
+        chunk.fill_row(2, 3, 0, 0, &5);
+        chunk.clear(2, 0, 0);
+
+        chunk.compact_palette();
+        assert_eq!(chunk.palette, vec![7, 2]);
+        assert_eq!(chunk.get(0, 0, 0), Some(&7));
+        assert_eq!(chunk.get(1, 0, 0), Some(&2));
+    }
+
+    #[test]
+    fn full_palettes_are_compacted_before_growing() {
+        let mut chunk = VoxelChunk::new();
+        chunk.fill_row(0, 1, 0, 0, &7);
+        // Entries left behind by cells overwritten since, filling the palette
+        chunk.palette.extend(100..100 + u16::MAX as u32);
+
+        chunk.fill_row(1, 2, 0, 0, &1);
+        assert_eq!(chunk.palette, vec![7, 1]);
+        assert_eq!(chunk.get(0, 0, 0), Some(&7));
+        assert_eq!(chunk.get(1, 0, 0), Some(&1));
+    }
+}
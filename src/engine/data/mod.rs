@@ -1,12 +1,12 @@
 
-use cgmath::{Vector3, Zero};
+use cgmath::Vector3;
 
-use super::models::instance::instance_data::InstanceData;
+pub mod chunk;
 
-#[derive(Debug)]
-struct Aabb {
-    min : Vector3<f32>, 
-    max : Vector3<f32>, 
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min : Vector3<f32>, 
+    pub max : Vector3<f32>, 
 }
 
 impl Aabb {
@@ -26,11 +26,38 @@ impl Aabb {
 
 }
 
-pub struct QuadtreeNode {
-    bounds: Aabb, 
-    children: Option<[Box<QuadtreeNode>; 8]>,
-    is_full : bool, 
-    remaining_subdivisions : u32, 
+#[derive(Debug, Clone, Default)]
+pub struct OctreeStats {
+    pub node_count : usize,
+    pub leaf_count : usize,
+    // Indexed by depth, 0 being the root
+    pub leaves_per_depth : Vec<usize>,
+    // Filled leaves above the unit cell level, i.e. merged regions
+    pub collapsed_per_depth : Vec<usize>,
+    pub cpu_bytes : usize,
+}
+
+impl OctreeStats {
+    pub fn collapsed_count(&self) -> usize {
+        self.collapsed_per_depth.iter().sum()
+    }
+}
+
+// Index 0 is always the root, so it doubles as the "no children" marker
+const NO_CHILDREN : u32 = 0;
+
+struct LinearNode<T> {
+    first_child : u32,
+    data : Option<T>,
+}
+
+// Octree stored as a flat array: the eight children of a node are contiguous and
+// addressed by the index of the first one, and a voxel is reached by reading three
+// bits of its Morton code per level instead of testing bounds.
+pub struct QuadtreeNode<T> {
+    nodes : Vec<LinearNode<T>>,
+    free_blocks : Vec<u32>,
+    depth : u32,
 }
 
 fn calc_subdivisions(mut size : f32) -> u32 {
@@ -43,132 +70,261 @@ fn calc_subdivisions(mut size : f32) -> u32 {
     subdv 
 }
 
-impl QuadtreeNode {
-    pub fn new(cells_per_side : u32) -> QuadtreeNode {
+// Spreads the lower 21 bits of v so that there are two zero bits between each of them
+fn spread_bits(v : u32) -> u64 {
+    let mut x = (v as u64) & 0x1f_ffff;
+    x = (x | x << 32) & 0x001f_0000_0000_ffff;
+    x = (x | x << 16) & 0x001f_0000_ff00_00ff;
+    x = (x | x << 8) & 0x100f_00f0_0f00_f00f;
+    x = (x | x << 4) & 0x10c3_0c30_c30c_30c3;
+    x = (x | x << 2) & 0x1249_2492_4924_9249;
+    x
+}
+
+pub fn morton_encode(x : u32, y : u32, z : u32) -> u64 {
+    spread_bits(x) | spread_bits(y) << 1 | spread_bits(z) << 2
+}
 
-        let size = cells_per_side as f32; 
+// Maps a position to the cell that inclusive f32 leaf bounds would have picked first
+fn cell_coordinate(p : f32, cells_per_side : u32) -> u32 {
+    (p.ceil() as i64 - 1).clamp(0, cells_per_side as i64 - 1) as u32
+}
 
+impl<T : PartialEq + Clone> QuadtreeNode<T> {
+    pub fn new(cells_per_side : u32) -> QuadtreeNode<T> {
         QuadtreeNode {
-            bounds: Aabb{
-                min : Vector3::zero(), 
-                max : Vector3::new(size, size, size),  
-            }, 
-            children: None, 
-            is_full: false, 
-            remaining_subdivisions: calc_subdivisions(size), 
+            nodes: vec![LinearNode { first_child: NO_CHILDREN, data: None }],
+            free_blocks: Vec::new(),
+            depth: calc_subdivisions(cells_per_side as f32),
         }
     }
 
-    fn from_bounds(bounds : Aabb) -> QuadtreeNode {
-        let size = bounds.get_size().x; 
+    pub fn cells_per_side(&self) -> u32 {
+        1 << self.depth
+    }
 
-        QuadtreeNode {
-            bounds, 
-            children: None, 
-            is_full: false, 
-            remaining_subdivisions: calc_subdivisions(size), 
+    pub fn bounds(&self) -> Aabb {
+        let size = self.cells_per_side() as f32;
+        Aabb {
+            min : Vector3::new(0.0, 0.0, 0.0),
+            max : Vector3::new(size, size, size),
         }
     }
 
-    pub fn insert_voxel(&mut self, pos: Vector3<f32>) {
-        // Check if we are at the max LOD (smallest subdivision)
-        if self.remaining_subdivisions == 0 {
-            self.is_full = true; 
+    pub fn insert_voxel(&mut self, pos: Vector3<f32>, data: T) {
+        if !self.bounds().contains(pos) {
             return;
         }
 
-        // If this node has no children, create them
-        if self.children.is_none() {
-            let children = [
-                Box::new(QuadtreeNode::from_bounds(self.subdivide(0))),
-                Box::new(QuadtreeNode::from_bounds(self.subdivide(1))),
-                Box::new(QuadtreeNode::from_bounds(self.subdivide(2))),
-                Box::new(QuadtreeNode::from_bounds(self.subdivide(3))),
-                Box::new(QuadtreeNode::from_bounds(self.subdivide(4))),
-                Box::new(QuadtreeNode::from_bounds(self.subdivide(5))),
-                Box::new(QuadtreeNode::from_bounds(self.subdivide(6))),
-                Box::new(QuadtreeNode::from_bounds(self.subdivide(7))),
-            ];
-            self.children = Some(children);
+        let side = self.cells_per_side();
+        self.insert_cell(
+            cell_coordinate(pos.x, side),
+            cell_coordinate(pos.y, side),
+            cell_coordinate(pos.z, side),
+            data,
+        );
+    }
+
+    // Inserts into the unit cell whose min corner is at (x, y, z)
+    pub fn insert_cell(&mut self, x : u32, y : u32, z : u32, data: T) {
+        self.set_cell(x, y, z, Some(data));
+    }
+
+    // Empties the unit cell whose min corner is at (x, y, z)
+    pub fn remove_cell(&mut self, x : u32, y : u32, z : u32) {
+        self.set_cell(x, y, z, None);
+    }
+
+    fn set_cell(&mut self, x : u32, y : u32, z : u32, data: Option<T>) {
+        // Only the low levels of the code are walked, so outside cells would wrap onto inside ones
+        let side = self.cells_per_side();
+        if x >= side || y >= side || z >= side {
+            return;
         }
 
-        // Forward voxel insertion to the correct child
-        if let Some(children) = &mut self.children {
-            for child in children.iter_mut() {
-                if child.bounds.contains(pos) {
-                    child.insert_voxel(pos);
+        let code = morton_encode(x, y, z);
+
+        let mut path = Vec::<u32>::with_capacity(self.depth as usize);
+        let mut node = 0;
 
-                    if children.iter().all(|child| child.is_full) {
-                        self.is_full = true;
-                        self.children = None; 
-                    }
+        for level in (0..self.depth).rev() {
+            let current = &self.nodes[node as usize];
 
+            if current.first_child == NO_CHILDREN {
+                // A collapsed node already holding the same payload has nothing to change
+                if current.data == data {
                     return;
                 }
+                self.split(node);
+            }
+
+            path.push(node);
+            let child_slot = ((code >> (3 * level)) & 7) as u32;
+            node = self.nodes[node as usize].first_child + child_slot;
+        }
+
+        self.nodes[node as usize].data = data;
+
+        for parent in path.into_iter().rev() {
+            if !self.try_merge(parent) {
+                break;
             }
         }
     }
 
-    fn subdivide(&self, index: usize) -> Aabb {
-        let center = self.bounds.get_center();
-
-        match index {
-            0 => Aabb { // Front-top-left
-                min: self.bounds.min,
-                max: Vector3::new(center.x, center.y, center.z),
-            },
-            1 => Aabb { // Front-top-right
-                min: Vector3::new(center.x, self.bounds.min.y, self.bounds.min.z),
-                max: Vector3::new(self.bounds.max.x, center.y, center.z),
-            },
-            2 => Aabb { // Front-bottom-left
-                min: Vector3::new(self.bounds.min.x, center.y, self.bounds.min.z),
-                max: Vector3::new(center.x, self.bounds.max.y, center.z),
-            },
-            3 => Aabb { // Front-bottom-right
-                min: Vector3::new(center.x, center.y, self.bounds.min.z),
-                max: Vector3::new(self.bounds.max.x, self.bounds.max.y, center.z),
-            },
-            4 => Aabb { // Back-top-left
-                min: Vector3::new(self.bounds.min.x, self.bounds.min.y, center.z),
-                max: Vector3::new(center.x, center.y, self.bounds.max.z),
-            },
-            5 => Aabb { // Back-top-right
-                min: Vector3::new(center.x, self.bounds.min.y, center.z),
-                max: Vector3::new(self.bounds.max.x, center.y, self.bounds.max.z),
-            },
-            6 => Aabb { // Back-bottom-left
-                min: Vector3::new(self.bounds.min.x, center.y, center.z),
-                max: Vector3::new(center.x, self.bounds.max.y, self.bounds.max.z),
-            },
-            7 => Aabb { // Back-bottom-right
-                min: Vector3::new(center.x, center.y, center.z),
-                max: self.bounds.max,
-            },
-            _ => unreachable!(),
+    // Allocates the eight children of a leaf, handing down any collapsed payload
+    fn split(&mut self, node : u32) {
+        let inherited = self.nodes[node as usize].data.take();
+
+        let first_child = match self.free_blocks.pop() {
+            Some(first_child) => {
+                for child in &mut self.nodes[first_child as usize..first_child as usize + 8] {
+                    child.data = inherited.clone();
+                }
+                first_child
+            }
+            None => {
+                let first_child = self.nodes.len() as u32;
+                for _ in 0..8 {
+                    self.nodes.push(LinearNode { first_child: NO_CHILDREN, data: inherited.clone() });
+                }
+                first_child
+            }
+        };
+
+        self.nodes[node as usize].first_child = first_child;
+    }
+
+    // Collapses the children into this node when they are all leaves with an equal payload,
+    // or all empty
+    fn try_merge(&mut self, node : u32) -> bool {
+        let first_child = self.nodes[node as usize].first_child as usize;
+        let children = &self.nodes[first_child..first_child + 8];
+
+        let first = &children[0].data;
+        let mergeable = children.iter().all(|child| {
+            child.first_child == NO_CHILDREN && child.data == *first
+        });
+
+        if !mergeable {
+            return false;
+        }
+
+        let merged = first.clone();
+        for child in &mut self.nodes[first_child..first_child + 8] {
+            child.data = None;
         }
+        self.free_blocks.push(first_child as u32);
+
+        let parent = &mut self.nodes[node as usize];
+        parent.first_child = NO_CHILDREN;
+        parent.data = merged;
+        true
     }
 
-    pub fn get_data(&self) -> Vec<InstanceData> {
-        let mut positions = Vec::<InstanceData>::new();
-        self.collect_leaf_positions(&mut positions);
-        positions
+    // Calls the visitor once per filled leaf with its bounds and payload
+    pub fn visit<F>(&self, visitor: F)
+        where F : FnMut(&Aabb, &T)
+    {
+        self.visit_region(&self.bounds(), visitor);
     }
 
-    fn collect_leaf_positions(&self, positions: &mut Vec<InstanceData>) {
-        if let Some(children) = &self.children {
-            for child in children.iter() {
-                child.collect_leaf_positions(positions);
+    // Same as visit, skipping the leaves that do not overlap `region`
+    pub fn visit_region<F>(&self, region : &Aabb, mut visitor: F)
+        where F : FnMut(&Aabb, &T)
+    {
+        // (node, integer min corner, integer side length)
+        let mut stack = vec![(0u32, [0u32; 3], self.cells_per_side())];
+
+        while let Some((node, min, size)) = stack.pop() {
+            let overlaps = (0..3).all(|axis| {
+                (min[axis] as f32) < region.max[axis] && ((min[axis] + size) as f32) > region.min[axis]
+            });
+            if !overlaps {
+                continue;
+            }
+            let current = &self.nodes[node as usize];
+
+            if current.first_child != NO_CHILDREN {
+                let half = size / 2;
+                // Pushed in reverse so that children are visited in slot order
+                for slot in (0..8u32).rev() {
+                    let child_min = [
+                        min[0] + (slot & 1) * half,
+                        min[1] + ((slot >> 1) & 1) * half,
+                        min[2] + ((slot >> 2) & 1) * half,
+                    ];
+                    stack.push((current.first_child + slot, child_min, half));
+                }
+            } else if let Some(data) = &current.data {
+                let min = Vector3::new(min[0] as f32, min[1] as f32, min[2] as f32);
+                let bounds = Aabb {
+                    min,
+                    max : min + Vector3::new(size as f32, size as f32, size as f32),
+                };
+                visitor(&bounds, data);
             }
-        } else if self.is_full {
-            let center = self.bounds.get_center(); 
-            let size : Vector3<f32> = self.bounds.get_size();  
-            let p = InstanceData {
-                position: center.extend(1.0).into(), 
-                size: size.extend(1.0).into(), 
-            }; 
-            positions.push(p);
         }
     }
 
-}
\ No newline at end of file
+    // Bytes held on the CPU by the node array and its free list
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.nodes.capacity() * std::mem::size_of::<LinearNode<T>>()
+            + self.free_blocks.capacity() * std::mem::size_of::<u32>()
+    }
+
+    pub fn stats(&self) -> OctreeStats {
+        let levels = self.depth as usize + 1;
+        let mut stats = OctreeStats {
+            leaves_per_depth: vec![0; levels],
+            collapsed_per_depth: vec![0; levels],
+            cpu_bytes: self.memory_usage(),
+            ..Default::default()
+        };
+
+        let mut stack = vec![(0u32, 0usize)];
+        while let Some((node, depth)) = stack.pop() {
+            stats.node_count += 1;
+            let current = &self.nodes[node as usize];
+
+            if current.first_child != NO_CHILDREN {
+                stack.extend((0..8).map(|slot| (current.first_child + slot, depth + 1)));
+            } else if current.data.is_some() {
+                stats.leaf_count += 1;
+                stats.leaves_per_depth[depth] += 1;
+                if depth < self.depth as usize {
+                    stats.collapsed_per_depth[depth] += 1;
+                }
+            }
+        }
+
+        stats
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(tree : &QuadtreeNode<u32>) -> Vec<(Vector3<f32>, u32)> {
+        let mut leaves = Vec::new();
+        tree.visit(|bounds, data| leaves.push((bounds.min, *data)));
+        leaves
+    }
+
+    #[test]
+    fn cells_outside_the_tree_are_ignored() {
+        let mut tree = QuadtreeNode::new(16);
+        tree.insert_cell(3, 4, 5, 1);
+        // (19, 4, 5) and (3, 20, 5) would wrap onto the filled cell
+        for [x, y, z] in [[16, 0, 0], [0, 16, 0], [0, 0, 16], [19, 4, 5], [3, 20, 5], [u32::MAX, 0, 0]] {
+            tree.insert_cell(x, y, z, 2);
+        }
+        tree.remove_cell(19, 4, 5);
+        tree.remove_cell(3, 20, 5);
+
+        assert_eq!(leaves(&tree), vec![(Vector3::new(3.0, 4.0, 5.0), 1)]);
+    }
+}
@@ -0,0 +1,205 @@
+use std::path::Path;
+
+use cgmath::Vector3;
+use image::RgbaImage;
+use pollster::block_on;
+
+use crate::engine::capture;
+use crate::engine::capture::panorama::{self, PanoramaFormat};
+use crate::engine::camera::fps_camera::FpsCamera;
+use crate::engine::light::DirectionalLight;
+use crate::engine::post::{self, PostProcessor};
+use crate::engine::voxel_engine::VoxelEngine;
+
+use super::builders::texture_builder;
+
+pub const HEADLESS_FORMAT : wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+// Renders into an offscreen texture instead of a window surface
+pub struct HeadlessEngine {
+    device : wgpu::Device,
+    queue : wgpu::Queue,
+    width : u32,
+    height : u32,
+    color_texture : wgpu::Texture,
+    color_view : wgpu::TextureView,
+    scene_view : wgpu::TextureView,
+    depth_texture : wgpu::TextureView,
+    pub post : PostProcessor,
+}
+
+impl HeadlessEngine {
+    pub fn new(width : u32, height : u32) -> Self {
+        Self::try_new(width, height).expect("No adapter available for headless rendering")
+    }
+
+    pub fn try_new(width : u32, height : u32) -> Option<Self> {
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+
+        // Prefer a software adapter so that output does not depend on the GPU, but take
+        // whatever is available on machines without one
+        let adapter = block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::LowPower,
+            compatible_surface: None,
+            force_fallback_adapter: true,
+        }))
+        .or_else(|| block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::LowPower,
+            compatible_surface: None,
+            force_fallback_adapter: false,
+        })))?;
+
+        log::info!("headless rendering on {:?}", adapter.get_info());
+
+        let (device, queue) = block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+            label: Some("Headless Device Descriptor"),
+            features: wgpu::Features::empty(),
+            limits: adapter.limits(),
+        }, None)).unwrap();
+
+        let color_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Headless Color Target"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HEADLESS_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        });
+        let color_view = color_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let scene_view = post::create_hdr_texture(&device, width, height, 1);
+        let post = PostProcessor::new(&device, &queue, HEADLESS_FORMAT);
+
+        let depth_texture = texture_builder::TextureBuilder::new("", texture_builder::TextureType::TextureDepth)
+            .set_dimensions(2)
+            .set_extent(width, height, 1)
+            .set_format(wgpu::TextureFormat::Depth32Float)
+            .set_usage(wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING)
+            .build(&device, &queue);
+
+        Some(Self {
+            device,
+            queue,
+            width,
+            height,
+            color_texture,
+            color_view,
+            scene_view,
+            depth_texture,
+            post,
+        })
+    }
+
+    pub fn get_device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn get_queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    pub fn aspect_ratio(&self) -> f32 {
+        self.width as f32 / self.height as f32
+    }
+
+    pub fn render(&mut self, voxel_engine : &mut VoxelEngine, camera : &FpsCamera, light : &DirectionalLight) -> RgbaImage {
+        let camera_view = camera.get_camera_view();
+        voxel_engine.update(&self.device, &self.queue, &camera_view, light, (self.width, self.height));
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Headless Render Encoder"),
+        });
+
+        voxel_engine.render(&self.scene_view, None, &self.depth_texture, &mut encoder, &camera_view);
+        self.post.render(&self.device, &self.queue, &mut encoder, &self.scene_view, &[&self.color_view], (self.width, self.height));
+        self.queue.submit(Some(encoder.finish()));
+
+        capture::read_texture(&self.device, &self.queue, &self.color_texture, HEADLESS_FORMAT, self.width, self.height)
+    }
+}
+
+pub struct HeadlessOptions {
+    pub output : String,
+    pub width : u32,
+    pub height : u32,
+    pub position : Vector3<f32>,
+    pub yaw : f32,
+    pub pitch : f32,
+    pub panorama : Option<PanoramaFormat>,
+}
+
+impl HeadlessOptions {
+    // Reads `--headless <out.png> [--size <w>x<h>] [--camera <x>,<y>,<z>,<yaw>,<pitch>] [--panorama <cube|equirect>]`.
+    // Panoramas use the height as the size of a cube face, and cube faces are written to a directory.
+    pub fn from_args(args : &[String]) -> Option<Self> {
+        let mut options = Self {
+            output : String::new(),
+            width : 1280,
+            height : 720,
+            position : Vector3::new(-40.0, 80.0, -40.0),
+            yaw : 45.0,
+            pitch : -25.0,
+            panorama : None,
+        };
+
+        let mut headless = false;
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => {
+                    headless = true;
+                    options.output = args.next().expect("--headless expects an output path").clone();
+                }
+                "--size" => {
+                    let size = args.next().expect("--size expects <width>x<height>");
+                    let (width, height) = size.split_once('x').expect("--size expects <width>x<height>");
+                    options.width = width.parse().expect("Invalid width");
+                    options.height = height.parse().expect("Invalid height");
+                }
+                "--camera" => {
+                    let pose : Vec<f32> = args.next().expect("--camera expects <x>,<y>,<z>,<yaw>,<pitch>")
+                        .split(',')
+                        .map(|v| v.parse().expect("Invalid camera value"))
+                        .collect();
+                    assert!(pose.len() == 5, "--camera expects <x>,<y>,<z>,<yaw>,<pitch>");
+                    options.position = Vector3::new(pose[0], pose[1], pose[2]);
+                    options.yaw = pose[3];
+                    options.pitch = pose[4];
+                }
+                "--panorama" => {
+                    options.panorama = match args.next().map(|format| format.as_str()) {
+                        Some("cube") => Some(PanoramaFormat::CubeFaces),
+                        Some("equirect") => Some(PanoramaFormat::Equirectangular),
+                        _ => panic!("--panorama expects cube or equirect"),
+                    };
+                }
+                _ => {}
+            }
+        }
+
+        headless.then_some(options)
+    }
+}
+
+// Renders a single frame or panorama of the default world and writes it to the output path
+pub fn render_to_png(options : &HeadlessOptions) {
+    let mut engine = HeadlessEngine::new(options.width, options.height);
+    let camera = FpsCamera::with_pose(options.position, options.yaw, options.pitch, engine.aspect_ratio());
+    let light = DirectionalLight::new();
+
+    let mut voxel_engine = VoxelEngine::init(engine.get_device(), engine.get_queue(), &post::HDR_FORMAT, &camera, &light);
+
+    let result = match options.panorama {
+        Some(format) => {
+            let faces = panorama::capture_cubemap(&engine.device, &engine.queue, &mut voxel_engine, &mut engine.post, &light, options.position, options.height);
+            panorama::save_panorama(&faces, format, Path::new(&options.output))
+        }
+        None => engine.render(&mut voxel_engine, &camera, &light).save(&options.output),
+    };
+
+    result.unwrap_or_else(|_| panic!("Failed to write image: {}", options.output));
+}
@@ -59,25 +59,26 @@ impl ImguiEngine {
 
     }
     
-    pub fn end_update(&mut self, device : &Device, queue : &wgpu::Queue, view : &TextureView, encoder : &mut wgpu::CommandEncoder) {        
-        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: None,
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Load, 
-                    store: true,
-                },
-            })],
-            depth_stencil_attachment: None,
-        });
+    pub fn end_update(&mut self, device : &Device, queue : &wgpu::Queue, views : &[&TextureView], encoder : &mut wgpu::CommandEncoder) {        
+        let draw_data = self.imgui_context.render();
+        for view in views {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load, 
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
 
-        self.renderer
-            .render(self.imgui_context.render(), queue, device, &mut rpass)
-            .expect("Rendering failed");
-
-        drop(rpass);
+            self.renderer
+                .render(draw_data, queue, device, &mut rpass)
+                .expect("Rendering failed");
+        }
     }
 
     pub fn handle_event(&mut self, window : &Window, event : &winit::event::Event<()>) {
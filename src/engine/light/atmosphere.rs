@@ -0,0 +1,234 @@
+use cgmath::{InnerSpace, Vector3};
+
+use crate::engine::buffers::{self, BufferType};
+use crate::engine::builders::pipeline_bind_group_builder::BindGroupBuilder;
+use crate::engine::builders::pipeline_bind_group_layout_builder::{BindGroupLayoutBuilder, EntryVisibility, LayoutEntryType};
+use crate::engine::builders::pipeline_builder::PipelineBuilder;
+use crate::engine::builders::pipeline_layout_builder::PipelineLayoutBuilder;
+use crate::engine::camera::camera_view::{CameraView, FAR_PLANE};
+use crate::engine::models::instance::VertexType;
+use crate::engine::post::{create_hdr_texture, HDR_FORMAT};
+
+use super::DirectionalLight;
+use super::fog::FogSettings;
+
+const TRANSMITTANCE_LUT_SIZE : (u32, u32) = (256, 64);
+const SKY_VIEW_LUT_SIZE : (u32, u32) = (192, 108);
+// Illuminance of the sun for a light of brightness 1
+const SUN_ILLUMINANCE : f32 = 10.0;
+// Height of the bottom of the world above the ground of the planet, in kilometres
+const BASE_ALTITUDE : f32 = 0.05;
+
+// Inverse view projection, sun direction, sun colour, (altitude, kilometres per unit, aerial perspective, enabled),
+// (camera position, far plane) and the fog
+type AtmosphereData = [[f32; 4]; 12];
+const ATMOSPHERE_DATA_SIZE : u64 = std::mem::size_of::<AtmosphereData>() as u64;
+
+// Sun lighting the sky, apart from the directional light which is the moon at night
+#[derive(Debug, Clone, Copy)]
+pub struct Sun {
+    // Unit vector from the ground towards the sun
+    pub direction : Vector3<f32>,
+    pub color : Vector3<f32>,
+}
+
+#[derive(Clone, Copy)]
+pub struct AtmosphereSettings {
+    pub enabled : bool,
+    // Size of the world in the atmosphere, in kilometres per world unit
+    pub scale : f32,
+    // Scale of the haze over distant terrain, 0 to turn it off
+    pub aerial_perspective : f32,
+}
+
+impl AtmosphereSettings {
+    pub fn new() -> Self {
+        Self {
+            enabled : true,
+            scale : 0.05,
+            aerial_perspective : 1.0,
+        }
+    }
+}
+
+fn create_lut_pipeline(device : &wgpu::Device, shader_path : &str, layouts : &[&wgpu::BindGroupLayout]) -> wgpu::RenderPipeline {
+    let mut pipeline_layout = PipelineLayoutBuilder::new();
+    for layout in layouts {
+        pipeline_layout = pipeline_layout.add_bind_group_layout(layout);
+    }
+    PipelineBuilder::new()
+        .set_primitive_state(None)
+        .set_depth_test(false)
+        .set_vertex_shader(device, shader_path, VertexType::Fullscreen)
+        .set_fragment_shader(device, shader_path, &HDR_FORMAT)
+        .set_pipeline_layout(pipeline_layout.build(device))
+        .build(device)
+}
+
+fn create_sky_pipeline(device : &wgpu::Device, layout : &wgpu::BindGroupLayout, format : &wgpu::TextureFormat, sample_count : u32) -> wgpu::RenderPipeline {
+    PipelineBuilder::new()
+        .set_primitive_state(None)
+        .set_sample_count(sample_count)
+        .set_depth_compare(wgpu::CompareFunction::LessEqual)
+        .set_vertex_shader(device, "./shaders/sky.wgsl", VertexType::Fullscreen)
+        .set_fragment_shader(device, "./shaders/sky.wgsl", format)
+        .set_pipeline_layout(PipelineLayoutBuilder::new().add_bind_group_layout(layout).build(device))
+        .build(device)
+}
+
+fn render_lut(encoder : &mut wgpu::CommandEncoder, pipeline : &wgpu::RenderPipeline, target : &wgpu::TextureView, bind_groups : &[&wgpu::BindGroup]) {
+    let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("Atmosphere LUT Pass"),
+        color_attachments: &[
+            Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                }
+            })
+        ],
+        depth_stencil_attachment: None,
+    });
+    rpass.set_pipeline(pipeline);
+    for (i, bind_group) in bind_groups.iter().enumerate() {
+        rpass.set_bind_group(i as u32, bind_group, &[]);
+    }
+    rpass.draw(0..3, 0..1);
+}
+
+// Sky of single scattered sunlight through an Earth-like atmosphere. The transmittance of the
+// air is computed once, the light scattered towards the camera from every direction of the sky
+// on every frame from the light direction, then the sky pass draws it behind the terrain and
+// the main pass hazes distant terrain with it.
+pub struct Atmosphere {
+    pub settings : AtmosphereSettings,
+    // Applied with the atmosphere, even when the sky is off
+    pub fog : FogSettings,
+    // Sun of the sky, the directional light shining from it when unset
+    pub sun : Option<Sun>,
+    atmosphere_buffer : wgpu::Buffer,
+    sky_view_pipeline : wgpu::RenderPipeline,
+    sky_view_bind_groups : [wgpu::BindGroup; 2],
+    sky_view_lut : wgpu::TextureView,
+    // Draws in the main pass, so it follows the format and samples of its targets
+    sky_pipeline : wgpu::RenderPipeline,
+    format : wgpu::TextureFormat,
+    // Atmosphere and lookup tables read by the sky and main passes
+    pub bind_group_layout : wgpu::BindGroupLayout,
+    pub bind_group : wgpu::BindGroup,
+}
+
+impl Atmosphere {
+    pub fn new(device : &wgpu::Device, queue : &wgpu::Queue, format : &wgpu::TextureFormat) -> Self {
+        let atmosphere_buffer = buffers::create_buffer(device, BufferType::Uniform, &[[0.0f32; 4]; 12]);
+        let transmittance_lut = create_hdr_texture(device, TRANSMITTANCE_LUT_SIZE.0, TRANSMITTANCE_LUT_SIZE.1, 1);
+        let sky_view_lut = create_hdr_texture(device, SKY_VIEW_LUT_SIZE.0, SKY_VIEW_LUT_SIZE.1, 1);
+
+        // Repeats across the azimuth of the sky view, the shaders keep the other coordinates inside
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Atmosphere LUT Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let atmosphere_layout = BindGroupLayoutBuilder::new()
+            .add_entry(LayoutEntryType::UniformBuffer, EntryVisibility::Fragment, ATMOSPHERE_DATA_SIZE)
+            .build(device);
+        let atmosphere_bind_group = BindGroupBuilder::new()
+            .add_uniform_buffer_entry(&atmosphere_buffer, ATMOSPHERE_DATA_SIZE)
+            .build(device, &atmosphere_layout);
+        let texture_layout = BindGroupLayoutBuilder::new()
+            .add_entry(LayoutEntryType::FloatTexture2D, EntryVisibility::Fragment, 0)
+            .add_entry(LayoutEntryType::FilteringSampler, EntryVisibility::Fragment, 0)
+            .build(device);
+        let transmittance_bind_group = BindGroupBuilder::new()
+            .add_texture_entry(&transmittance_lut)
+            .add_sampler_entry(&sampler)
+            .build(device, &texture_layout);
+
+        // The transmittance only depends on the planet and its air
+        let transmittance_pipeline = create_lut_pipeline(device, "./shaders/sky_transmittance.wgsl", &[]);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Atmosphere Encoder") });
+        render_lut(&mut encoder, &transmittance_pipeline, &transmittance_lut, &[]);
+        queue.submit(Some(encoder.finish()));
+
+        let sky_view_pipeline = create_lut_pipeline(device, "./shaders/sky_view.wgsl", &[&atmosphere_layout, &texture_layout]);
+
+        let bind_group_layout = BindGroupLayoutBuilder::new()
+            .add_entry(LayoutEntryType::UniformBuffer, EntryVisibility::Fragment, ATMOSPHERE_DATA_SIZE)
+            .add_entry(LayoutEntryType::FloatTexture2D, EntryVisibility::Fragment, 0)
+            .add_entry(LayoutEntryType::FloatTexture2D, EntryVisibility::Fragment, 0)
+            .add_entry(LayoutEntryType::FilteringSampler, EntryVisibility::Fragment, 0)
+            .build(device);
+        let bind_group = BindGroupBuilder::new()
+            .add_uniform_buffer_entry(&atmosphere_buffer, ATMOSPHERE_DATA_SIZE)
+            .add_texture_entry(&transmittance_lut)
+            .add_texture_entry(&sky_view_lut)
+            .add_sampler_entry(&sampler)
+            .build(device, &bind_group_layout);
+
+        Self {
+            settings : AtmosphereSettings::new(),
+            fog : FogSettings::new(),
+            sun : None,
+            atmosphere_buffer,
+            sky_view_pipeline,
+            sky_view_bind_groups : [atmosphere_bind_group, transmittance_bind_group],
+            sky_view_lut,
+            sky_pipeline : create_sky_pipeline(device, &bind_group_layout, format, 1),
+            format : *format,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    // Rebuilds the sky pipeline for main pass targets with a different number of samples per pixel
+    pub fn set_sample_count(&mut self, device : &wgpu::Device, sample_count : u32) {
+        self.sky_pipeline = create_sky_pipeline(device, &self.bind_group_layout, &self.format, sample_count);
+    }
+
+    // Uploads the sun and the camera. Without a sun of its own the sky is lit by `light`, the sun
+    // direction being the opposite of the light direction.
+    pub fn update(&self, queue : &wgpu::Queue, camera : &CameraView, light : &DirectionalLight) {
+        let sun = self.sun.unwrap_or(Sun { direction : -light.direction, color : light.color });
+        let sun_direction = if sun.direction.magnitude2() > 0.0 { sun.direction.normalize() } else { sun.direction };
+        let settings = &self.settings;
+        let altitude = BASE_ALTITUDE + camera.position.y.max(0.0) * settings.scale;
+
+        let inverse_view_projection : [[f32; 4]; 4] = camera.get_inverse_sky_matrix().into();
+        let mut atmosphere_data : AtmosphereData = [[0.0; 4]; 12];
+        atmosphere_data[0..4].copy_from_slice(&inverse_view_projection);
+        atmosphere_data[4] = sun_direction.extend(0.0).into();
+        // Sunlight is white above the air, which gives the sky its colour
+        let sun_intensity = sun.color.x.max(sun.color.y).max(sun.color.z) * SUN_ILLUMINANCE;
+        atmosphere_data[5] = [sun_intensity, sun_intensity, sun_intensity, 1.0];
+        atmosphere_data[6] = [altitude, settings.scale, settings.aerial_perspective, if settings.enabled { 1.0 } else { 0.0 }];
+        atmosphere_data[7] = camera.position.extend(FAR_PLANE).into();
+        atmosphere_data[8..12].copy_from_slice(&self.fog.fog_data(camera.position, light));
+        queue.write_buffer(&self.atmosphere_buffer, 0, bytemuck::cast_slice(&atmosphere_data));
+    }
+
+    // Scatters the sunlight of the frame into the sky view table, to be run before the main pass
+    pub fn render_sky_view(&self, encoder : &mut wgpu::CommandEncoder) {
+        if self.settings.enabled {
+            let [atmosphere, transmittance] = &self.sky_view_bind_groups;
+            render_lut(encoder, &self.sky_view_pipeline, &self.sky_view_lut, &[atmosphere, transmittance]);
+        }
+    }
+
+    // Draws the sky wherever the main pass left the depth on the far plane
+    pub fn render_sky<'a>(&'a self, rpass : &mut wgpu::RenderPass<'a>) {
+        if self.settings.enabled {
+            rpass.set_pipeline(&self.sky_pipeline);
+            rpass.set_bind_group(0, &self.bind_group, &[]);
+            rpass.draw(0..3, 0..1);
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use cgmath::{InnerSpace, Vector3};
+
+use crate::engine::buffers::{self, BufferType};
+use crate::engine::builders::pipeline_bind_group_builder::BindGroupBuilder;
+use crate::engine::builders::pipeline_bind_group_layout_builder::{BindGroupLayoutBuilder, EntryVisibility, LayoutEntryType};
+use crate::engine::builders::pipeline_layout_builder::PipelineLayoutBuilder;
+use crate::engine::camera::camera_view::{CameraView, FAR_PLANE, NEAR_PLANE};
+use crate::engine::shaders;
+
+pub const MAX_LOCAL_LIGHTS : usize = 256;
+// Screen tiles across, down and depth slices of the view frustum
+const CLUSTER_COUNTS : [u32; 3] = [16, 9, 24];
+const CLUSTER_COUNT : u32 = CLUSTER_COUNTS[0] * CLUSTER_COUNTS[1] * CLUSTER_COUNTS[2];
+// Lights beyond this number in one cluster are ignored there
+const MAX_LIGHTS_PER_CLUSTER : u32 = 63;
+const WORKGROUP_SIZE : u32 = 64;
+
+// Position, colour, direction and cone of each light
+type LightData = [[f32; 4]; 4];
+// View matrix, (width, height, near, far), (cluster counts, light count) and (projection scale, max lights per cluster)
+type ClusterData = [[f32; 4]; 7];
+const LIGHT_DATA_SIZE : u64 = (std::mem::size_of::<LightData>() * MAX_LOCAL_LIGHTS) as u64;
+const CLUSTER_DATA_SIZE : u64 = std::mem::size_of::<ClusterData>() as u64;
+// Per cluster, the number of lights followed by their indices
+const CLUSTER_LIGHTS_SIZE : u64 = (CLUSTER_COUNT * (MAX_LIGHTS_PER_CLUSTER + 1) * 4) as u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalLightKind {
+    Point,
+    // Lights a cone around its direction
+    Spot,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LocalLight {
+    pub kind : LocalLightKind,
+    pub position : Vector3<f32>,
+    pub color : Vector3<f32>,
+    pub intensity : f32,
+    // Distance at which the light fades out completely
+    pub range : f32,
+    pub direction : Vector3<f32>,
+    // Half angles in degrees, fully lit inside the inner cone and fading out to the outer one
+    pub inner_angle : f32,
+    pub outer_angle : f32,
+}
+
+impl LocalLight {
+    pub fn point(position : Vector3<f32>, color : Vector3<f32>, intensity : f32, range : f32) -> Self {
+        Self {
+            kind : LocalLightKind::Point,
+            position,
+            color,
+            intensity,
+            range,
+            direction : Vector3::new(0.0, -1.0, 0.0),
+            inner_angle : 20.0,
+            outer_angle : 30.0,
+        }
+    }
+
+    pub fn spot(position : Vector3<f32>, direction : Vector3<f32>, color : Vector3<f32>, intensity : f32, range : f32) -> Self {
+        Self {
+            kind : LocalLightKind::Spot,
+            direction,
+            ..Self::point(position, color, intensity, range)
+        }
+    }
+
+    fn light_data(&self) -> LightData {
+        let spot = if self.kind == LocalLightKind::Spot { 1.0 } else { 0.0 };
+        let direction = if self.direction.magnitude2() > 0.0 { self.direction.normalize() } else { Vector3::new(0.0, -1.0, 0.0) };
+        let outer = self.outer_angle.clamp(0.0, 90.0);
+        let inner = self.inner_angle.clamp(0.0, outer);
+        [
+            self.position.extend(self.range.max(0.001)).into(),
+            (self.color * self.intensity).extend(0.0).into(),
+            direction.extend(spot).into(),
+            [inner.to_radians().cos(), outer.to_radians().cos(), 0.0, 0.0],
+        ]
+    }
+}
+
+// Point and spot lights of the scene. A compute pass sorts them into clusters, the cells of a grid
+// of screen tiles and depth slices over the view frustum, so that the main pass only shades a
+// pixel with the lights reaching its cluster.
+pub struct ClusteredLights {
+    cluster_buffer : wgpu::Buffer,
+    light_buffer : wgpu::Buffer,
+    assign_pipeline : wgpu::ComputePipeline,
+    assign_bind_group : wgpu::BindGroup,
+    // Lights and clusters read by the main pass
+    pub bind_group_layout : wgpu::BindGroupLayout,
+    pub bind_group : wgpu::BindGroup,
+    pub lights : Vec<LocalLight>,
+}
+
+impl ClusteredLights {
+    pub fn new(device : &wgpu::Device) -> Self {
+        let cluster_buffer = buffers::create_buffer(device, BufferType::Uniform, &[[0.0f32; 4]; 7]);
+        let light_buffer = buffers::create_buffer(device, BufferType::Storage, &[[[0.0f32; 4]; 4]; MAX_LOCAL_LIGHTS]);
+        let cluster_lights = buffers::create_buffer(device, BufferType::Storage, &vec![0u32; (CLUSTER_LIGHTS_SIZE / 4) as usize]);
+
+        let assign_layout = BindGroupLayoutBuilder::new()
+            .add_entry(LayoutEntryType::UniformBuffer, EntryVisibility::Compute, CLUSTER_DATA_SIZE)
+            .add_entry(LayoutEntryType::StorageBuffer, EntryVisibility::Compute, LIGHT_DATA_SIZE)
+            .add_entry(LayoutEntryType::WritableStorageBuffer, EntryVisibility::Compute, CLUSTER_LIGHTS_SIZE)
+            .build(device);
+        let assign_bind_group = BindGroupBuilder::new()
+            .add_uniform_buffer_entry(&cluster_buffer, CLUSTER_DATA_SIZE)
+            .add_storage_buffer_entry(&light_buffer, LIGHT_DATA_SIZE)
+            .add_storage_buffer_entry(&cluster_lights, CLUSTER_LIGHTS_SIZE)
+            .build(device, &assign_layout);
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Light Clusters Shader"),
+            source: wgpu::ShaderSource::Wgsl(shaders::compile_shader("./shaders/light_clusters.wgsl").into()),
+        });
+        let assign_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Light Clusters Pipeline"),
+            layout: Some(&PipelineLayoutBuilder::new().add_bind_group_layout(&assign_layout).build(device)),
+            module: &module,
+            entry_point: "cs_main",
+        });
+
+        let bind_group_layout = BindGroupLayoutBuilder::new()
+            .add_entry(LayoutEntryType::UniformBuffer, EntryVisibility::Fragment, CLUSTER_DATA_SIZE)
+            .add_entry(LayoutEntryType::StorageBuffer, EntryVisibility::Fragment, LIGHT_DATA_SIZE)
+            .add_entry(LayoutEntryType::StorageBuffer, EntryVisibility::Fragment, CLUSTER_LIGHTS_SIZE)
+            .build(device);
+        let bind_group = BindGroupBuilder::new()
+            .add_uniform_buffer_entry(&cluster_buffer, CLUSTER_DATA_SIZE)
+            .add_storage_buffer_entry(&light_buffer, LIGHT_DATA_SIZE)
+            .add_storage_buffer_entry(&cluster_lights, CLUSTER_LIGHTS_SIZE)
+            .build(device, &bind_group_layout);
+
+        Self {
+            cluster_buffer,
+            light_buffer,
+            assign_pipeline,
+            assign_bind_group,
+            bind_group_layout,
+            bind_group,
+            lights : Vec::new(),
+        }
+    }
+
+    fn light_count(&self) -> usize {
+        self.lights.len().min(MAX_LOCAL_LIGHTS)
+    }
+
+    // Uploads the lights and the clusters of a frame of `size` pixels seen from the camera
+    pub fn update(&self, queue : &wgpu::Queue, camera : &CameraView, (width, height) : (u32, u32)) {
+        let view : [[f32; 4]; 4] = camera.view.into();
+        let mut cluster_data : ClusterData = [[0.0; 4]; 7];
+        cluster_data[0..4].copy_from_slice(&view);
+        cluster_data[4] = [width as f32, height as f32, NEAR_PLANE, FAR_PLANE];
+        cluster_data[5] = [CLUSTER_COUNTS[0] as f32, CLUSTER_COUNTS[1] as f32, CLUSTER_COUNTS[2] as f32, self.light_count() as f32];
+        cluster_data[6] = [camera.projection.x.x, camera.projection.y.y, MAX_LIGHTS_PER_CLUSTER as f32, 0.0];
+        queue.write_buffer(&self.cluster_buffer, 0, bytemuck::cast_slice(&cluster_data));
+
+        let light_data : Vec<LightData> = self.lights.iter().take(MAX_LOCAL_LIGHTS).map(|light| light.light_data()).collect();
+        if !light_data.is_empty() {
+            queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&light_data));
+        }
+    }
+
+    // Fills the light lists of the clusters, to be run before the main pass
+    pub fn assign(&self, encoder : &mut wgpu::CommandEncoder) {
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("Light Clusters Pass") });
+        cpass.set_pipeline(&self.assign_pipeline);
+        cpass.set_bind_group(0, &self.assign_bind_group, &[]);
+        cpass.dispatch_workgroups(CLUSTER_COUNT.div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+}
@@ -0,0 +1,200 @@
+use cgmath::{InnerSpace, Vector3};
+
+use super::DirectionalLight;
+use super::atmosphere::Sun;
+
+// Moonlight colour at full strength, far dimmer than the sun
+const MOON_COLOR : [f32; 3] = [0.18, 0.22, 0.35];
+// Sine of the elevation over which the sun and the moon fade in above the horizon
+const HORIZON_FADE : f32 = 0.15;
+
+// Lighting at one time of the day
+#[derive(Debug, Clone, Copy)]
+struct SkyKeyframe {
+    // Fraction of the day, 0 at midnight and 0.5 at noon
+    time : f32,
+    sun_color : [f32; 3],
+    sky_color : [f32; 3],
+    ambient : [f32; 3],
+}
+
+// Night, dawn, noon and dusk, in the order of the day
+const KEYFRAMES : [SkyKeyframe; 7] = [
+    SkyKeyframe { time : 0.0, sun_color : [0.0, 0.0, 0.0], sky_color : [0.005, 0.008, 0.02], ambient : [0.03, 0.035, 0.06] },
+    SkyKeyframe { time : 0.22, sun_color : [0.0, 0.0, 0.0], sky_color : [0.04, 0.04, 0.09], ambient : [0.05, 0.05, 0.08] },
+    SkyKeyframe { time : 0.25, sun_color : [1.0, 0.45, 0.2], sky_color : [0.55, 0.3, 0.2], ambient : [0.12, 0.09, 0.08] },
+    SkyKeyframe { time : 0.32, sun_color : [1.0, 0.85, 0.6], sky_color : [0.35, 0.55, 0.85], ambient : [0.18, 0.18, 0.2] },
+    SkyKeyframe { time : 0.5, sun_color : [1.0, 1.0, 0.85], sky_color : [0.3, 0.55, 0.95], ambient : [0.2, 0.2, 0.22] },
+    SkyKeyframe { time : 0.68, sun_color : [1.0, 0.85, 0.6], sky_color : [0.35, 0.55, 0.85], ambient : [0.18, 0.18, 0.2] },
+    SkyKeyframe { time : 0.75, sun_color : [1.0, 0.4, 0.15], sky_color : [0.6, 0.28, 0.15], ambient : [0.12, 0.08, 0.07] },
+];
+
+fn lerp(a : [f32; 3], b : [f32; 3], t : f32) -> Vector3<f32> {
+    Vector3::from(a) + (Vector3::from(b) - Vector3::from(a)) * t
+}
+
+fn smoothstep(edge0 : f32, edge1 : f32, x : f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Time of day moving the sun along its path and setting the colours of the light and the sky.
+// The moon faces the sun and takes over the directional light, and its shadows, at night.
+pub struct DayCycle {
+    // Whether the cycle drives the light at all
+    pub enabled : bool,
+    pub paused : bool,
+    // Real seconds for a whole day
+    pub day_length : f32,
+    // Fraction of the day, 0 at midnight and 0.5 at noon
+    pub time_of_day : f32,
+    // Compass direction of sunrise, in degrees around the y axis
+    pub sunrise_azimuth : f32,
+    // Height of the sun at noon, in degrees above the horizon
+    pub noon_elevation : f32,
+}
+
+impl DayCycle {
+    pub fn new() -> Self {
+        Self {
+            enabled : true,
+            paused : false,
+            day_length : 240.0,
+            time_of_day : 0.35,
+            sunrise_azimuth : 45.0,
+            noon_elevation : 60.0,
+        }
+    }
+
+    pub fn update(&mut self, delta_time : f32) {
+        if self.enabled && !self.paused && self.day_length > 0.0 {
+            self.time_of_day = (self.time_of_day + delta_time / self.day_length).rem_euclid(1.0);
+        }
+    }
+
+    // Unit vector from the ground towards the sun, rising at dawn and setting at dusk on
+    // the opposite side of the sky
+    pub fn sun_position(&self) -> Vector3<f32> {
+        let angle = (self.time_of_day - 0.25) * std::f32::consts::TAU;
+        let azimuth = self.sunrise_azimuth.to_radians();
+        let elevation = self.noon_elevation.clamp(0.0, 90.0).to_radians();
+        let sunrise = Vector3::new(azimuth.cos(), 0.0, azimuth.sin());
+        // Where the sun stands at noon, tilted from the zenith away from the sunrise
+        let noon = Vector3::new(0.0, 1.0, 0.0) * elevation.sin() + Vector3::new(-sunrise.z, 0.0, sunrise.x) * elevation.cos();
+        (sunrise * angle.cos() + noon * angle.sin()).normalize()
+    }
+
+    fn keyframe_colors(&self) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+        let time = self.time_of_day.rem_euclid(1.0);
+        let next = KEYFRAMES.iter().position(|keyframe| keyframe.time > time).unwrap_or(0);
+        let from = &KEYFRAMES[(next + KEYFRAMES.len() - 1) % KEYFRAMES.len()];
+        let to = &KEYFRAMES[next];
+        // The last keyframe blends into the first one across midnight
+        let span = (to.time - from.time).rem_euclid(1.0);
+        let t = (time - from.time).rem_euclid(1.0) / span;
+        (lerp(from.sun_color, to.sun_color, t), lerp(from.sky_color, to.sky_color, t), lerp(from.ambient, to.ambient, t))
+    }
+
+    // Clear colour of the sky behind the terrain, black when the cycle is off
+    pub fn sky_color(&self) -> Vector3<f32> {
+        if !self.enabled {
+            return Vector3::new(0.0, 0.0, 0.0);
+        }
+        self.keyframe_colors().1
+    }
+
+    // The sun lighting the sky, dark below the horizon, or none when the cycle is off
+    pub fn sun(&self) -> Option<Sun> {
+        if !self.enabled {
+            return None;
+        }
+        let direction = self.sun_position();
+        let color = self.keyframe_colors().0 * smoothstep(0.0, HORIZON_FADE, direction.y);
+        Some(Sun { direction, color })
+    }
+
+    // Points the light at the terrain from the sun by day and from the moon by night
+    pub fn apply(&self, light : &mut DirectionalLight) {
+        let sun = match self.sun() {
+            Some(sun) => sun,
+            None => return,
+        };
+        let ambient = self.keyframe_colors().2;
+        // Both are on the horizon and fully faded out when they swap
+        if sun.direction.y >= 0.0 {
+            light.direction = -sun.direction;
+            light.color = sun.color;
+        } else {
+            light.direction = sun.direction;
+            light.color = Vector3::from(MOON_COLOR) * smoothstep(0.0, HORIZON_FADE, -sun.direction.y);
+        }
+        light.ambient = ambient;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn light_at(cycle : &mut DayCycle, time_of_day : f32) -> DirectionalLight {
+        cycle.time_of_day = time_of_day;
+        let mut light = DirectionalLight::new();
+        cycle.apply(&mut light);
+        light
+    }
+
+    #[test]
+    fn sun_peaks_at_noon_and_sets_at_dusk() {
+        let mut cycle = DayCycle::new();
+        cycle.time_of_day = 0.5;
+        assert!((cycle.sun_position().y - cycle.noon_elevation.to_radians().sin()).abs() < 1e-4);
+        cycle.time_of_day = 0.75;
+        assert!(cycle.sun_position().y.abs() < 1e-4);
+        cycle.time_of_day = 0.0;
+        assert!(cycle.sun_position().y < -0.8);
+    }
+
+    #[test]
+    fn light_shines_down_from_the_sun_and_the_moon() {
+        let mut cycle = DayCycle::new();
+        for step in 0..100 {
+            let light = light_at(&mut cycle, step as f32 / 100.0);
+            assert!(light.direction.y <= 1e-4);
+        }
+        let night = light_at(&mut cycle, 0.0);
+        let noon = light_at(&mut cycle, 0.5);
+        assert!(night.color.magnitude() > 0.0);
+        assert!(night.color.magnitude() < noon.color.magnitude() * 0.5);
+    }
+
+    #[test]
+    fn sky_follows_the_sun_not_the_moon() {
+        let mut cycle = DayCycle::new();
+        for time_of_day in [0.0, 0.1, 0.9] {
+            let light = light_at(&mut cycle, time_of_day);
+            let sun = cycle.sun().unwrap();
+            assert!(sun.direction.y < 0.0);
+            assert!(sun.direction.dot(-light.direction) < 0.0);
+            assert_eq!(sun.color.magnitude(), 0.0);
+        }
+        let light = light_at(&mut cycle, 0.5);
+        let sun = cycle.sun().unwrap();
+        assert!((sun.direction + light.direction).magnitude() < 1e-5);
+        assert_eq!(sun.color, light.color);
+
+        cycle.enabled = false;
+        assert!(cycle.sun().is_none());
+    }
+
+    #[test]
+    fn light_fades_smoothly_through_dawn_and_dusk() {
+        let mut cycle = DayCycle::new();
+        for horizon in [0.25, 0.75] {
+            let before = light_at(&mut cycle, horizon - 0.0005);
+            let after = light_at(&mut cycle, horizon + 0.0005);
+            assert!(before.color.magnitude() < 0.01);
+            assert!(after.color.magnitude() < 0.01);
+            assert!((before.ambient - after.ambient).magnitude() < 0.01);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use cgmath::{ElementWise, Vector3};
+
+use super::DirectionalLight;
+
+// Per channel absorption of water relative to its strongest, red fades first and blue last
+const WATER_ABSORPTION : [f32; 3] = [1.0, 0.35, 0.2];
+
+// Fog thickening with distance and towards the ground, and the water the camera can dive into.
+// Below the water level the water replaces the fog.
+#[derive(Clone, Copy)]
+pub struct FogSettings {
+    pub enabled : bool,
+    pub color : Vector3<f32>,
+    // Extinction per world unit everywhere
+    pub density : f32,
+    // Extinction per world unit at the base height, thinning above it
+    pub height_density : f32,
+    // How fast the height fog thins, per world unit above the base height
+    pub height_falloff : f32,
+    pub base_height : f32,
+    pub underwater_enabled : bool,
+    pub water_level : f32,
+    pub water_color : Vector3<f32>,
+    // Distance over which the water absorbs nearly all of the red light
+    pub water_visibility : f32,
+}
+
+impl FogSettings {
+    pub fn new() -> Self {
+        Self {
+            enabled : true,
+            color : Vector3::new(0.6, 0.7, 0.8),
+            density : 0.0008,
+            height_density : 0.01,
+            height_falloff : 0.1,
+            base_height : 0.0,
+            underwater_enabled : true,
+            water_level : 9.0,
+            water_color : Vector3::new(0.02, 0.12, 0.16),
+            water_visibility : 40.0,
+        }
+    }
+
+    pub fn is_underwater(&self, camera_position : Vector3<f32>) -> bool {
+        self.underwater_enabled && camera_position.y < self.water_level
+    }
+
+    // Fog, its colour, the water absorption and the water colour as read by atmosphere.wgsl. The
+    // fog and the water are lit like a white surface facing the light, so they darken at night.
+    pub fn fog_data(&self, camera_position : Vector3<f32>, light : &DirectionalLight) -> [[f32; 4]; 4] {
+        let lighting = light.color + light.ambient;
+        let enabled = if self.enabled { 1.0 } else { 0.0 };
+        let underwater = if self.is_underwater(camera_position) { 1.0 } else { 0.0 };
+        // exp(-3) leaves 5% of the light
+        let absorption = Vector3::from(WATER_ABSORPTION) * 3.0 / self.water_visibility.max(0.1);
+        [
+            [self.density, self.height_density, self.height_falloff.max(0.0001), self.base_height],
+            self.color.mul_element_wise(lighting).extend(enabled).into(),
+            absorption.extend(self.water_level).into(),
+            self.water_color.mul_element_wise(lighting).extend(underwater).into(),
+        ]
+    }
+}
@@ -0,0 +1,476 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::engine::data::{Aabb, QuadtreeNode};
+use crate::engine::data::chunk::{ChunkMap, CHUNK_SIZE};
+
+pub const MAX_LIGHT : u8 = 15;
+
+const CHUNK_CELLS : usize = (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+const DOWN : [i32; 3] = [0, -1, 0];
+const NEIGHBOURS : [[i32; 3]; 6] = [
+    [0, 1, 0],
+    DOWN,
+    [1, 0, 0],
+    [-1, 0, 0],
+    [0, 0, 1],
+    [0, 0, -1],
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Channel {
+    Sky,
+    Block,
+}
+
+fn offset([x, y, z] : [i32; 3], [dx, dy, dz] : [i32; 3]) -> [i32; 3] {
+    [x + dx, y + dy, z + dz]
+}
+
+// Level reaching a neighbour, sky light at full strength falls straight down without fading
+fn spread(channel : Channel, level : u8, direction : [i32; 3]) -> u8 {
+    if channel == Channel::Sky && level == MAX_LIGHT && direction == DOWN {
+        MAX_LIGHT
+    } else {
+        level.saturating_sub(1)
+    }
+}
+
+// Flood-filled light levels of the empty cells of the world, from 0 to MAX_LIGHT. The sky channel
+// falls from above and spreads sideways under overhangs, the block channel spreads from emitting
+// voxels. Air above the highest voxel of its column sees the sky directly and stores nothing.
+pub struct LightMap {
+    size : i32,
+    // Highest solid cell of each column, -1 for empty columns
+    heights : Vec<i32>,
+    // Sky light in the high and block light in the low four bits of each cell, in the chunks of the ChunkMap
+    chunks : HashMap<[i32; 3], Vec<u8>>,
+    // Chunks holding faces next to cells whose light changed since the last take_changed_chunks,
+    // tracked once the world is lit
+    changed : Option<HashSet<[i32; 3]>>,
+}
+
+impl LightMap {
+    // Lights the whole world, `emission` gives the block light level emitted by a voxel
+    pub fn new<T, F>(world : &QuadtreeNode<T>, chunks : &ChunkMap<T>, emission : F) -> Self
+        where T : PartialEq + Clone, F : Fn(&T) -> u8
+    {
+        let size = world.cells_per_side() as i32;
+        let mut map = Self {
+            size,
+            heights : vec![-1; (size * size) as usize],
+            chunks : HashMap::new(),
+            changed : None,
+        };
+
+        let mut emitters = Vec::new();
+        world.visit(|bounds, data| {
+            let min = [bounds.min.x as i32, bounds.min.y as i32, bounds.min.z as i32];
+            let max = [bounds.max.x as i32, bounds.max.y as i32, bounds.max.z as i32];
+            for z in min[2]..max[2] {
+                for x in min[0]..max[0] {
+                    let height = &mut map.heights[(z * size + x) as usize];
+                    *height = (*height).max(max[1] - 1);
+                }
+            }
+
+            let level = emission(data);
+            if level > 0 {
+                for z in min[2]..max[2] {
+                    for y in min[1]..max[1] {
+                        for x in min[0]..max[0] {
+                            emitters.push(([x, y, z], level));
+                        }
+                    }
+                }
+            }
+        });
+
+        // Covered air next to a column open to the sky
+        let mut sky_sources = VecDeque::new();
+        for z in 0..size {
+            for x in 0..size {
+                for [dx, _, dz] in NEIGHBOURS.iter().filter(|direction| direction[1] == 0) {
+                    for y in map.height(x + dx, z + dz) + 1..=map.height(x, z) {
+                        if !chunks.is_cell_solid(x, y, z) && map.get(Channel::Sky, [x, y, z]) < MAX_LIGHT - 1 {
+                            map.set(Channel::Sky, [x, y, z], MAX_LIGHT - 1);
+                            sky_sources.push_back([x, y, z]);
+                        }
+                    }
+                }
+            }
+        }
+        map.propagate(chunks, Channel::Sky, sky_sources);
+
+        let mut block_sources = VecDeque::new();
+        for (cell, level) in emitters {
+            map.set(Channel::Block, cell, level);
+            block_sources.push_back(cell);
+        }
+        map.propagate(chunks, Channel::Block, block_sources);
+
+        map.changed = Some(HashSet::new());
+        map
+    }
+
+    fn height(&self, x : i32, z : i32) -> i32 {
+        if x < 0 || z < 0 || x >= self.size || z >= self.size {
+            return -1;
+        }
+        self.heights[(z * self.size + x) as usize]
+    }
+
+    fn in_bounds(&self, [x, y, z] : [i32; 3]) -> bool {
+        (0..self.size).contains(&x) && (0..self.size).contains(&y) && (0..self.size).contains(&z)
+    }
+
+    fn is_open_sky(&self, [x, y, z] : [i32; 3]) -> bool {
+        y > self.height(x, z)
+    }
+
+    fn locate([x, y, z] : [i32; 3]) -> ([i32; 3], usize) {
+        let key = [x.div_euclid(CHUNK_SIZE), y.div_euclid(CHUNK_SIZE), z.div_euclid(CHUNK_SIZE)];
+        let [lx, ly, lz] = [x.rem_euclid(CHUNK_SIZE), y.rem_euclid(CHUNK_SIZE), z.rem_euclid(CHUNK_SIZE)];
+        (key, ((lz * CHUNK_SIZE + ly) * CHUNK_SIZE + lx) as usize)
+    }
+
+    fn get(&self, channel : Channel, cell : [i32; 3]) -> u8 {
+        if channel == Channel::Sky && self.is_open_sky(cell) {
+            return MAX_LIGHT;
+        }
+        let (key, index) = Self::locate(cell);
+        let packed = self.chunks.get(&key).map_or(0, |levels| levels[index]);
+        match channel {
+            Channel::Sky => packed >> 4,
+            Channel::Block => packed & 0xf,
+        }
+    }
+
+    fn set(&mut self, channel : Channel, cell : [i32; 3], level : u8) {
+        let (key, index) = Self::locate(cell);
+        if level == 0 && !self.chunks.contains_key(&key) {
+            return;
+        }
+        let packed = &mut self.chunks.entry(key).or_insert_with(|| vec![0; CHUNK_CELLS])[index];
+        let previous = *packed;
+        *packed = match channel {
+            Channel::Sky => (*packed & 0xf) | level << 4,
+            Channel::Block => (*packed & 0xf0) | level,
+        };
+        if *packed != previous {
+            self.mark_changed(cell);
+        }
+    }
+
+    // The light of a cell shades the faces of the voxels around it, which may lie in the
+    // neighbouring chunks
+    fn mark_changed(&mut self, [x, y, z] : [i32; 3]) {
+        if let Some(changed) = self.changed.as_mut() {
+            for corner in 0..8 {
+                let neighbour = [x + (corner & 1) * 2 - 1, y + (corner >> 1 & 1) * 2 - 1, z + (corner >> 2 & 1) * 2 - 1];
+                changed.insert(Self::locate(neighbour).0);
+            }
+        }
+    }
+
+    // Chunks whose faces have to be shaded again after the last updates
+    pub fn take_changed_chunks(&mut self) -> HashSet<[i32; 3]> {
+        self.changed.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    pub fn sky_light(&self, cell : [i32; 3]) -> u8 {
+        self.get(Channel::Sky, cell)
+    }
+
+    pub fn block_light(&self, cell : [i32; 3]) -> u8 {
+        self.get(Channel::Block, cell)
+    }
+
+    // Spreads the light of the queued cells through the empty cells around them
+    fn propagate<T : PartialEq + Clone>(&mut self, chunks : &ChunkMap<T>, channel : Channel, mut queue : VecDeque<[i32; 3]>) {
+        while let Some(cell) = queue.pop_front() {
+            let level = self.get(channel, cell);
+            for direction in NEIGHBOURS {
+                let next = offset(cell, direction);
+                if !self.in_bounds(next) || chunks.is_cell_solid(next[0], next[1], next[2]) {
+                    continue;
+                }
+                let next_level = spread(channel, level, direction);
+                if next_level > self.get(channel, next) {
+                    self.set(channel, next, next_level);
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+
+    // Darkens the cells lit through the given cells, whose light was just cleared from the level
+    // they held, then lets the remaining sources fill the darkened area again
+    fn remove_light<T : PartialEq + Clone>(&mut self, chunks : &ChunkMap<T>, channel : Channel, removed : Vec<([i32; 3], u8)>) {
+        let mut queue : VecDeque<([i32; 3], u8)> = removed.into();
+        let mut sources = VecDeque::new();
+        while let Some((cell, level)) = queue.pop_front() {
+            for direction in NEIGHBOURS {
+                let next = offset(cell, direction);
+                if !self.in_bounds(next) {
+                    continue;
+                }
+                let next_level = self.get(channel, next);
+                if next_level == 0 {
+                    continue;
+                }
+
+                // Emitters and open sky keep their light whatever happens around them
+                let fixed = chunks.is_cell_solid(next[0], next[1], next[2]) || (channel == Channel::Sky && self.is_open_sky(next));
+                let lit_from_cell = next_level < level || (spread(channel, level, direction) == MAX_LIGHT && next_level == MAX_LIGHT);
+                if !fixed && lit_from_cell {
+                    self.set(channel, next, 0);
+                    queue.push_back((next, next_level));
+                } else {
+                    sources.push_back(next);
+                }
+            }
+        }
+        self.propagate(chunks, channel, sources);
+    }
+
+    // Updates the light after a voxel was added to `chunks`
+    pub fn add_voxel<T : PartialEq + Clone>(&mut self, chunks : &ChunkMap<T>, cell : [i32; 3], emission : u8) {
+        let [x, y, z] = cell;
+        // The voxel hides and occludes the faces around it
+        self.mark_changed(cell);
+        let mut sky_removed = vec![(cell, self.get(Channel::Sky, cell))];
+        self.set(Channel::Sky, cell, 0);
+
+        let height = self.height(x, z);
+        if y > height && self.in_bounds(cell) {
+            self.heights[(z * self.size + x) as usize] = y;
+            // The air between the old top and the new voxel loses the direct sky
+            for covered in height + 1..y {
+                sky_removed.push(([x, covered, z], MAX_LIGHT));
+                self.mark_changed([x, covered, z]);
+            }
+        }
+        self.remove_light(chunks, Channel::Sky, sky_removed);
+
+        let block = self.get(Channel::Block, cell);
+        if block > 0 {
+            self.set(Channel::Block, cell, 0);
+            self.remove_light(chunks, Channel::Block, vec![(cell, block)]);
+        }
+        if emission > 0 {
+            self.set(Channel::Block, cell, emission);
+            self.propagate(chunks, Channel::Block, VecDeque::from([cell]));
+        }
+    }
+
+    // Updates the light after a voxel was removed from `chunks`
+    pub fn remove_voxel<T : PartialEq + Clone>(&mut self, chunks : &ChunkMap<T>, cell : [i32; 3]) {
+        let [x, y, z] = cell;
+        self.mark_changed(cell);
+        let emitted = self.get(Channel::Block, cell);
+        if emitted > 0 {
+            self.set(Channel::Block, cell, 0);
+            self.remove_light(chunks, Channel::Block, vec![(cell, emitted)]);
+        }
+
+        // The new air takes the light of its neighbours
+        let neighbours : VecDeque<[i32; 3]> = NEIGHBOURS.iter().map(|direction| offset(cell, *direction)).collect();
+        let mut sky_sources = neighbours.clone();
+
+        if y == self.height(x, z) {
+            let mut top = y - 1;
+            while top >= 0 && !chunks.is_cell_solid(x, top, z) {
+                top -= 1;
+            }
+            self.heights[(z * self.size + x) as usize] = top;
+            // The uncovered cells see the sky directly, their stored level is no longer used
+            for uncovered in top + 1..=y {
+                self.set(Channel::Sky, [x, uncovered, z], 0);
+                self.mark_changed([x, uncovered, z]);
+                sky_sources.push_back([x, uncovered, z]);
+            }
+        }
+
+        self.propagate(chunks, Channel::Sky, sky_sources);
+        self.propagate(chunks, Channel::Block, neighbours);
+    }
+
+    // Average sky and block light of the empty cells just outside the box side facing along
+    // `normal`, as fractions of MAX_LIGHT
+    pub fn face_light<T : PartialEq + Clone>(&self, chunks : &ChunkMap<T>, bounds : &Aabb, normal : [i32; 3]) -> [f32; 2] {
+        let min = [bounds.min.x as i32, bounds.min.y as i32, bounds.min.z as i32];
+        let max = [bounds.max.x as i32, bounds.max.y as i32, bounds.max.z as i32];
+
+        let mut from = min;
+        let mut to = max;
+        for axis in 0..3 {
+            if normal[axis] > 0 {
+                from[axis] = max[axis];
+                to[axis] = max[axis] + 1;
+            } else if normal[axis] < 0 {
+                from[axis] = min[axis] - 1;
+                to[axis] = min[axis];
+            }
+        }
+
+        let mut total = [0u32; 2];
+        let mut count = 0;
+        for z in from[2]..to[2] {
+            for y in from[1]..to[1] {
+                for x in from[0]..to[0] {
+                    if chunks.is_cell_solid(x, y, z) {
+                        continue;
+                    }
+                    total[0] += self.sky_light([x, y, z]) as u32;
+                    total[1] += self.block_light([x, y, z]) as u32;
+                    count += 1;
+                }
+            }
+        }
+        if count == 0 {
+            return [0.0; 2];
+        }
+        total.map(|level| level as f32 / (count * MAX_LIGHT as u32) as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE : u32 = 32;
+    const LAMP : u32 = 9;
+
+    fn emission(material : &u32) -> u8 {
+        if *material == LAMP { 12 } else { 0 }
+    }
+
+    // Hilly ground with a cave under an overhang
+    fn test_world() -> QuadtreeNode<u32> {
+        let mut world = QuadtreeNode::new(SIZE);
+        for z in 0..SIZE {
+            for x in 0..SIZE {
+                for y in 0..=4 + (x * 7 + z * 3) % 5 {
+                    world.insert_cell(x, y, z, 1);
+                }
+            }
+        }
+        for z in 8..20 {
+            for x in 8..20 {
+                world.insert_cell(x, 16, z, 1);
+            }
+        }
+        world
+    }
+
+    struct Scene {
+        world : QuadtreeNode<u32>,
+        chunks : ChunkMap<u32>,
+        light : LightMap,
+    }
+
+    impl Scene {
+        fn new() -> Self {
+            let world = test_world();
+            let chunks = ChunkMap::from_octree(&world);
+            let light = LightMap::new(&world, &chunks, emission);
+            Self { world, chunks, light }
+        }
+
+        fn set(&mut self, [x, y, z] : [i32; 3], material : Option<u32>) {
+            match material {
+                Some(material) => {
+                    self.world.insert_cell(x as u32, y as u32, z as u32, material);
+                    self.chunks.insert_cell(x, y, z, &material);
+                    self.light.add_voxel(&self.chunks, [x, y, z], emission(&material));
+                }
+                None => {
+                    self.world.remove_cell(x as u32, y as u32, z as u32);
+                    self.chunks.remove_cell(x, y, z);
+                    self.light.remove_voxel(&self.chunks, [x, y, z]);
+                }
+            }
+        }
+
+        // Compares the incrementally updated light of every empty cell with a full relight
+        fn assert_matches_relight(&self, step : &str) {
+            let fresh = LightMap::new(&self.world, &ChunkMap::from_octree(&self.world), emission);
+            let size = SIZE as i32;
+            for z in 0..size {
+                for y in 0..size {
+                    for x in 0..size {
+                        if self.chunks.is_cell_solid(x, y, z) {
+                            continue;
+                        }
+                        let cell = [x, y, z];
+                        assert_eq!(self.light.sky_light(cell), fresh.sky_light(cell), "sky light at {:?} after {}", cell, step);
+                        assert_eq!(self.light.block_light(cell), fresh.block_light(cell), "block light at {:?} after {}", cell, step);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn covering_and_uncovering_the_sky_matches_a_relight() {
+        let mut scene = Scene::new();
+        assert!(scene.light.sky_light([12, 12, 12]) < MAX_LIGHT);
+
+        // Close the gap between the overhang and the ground, then open the overhang
+        for y in 10..16 {
+            scene.set([20, y, 14], Some(1));
+            scene.assert_matches_relight("walling off the cave");
+        }
+        for x in 8..20 {
+            scene.set([x, 16, 12], None);
+            scene.assert_matches_relight("opening the overhang");
+        }
+        scene.set([12, 25, 12], Some(1));
+        scene.assert_matches_relight("floating a voxel above the opening");
+        scene.set([12, 25, 12], None);
+        scene.assert_matches_relight("removing the floating voxel");
+    }
+
+    #[test]
+    fn placing_and_removing_lamps_matches_a_relight() {
+        let mut scene = Scene::new();
+        scene.set([12, 12, 12], Some(LAMP));
+        scene.assert_matches_relight("placing a lamp in the cave");
+        assert_eq!(scene.light.block_light([12, 13, 12]), 11);
+
+        scene.set([15, 12, 12], Some(LAMP));
+        scene.assert_matches_relight("placing a second lamp");
+        scene.set([13, 12, 12], Some(1));
+        scene.assert_matches_relight("blocking between the lamps");
+        scene.set([12, 12, 12], None);
+        scene.assert_matches_relight("removing the first lamp");
+        scene.set([13, 12, 12], None);
+        scene.assert_matches_relight("unblocking");
+        scene.set([15, 12, 12], Some(1));
+        scene.assert_matches_relight("replacing the second lamp");
+        assert_eq!(scene.light.block_light([12, 13, 12]), 0);
+    }
+
+    #[test]
+    fn random_edits_match_a_relight() {
+        let mut scene = Scene::new();
+        let mut state = 0x2545_f491u32;
+        let mut random = |range : u32| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state % range
+        };
+        for step in 0..200 {
+            let cell = [4 + random(24) as i32, 4 + random(20) as i32, 4 + random(24) as i32];
+            let material = match random(4) {
+                0 => None,
+                1 => Some(LAMP),
+                _ => Some(1),
+            };
+            scene.set(cell, material);
+            scene.assert_matches_relight(&format!("edit {} setting {:?} to {:?}", step, cell, material));
+        }
+    }
+}
@@ -2,10 +2,34 @@ use cgmath::Vector3;
 use crate::engine::buffers::uniform_buffer::UniformBuffer;
 
 use super::buffers::traits::AsUniformBuffer; 
+use shadow_map::ShadowSettings;
+
+pub mod shadow_map;
+pub mod ssao;
+pub mod light_map;
+pub mod clustered_lights;
+pub mod day_cycle;
+pub mod atmosphere;
+pub mod fog;
+pub mod skybox;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShadingModel {
+    Phong,
+    // Cook-Torrance GGX using the metallic and roughness of the materials
+    Pbr,
+    // Debug view of the shading normals, normal maps included, as colours
+    Normals,
+}
 
 pub struct DirectionalLight {
     pub direction : Vector3<f32>, 
-    pub color : Vector3<f32>
+    pub color : Vector3<f32>,
+    // Light of the sky reaching every surface, shadowed or not
+    pub ambient : Vector3<f32>,
+    pub shadows : ShadowSettings,
+    // How surfaces reflect the light
+    pub shading : ShadingModel,
 }
 
 impl DirectionalLight {
@@ -13,6 +37,9 @@ impl DirectionalLight {
         DirectionalLight {
             direction: Vector3::new(0.5, -0.5, 0.5), 
             color: Vector3::new(1.0, 1.0, 0.5), 
+            ambient: Vector3::new(0.2, 0.2, 0.2),
+            shadows: ShadowSettings::new(),
+            shading: ShadingModel::Pbr,
         }
     }
 }
@@ -22,6 +49,13 @@ impl AsUniformBuffer for DirectionalLight {
         let mut light_data = Vec::<f32>::new();
         light_data.extend::<[f32; 4]>(self.direction.extend(1.0).into());
         light_data.extend::<[f32; 4]>(self.color.extend(1.0).into());
+        let shading = match self.shading {
+            ShadingModel::Phong => 0.0,
+            ShadingModel::Pbr => 1.0,
+            ShadingModel::Normals => 2.0,
+        };
+        light_data.extend([shading, 0.0, 0.0, 0.0]);
+        light_data.extend::<[f32; 4]>(self.ambient.extend(1.0).into());
         
         let buffer_size = std::mem::size_of::<f32>() * light_data.len();
         UniformBuffer::new(device, &light_data, buffer_size as u64)
@@ -0,0 +1,292 @@
+use cgmath::{InnerSpace, Matrix4, Point3, SquareMatrix, Transform, Vector3, Vector4};
+
+use crate::engine::buffers::uniform_buffer::UniformBuffer;
+use crate::engine::builders::pipeline_bind_group_builder::BindGroupBuilder;
+use crate::engine::builders::pipeline_bind_group_layout_builder::{BindGroupLayoutBuilder, EntryVisibility, LayoutEntryType};
+use crate::engine::builders::pipeline_builder::PipelineBuilder;
+use crate::engine::builders::pipeline_layout_builder::PipelineLayoutBuilder;
+use crate::engine::builders::texture_builder;
+use crate::engine::camera::camera_view::{CameraView, FAR_PLANE, NEAR_PLANE};
+use crate::engine::camera::OPENGL_TO_WGPU_MATRIX;
+use crate::engine::buffers::uniform_buffer::SetUniformBuffer;
+use crate::engine::models::instance::{VertexData, VertexType};
+use crate::engine::models::instance::instance_data::InstanceData;
+use crate::engine::models::instance::voxel_vertex::VoxelVertex;
+use crate::engine::models::rendering::DrawModel;
+use crate::engine::models::voxel_face_model::VoxelFaceModel;
+
+use super::DirectionalLight;
+
+pub const SHADOW_MAP_RESOLUTIONS : [u32; 4] = [512, 1024, 2048, 4096];
+pub const MAX_CASCADES : usize = 4;
+
+// Extra depth kept towards the light, so that terrain outside the frustum still casts shadows
+const CASTER_MARGIN : f32 = 200.0;
+
+// Light view projection of one cascade, used by the depth pass
+type CascadeData = [[f32; 4]; 4];
+const CASCADE_DATA_SIZE : u64 = std::mem::size_of::<CascadeData>() as u64;
+
+// Light view projection of every cascade, then the far distance and bias of each cascade,
+// then (cascade count, texel size, enabled, debug colours) and (blend width, unused...)
+type ShadowData = [[f32; 4]; 4 * MAX_CASCADES + 4];
+const SHADOW_DATA_SIZE : u64 = std::mem::size_of::<ShadowData>() as u64;
+
+#[derive(Clone, Copy)]
+pub struct ShadowSettings {
+    pub enabled : bool,
+    pub resolution : u32,
+    // Depth offset in world units applied before comparing against the shadow map
+    pub bias : f32,
+    pub cascade_count : u32,
+    // View distance covered by the cascades
+    pub distance : f32,
+    // Blend between uniform (0) and logarithmic (1) cascade splits
+    pub split_lambda : f32,
+    // Fraction of each cascade over which it fades into the next one
+    pub blend_width : f32,
+    // Tints every cascade with its own colour
+    pub debug_cascades : bool,
+}
+
+impl ShadowSettings {
+    pub fn new() -> Self {
+        Self {
+            enabled : true,
+            resolution : 2048,
+            bias : 0.3,
+            cascade_count : 4,
+            distance : 700.0,
+            split_lambda : 0.75,
+            blend_width : 0.1,
+            debug_cascades : false,
+        }
+    }
+
+    // View distances at which each cascade ends
+    pub fn cascade_splits(&self) -> Vec<f32> {
+        let far = self.distance.min(FAR_PLANE);
+        let count = self.cascade_count.clamp(1, MAX_CASCADES as u32);
+        (1..=count)
+            .map(|i| {
+                let fraction = i as f32 / count as f32;
+                let logarithmic = NEAR_PLANE * (far / NEAR_PLANE).powf(fraction);
+                let uniform = NEAR_PLANE + (far - NEAR_PLANE) * fraction;
+                self.split_lambda * logarithmic + (1.0 - self.split_lambda) * uniform
+            })
+            .collect()
+    }
+}
+
+fn create_depth_texture(device : &wgpu::Device, queue : &wgpu::Queue, resolution : u32) -> wgpu::Texture {
+    // Always allocated with every cascade, the GL backend cannot sample single layer arrays
+    texture_builder::TextureBuilder::new("", texture_builder::TextureType::TextureDepth)
+        .set_dimensions(2)
+        .set_extent(resolution, resolution, MAX_CASCADES as u32)
+        .set_format(wgpu::TextureFormat::Depth32Float)
+        .set_usage(wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING)
+        .build_texture(device, queue)
+}
+
+fn create_layer_views(depth_texture : &wgpu::Texture) -> Vec<wgpu::TextureView> {
+    (0..MAX_CASCADES as u32)
+        .map(|layer| depth_texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Shadow Cascade"),
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_array_layer: layer,
+            array_layer_count: std::num::NonZeroU32::new(1),
+            ..Default::default()
+        }))
+        .collect()
+}
+
+// Orthographic projection looking along `direction` that covers the slice of the camera frustum
+// between the view distances `near` and `far`. The box is sized from the bounding sphere of the
+// slice and snapped to whole texels, so that it does not change size or swim when the camera
+// turns or moves. Also returns the depth range covered by the projection.
+fn fit_light_projection(camera : &CameraView, direction : Vector3<f32>, near : f32, far : f32, resolution : u32) -> (Matrix4<f32>, f32) {
+    let inverse = camera.get_view_projection_matrix().invert().expect("Camera matrix is not invertible");
+    let unproject = |x : f32, y : f32, z : f32| {
+        let point = inverse * Vector4::new(x, y, z, 1.0);
+        point.truncate() / point.w
+    };
+
+    // Points along a corner ray move linearly with the view distance
+    let (from, to) = ((near - NEAR_PLANE) / (FAR_PLANE - NEAR_PLANE), (far - NEAR_PLANE) / (FAR_PLANE - NEAR_PLANE));
+    let mut corners = Vec::with_capacity(8);
+    for (x, y) in [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)] {
+        let near_corner = unproject(x, y, 0.0);
+        let ray = unproject(x, y, 1.0) - near_corner;
+        corners.push(near_corner + ray * from);
+        corners.push(near_corner + ray * to);
+    }
+
+    let center = corners.iter().fold(Vector3::new(0.0, 0.0, 0.0), |sum, corner| sum + corner) / corners.len() as f32;
+    let radius = corners.iter().map(|corner| (corner - center).magnitude()).fold(0.0, f32::max).ceil();
+
+    let direction = direction.normalize();
+    let up = if direction.y.abs() > 0.99 { Vector3::unit_z() } else { Vector3::unit_y() };
+    let light_view = Matrix4::look_to_rh(Point3::new(0.0, 0.0, 0.0), direction, up);
+
+    let texel_size = 2.0 * radius / resolution as f32;
+    let light_center = light_view.transform_point(Point3::new(center.x, center.y, center.z));
+    let x = (light_center.x / texel_size).floor() * texel_size;
+    let y = (light_center.y / texel_size).floor() * texel_size;
+
+    // The light looks down -z, casters between the light and the slice have a larger z
+    let near = -(light_center.z + radius + CASTER_MARGIN);
+    let far = -(light_center.z - radius);
+    let projection = cgmath::ortho(x - radius, x + radius, y - radius, y + radius, near, far);
+
+    (OPENGL_TO_WGPU_MATRIX * projection * light_view, far - near)
+}
+
+// Cascaded depth maps of the scene seen from the directional light, each covering a further
+// slice of the view frustum, sampled with PCF in the main pass
+pub struct ShadowMap {
+    resolution : u32,
+    cascade_count : usize,
+    depth_texture : wgpu::Texture,
+    cascade_views : Vec<wgpu::TextureView>,
+    sampler : wgpu::Sampler,
+    pipeline : wgpu::RenderPipeline,
+    // Light matrix of each cascade used by the depth pass
+    cascade_uniforms : Vec<UniformBuffer>,
+    // Cascade matrices and splits, depth maps and sampler used by the main pass
+    pub bind_group_layout : wgpu::BindGroupLayout,
+    pub bind_group : wgpu::BindGroup,
+}
+
+impl ShadowMap {
+    pub fn new(device : &wgpu::Device, queue : &wgpu::Queue, settings : &ShadowSettings) -> Self {
+        let depth_texture = create_depth_texture(device, queue, settings.resolution);
+        let cascade_views = create_layer_views(&depth_texture);
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let bind_group_layout = BindGroupLayoutBuilder::new()
+            .add_entry(LayoutEntryType::UniformBuffer, EntryVisibility::Fragment, SHADOW_DATA_SIZE)
+            .add_entry(LayoutEntryType::DepthTextureArray, EntryVisibility::Fragment, 0)
+            .add_entry(LayoutEntryType::ComparisonSampler, EntryVisibility::Fragment, 0)
+            .build(device);
+
+        let identity : CascadeData = Matrix4::identity().into();
+        let cascade_uniforms : Vec<UniformBuffer> = (0..MAX_CASCADES)
+            .map(|_| UniformBuffer::new(device, &identity, CASCADE_DATA_SIZE))
+            .collect();
+
+        let pipeline_layout = PipelineLayoutBuilder::new()
+            .add_bind_group_layout(&cascade_uniforms[0].bind_group_layout)
+            .build(device);
+
+        let pipeline = PipelineBuilder::new()
+            .add_vertex_buffer_layout(VoxelVertex::desc())
+            .add_vertex_buffer_layout(InstanceData::desc())
+            .set_primitive_state(None)
+            .set_vertex_shader(device, "./shaders/shadow.wgsl", VertexType::InstancedVertex)
+            .set_pipeline_layout(pipeline_layout)
+            .build(device);
+
+        // Disabled until the first update fits the cascades to a camera
+        let shadow_uniform = UniformBuffer::new(device, &[[0.0f32; 4]; 4 * MAX_CASCADES + 4], SHADOW_DATA_SIZE);
+        let bind_group = Self::create_bind_group(device, &bind_group_layout, &shadow_uniform, &depth_texture, &sampler);
+
+        Self {
+            resolution : settings.resolution,
+            cascade_count : 0,
+            depth_texture,
+            cascade_views,
+            sampler,
+            pipeline,
+            cascade_uniforms,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    fn create_bind_group(
+        device : &wgpu::Device,
+        layout : &wgpu::BindGroupLayout,
+        shadow_uniform : &UniformBuffer,
+        depth_texture : &wgpu::Texture,
+        sampler : &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        let cascades = depth_texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        BindGroupBuilder::new()
+            .add_uniform_buffer_entry(&shadow_uniform.buffers[0].0, SHADOW_DATA_SIZE)
+            .add_texture_entry(&cascades)
+            .add_sampler_entry(sampler)
+            .build(device, layout)
+    }
+
+    pub fn update(&mut self, device : &wgpu::Device, queue : &wgpu::Queue, camera : &CameraView, light : &DirectionalLight) {
+        let settings = &light.shadows;
+        if settings.resolution != self.resolution {
+            self.resolution = settings.resolution;
+            self.depth_texture = create_depth_texture(device, queue, settings.resolution);
+            self.cascade_views = create_layer_views(&self.depth_texture);
+        }
+
+        let splits = settings.cascade_splits();
+        self.cascade_count = splits.len();
+
+        let mut shadow_data : ShadowData = [[0.0; 4]; 4 * MAX_CASCADES + 4];
+        let mut near = NEAR_PLANE;
+        for (cascade, far) in splits.iter().enumerate() {
+            let (view_projection, depth_range) = fit_light_projection(camera, light.direction, near, *far, self.resolution);
+            let matrix : CascadeData = view_projection.into();
+
+            shadow_data[cascade * 4..cascade * 4 + 4].copy_from_slice(&matrix);
+            shadow_data[4 * MAX_CASCADES][cascade] = *far;
+            shadow_data[4 * MAX_CASCADES + 1][cascade] = settings.bias / depth_range;
+            self.cascade_uniforms[cascade] = UniformBuffer::new(device, &matrix, CASCADE_DATA_SIZE);
+            near = *far;
+        }
+        shadow_data[4 * MAX_CASCADES + 2] = [
+            splits.len() as f32,
+            1.0 / self.resolution as f32,
+            if settings.enabled { 1.0 } else { 0.0 },
+            if settings.debug_cascades { 1.0 } else { 0.0 },
+        ];
+        shadow_data[4 * MAX_CASCADES + 3] = [settings.blend_width, 0.0, 0.0, 0.0];
+
+        let shadow_uniform = UniformBuffer::new(device, &shadow_data, SHADOW_DATA_SIZE);
+        self.bind_group = Self::create_bind_group(device, &self.bind_group_layout, &shadow_uniform, &self.depth_texture, &self.sampler);
+    }
+
+    pub fn render(&self, encoder : &mut wgpu::CommandEncoder, models : &[VoxelFaceModel]) {
+        for cascade in 0..self.cascade_count {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.cascade_views[cascade],
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_uniform_buffer(0, &self.cascade_uniforms[cascade]);
+            for model in models.iter() {
+                rpass.draw_voxel_instanced(1, model);
+            }
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+use crate::engine::buffers::{self, BufferType};
+use crate::engine::builders::pipeline_bind_group_builder::BindGroupBuilder;
+use crate::engine::builders::pipeline_bind_group_layout_builder::{BindGroupLayoutBuilder, EntryVisibility, LayoutEntryType};
+use crate::engine::builders::pipeline_builder::PipelineBuilder;
+use crate::engine::builders::pipeline_layout_builder::PipelineLayoutBuilder;
+use crate::engine::builders::texture_builder::{self, TextureBuilder};
+use crate::engine::camera::camera_view::CameraView;
+use crate::engine::models::instance::VertexType;
+
+// File names of the faces of a cubemap directory, in the layer order of the cubemap
+const FACE_NAMES : [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+const FACE_EXTENSIONS : [&str; 3] = ["png", "jpg", "jpeg"];
+
+// Inverse view projection and (intensity)
+type SkyboxData = [[f32; 4]; 5];
+const SKYBOX_DATA_SIZE : u64 = std::mem::size_of::<SkyboxData>() as u64;
+
+// Finds the six faces of a cubemap directory, px.png, nx.png and so on
+fn find_faces(directory : &Path) -> Result<[String; 6], String> {
+    let mut faces = FACE_NAMES.map(|_| String::new());
+    for (face, name) in faces.iter_mut().zip(FACE_NAMES) {
+        *face = FACE_EXTENSIONS.iter()
+            .map(|extension| directory.join(format!("{}.{}", name, extension)))
+            .find(|path| path.is_file())
+            .ok_or_else(|| format!("no {} face in {:?}", name, directory))?
+            .to_string_lossy()
+            .into_owned();
+    }
+    Ok(faces)
+}
+
+// Loads a directory of six faces or a single equirectangular image, ready to be uploaded
+fn load_cubemap(path : &Path) -> Result<TextureBuilder, String> {
+    let builder = if path.is_dir() {
+        let faces = find_faces(path)?;
+        TextureBuilder::new_cubemap(&faces.each_ref().map(|face| face.as_str()))?
+    } else {
+        TextureBuilder::new_cubemap_from_equirectangular(&path.to_string_lossy())?
+    };
+    Ok(builder.generate_mipmaps().encode())
+}
+
+// Decoding and projecting a large panorama takes seconds, so cubemaps are loaded off the render thread
+fn load_in_background(path : PathBuf) -> (PathBuf, Receiver<Result<TextureBuilder, String>>) {
+    let (sender, receiver) = mpsc::channel();
+    let thread_path = path.clone();
+    std::thread::spawn(move || {
+        // The skybox may have moved on to another texture and dropped the receiver
+        let _ = sender.send(load_cubemap(&thread_path));
+    });
+    (path, receiver)
+}
+
+#[derive(Clone)]
+pub struct SkyboxSettings {
+    // Directory of six faces or equirectangular image drawn instead of the atmosphere, if any
+    pub texture : Option<PathBuf>,
+    // Scale of the texture colours, HDR panoramas often need less
+    pub intensity : f32,
+}
+
+impl SkyboxSettings {
+    pub fn new() -> Self {
+        Self {
+            texture : None,
+            intensity : 1.0,
+        }
+    }
+}
+
+// Cubemap drawn behind the terrain in place of the atmosphere. The texture is loaded in the
+// background whenever the one in the settings changes, the previous one staying in place until
+// it is ready, and dropped when it cannot be read.
+pub struct Skybox {
+    pub settings : SkyboxSettings,
+    loaded_texture : Option<PathBuf>,
+    // Texture being loaded and where it will arrive
+    loading : Option<(PathBuf, Receiver<Result<TextureBuilder, String>>)>,
+    skybox_buffer : wgpu::Buffer,
+    skybox_bind_group : wgpu::BindGroup,
+    texture_layout : wgpu::BindGroupLayout,
+    texture_bind_group : Option<wgpu::BindGroup>,
+    sampler : wgpu::Sampler,
+    // Draws in the main pass, so it follows the format and samples of its targets
+    pipeline : wgpu::RenderPipeline,
+    skybox_layout : wgpu::BindGroupLayout,
+    format : wgpu::TextureFormat,
+}
+
+impl Skybox {
+    pub fn new(device : &wgpu::Device, format : &wgpu::TextureFormat) -> Self {
+        let skybox_buffer = buffers::create_buffer(device, BufferType::Uniform, &[[0.0f32; 4]; 5]);
+        let skybox_layout = BindGroupLayoutBuilder::new()
+            .add_entry(LayoutEntryType::UniformBuffer, EntryVisibility::Fragment, SKYBOX_DATA_SIZE)
+            .build(device);
+        let skybox_bind_group = BindGroupBuilder::new()
+            .add_uniform_buffer_entry(&skybox_buffer, SKYBOX_DATA_SIZE)
+            .build(device, &skybox_layout);
+        let texture_layout = BindGroupLayoutBuilder::new()
+            .add_entry(LayoutEntryType::FloatTextureCube, EntryVisibility::Fragment, 0)
+            .add_entry(LayoutEntryType::FilteringSampler, EntryVisibility::Fragment, 0)
+            .build(device);
+
+        Self {
+            settings : SkyboxSettings::new(),
+            loaded_texture : None,
+            loading : None,
+            skybox_buffer,
+            skybox_bind_group,
+            texture_bind_group : None,
+            sampler : texture_builder::create_cube_sampler(device),
+            pipeline : Self::create_pipeline(device, &skybox_layout, &texture_layout, format, 1),
+            texture_layout,
+            skybox_layout,
+            format : *format,
+        }
+    }
+
+    fn create_pipeline(
+        device : &wgpu::Device,
+        skybox_layout : &wgpu::BindGroupLayout,
+        texture_layout : &wgpu::BindGroupLayout,
+        format : &wgpu::TextureFormat,
+        sample_count : u32,
+    ) -> wgpu::RenderPipeline {
+        let pipeline_layout = PipelineLayoutBuilder::new()
+            .add_bind_group_layout(skybox_layout)
+            .add_bind_group_layout(texture_layout)
+            .build(device);
+        PipelineBuilder::new()
+            .set_primitive_state(None)
+            .set_sample_count(sample_count)
+            .set_depth_compare(wgpu::CompareFunction::LessEqual)
+            .set_vertex_shader(device, "./shaders/skybox.wgsl", VertexType::Fullscreen)
+            .set_fragment_shader(device, "./shaders/skybox.wgsl", format)
+            .set_pipeline_layout(pipeline_layout)
+            .build(device)
+    }
+
+    // Rebuilds the pipeline for main pass targets with a different number of samples per pixel
+    pub fn set_sample_count(&mut self, device : &wgpu::Device, sample_count : u32) {
+        self.pipeline = Self::create_pipeline(device, &self.skybox_layout, &self.texture_layout, &self.format, sample_count);
+    }
+
+    // Whether a texture is loaded and drawn instead of the atmosphere
+    pub fn is_active(&self) -> bool {
+        self.texture_bind_group.is_some()
+    }
+
+    // Starts loading the texture of the settings, or drops the current one when there is none
+    fn update_texture(&mut self) {
+        self.loaded_texture = self.settings.texture.clone();
+        self.loading = self.loaded_texture.clone().map(load_in_background);
+        if self.loaded_texture.is_none() {
+            self.texture_bind_group = None;
+        }
+    }
+
+    // Uploads the texture once it has been loaded
+    fn receive_texture(&mut self, device : &wgpu::Device, queue : &wgpu::Queue) {
+        let (path, result) = match &self.loading {
+            Some((path, receiver)) => match receiver.try_recv() {
+                Ok(result) => (path.clone(), result),
+                Err(TryRecvError::Disconnected) => (path.clone(), Err("the loader stopped".to_string())),
+                Err(TryRecvError::Empty) => return,
+            },
+            None => return,
+        };
+        self.loading = None;
+        self.texture_bind_group = match result {
+            Ok(builder) => Some(BindGroupBuilder::new()
+                .add_texture_entry(&builder.build(device, queue))
+                .add_sampler_entry(&self.sampler)
+                .build(device, &self.texture_layout)),
+            Err(e) => {
+                eprintln!("failed to load skybox {:?}: {}", path, e);
+                None
+            }
+        };
+    }
+
+    pub fn update(&mut self, device : &wgpu::Device, queue : &wgpu::Queue, camera : &CameraView) {
+        if self.settings.texture != self.loaded_texture {
+            self.update_texture();
+        }
+        self.receive_texture(device, queue);
+
+        let inverse_view_projection : [[f32; 4]; 4] = camera.get_inverse_sky_matrix().into();
+        let mut skybox_data : SkyboxData = [[0.0; 4]; 5];
+        skybox_data[0..4].copy_from_slice(&inverse_view_projection);
+        skybox_data[4] = [self.settings.intensity, 0.0, 0.0, 0.0];
+        queue.write_buffer(&self.skybox_buffer, 0, bytemuck::cast_slice(&skybox_data));
+    }
+
+    // Draws the cubemap wherever the main pass left the depth on the far plane
+    pub fn render<'a>(&'a self, rpass : &mut wgpu::RenderPass<'a>) {
+        if let Some(texture_bind_group) = &self.texture_bind_group {
+            rpass.set_pipeline(&self.pipeline);
+            rpass.set_bind_group(0, &self.skybox_bind_group, &[]);
+            rpass.set_bind_group(1, texture_bind_group, &[]);
+            rpass.draw(0..3, 0..1);
+        }
+    }
+}
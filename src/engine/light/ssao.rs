@@ -0,0 +1,354 @@
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3};
+
+use crate::engine::buffers::uniform_buffer::{SetUniformBuffer, UniformBuffer};
+use crate::engine::builders::pipeline_bind_group_builder::BindGroupBuilder;
+use crate::engine::builders::pipeline_bind_group_layout_builder::{BindGroupLayoutBuilder, EntryVisibility, LayoutEntryType};
+use crate::engine::builders::pipeline_builder::PipelineBuilder;
+use crate::engine::builders::pipeline_layout_builder::PipelineLayoutBuilder;
+use crate::engine::builders::texture_builder;
+use crate::engine::camera::camera_view::CameraView;
+use crate::engine::camera::OPENGL_TO_WGPU_MATRIX;
+use crate::engine::models::instance::{VertexData, VertexType};
+use crate::engine::models::instance::instance_data::InstanceData;
+use crate::engine::models::instance::voxel_vertex::VoxelVertex;
+use crate::engine::models::rendering::DrawModel;
+use crate::engine::models::voxel_face_model::VoxelFaceModel;
+
+pub const MAX_SSAO_SAMPLES : usize = 64;
+const NOISE_SIZE : usize = 16;
+const OCCLUSION_FORMAT : wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+// Linear view space depth written by the prepass
+const DEPTH_FORMAT : wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+// Relative depth difference at which the blur stops mixing neighbours
+const BLUR_DEPTH_THRESHOLD : f32 = 0.05;
+
+// Projection, inverse projection, kernel, noise and (radius, strength, sample count, bias)
+type SsaoData = [[f32; 4]; 8 + MAX_SSAO_SAMPLES + NOISE_SIZE + 1];
+const SSAO_DATA_SIZE : u64 = std::mem::size_of::<SsaoData>() as u64;
+const CAMERA_DATA_SIZE : u64 = std::mem::size_of::<[[f32; 4]; 4]>() as u64;
+const BLUR_DATA_SIZE : u64 = std::mem::size_of::<[f32; 4]>() as u64;
+
+#[derive(Clone, Copy)]
+pub struct SsaoSettings {
+    pub enabled : bool,
+    // View space radius of the sampled hemisphere
+    pub radius : f32,
+    // Scale of the occlusion, 1 darkens fully occluded points to black
+    pub strength : f32,
+    pub sample_count : u32,
+    // View space distance a surface has to be in front of a sample to occlude it
+    pub bias : f32,
+}
+
+impl SsaoSettings {
+    pub fn new() -> Self {
+        Self {
+            enabled : true,
+            radius : 1.5,
+            strength : 1.0,
+            sample_count : 16,
+            bias : 0.025,
+        }
+    }
+}
+
+// Small xorshift generator, the kernel and noise only need to be fixed and well spread
+struct Random(u32);
+
+impl Random {
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+// Points in the z-up unit hemisphere, denser close to the origin
+fn hemisphere_kernel(random : &mut Random) -> Vec<[f32; 4]> {
+    (0..MAX_SSAO_SAMPLES)
+        .map(|i| {
+            let direction = Vector3::new(random.next() * 2.0 - 1.0, random.next() * 2.0 - 1.0, random.next()).normalize();
+            let t = i as f32 / MAX_SSAO_SAMPLES as f32;
+            let scale = 0.1 + 0.9 * t * t;
+            (direction * random.next() * scale).extend(0.0).into()
+        })
+        .collect()
+}
+
+// Random rotations around the z axis, tiled over the screen
+fn rotation_noise(random : &mut Random) -> Vec<[f32; 4]> {
+    (0..NOISE_SIZE)
+        .map(|_| [random.next() * 2.0 - 1.0, random.next() * 2.0 - 1.0, 0.0, 0.0])
+        .collect()
+}
+
+struct SsaoTargets {
+    size : (u32, u32),
+    depth_buffer : wgpu::TextureView,
+    depth : wgpu::TextureView,
+    occlusion : wgpu::TextureView,
+    blurred : wgpu::TextureView,
+}
+
+fn create_target(device : &wgpu::Device, (width, height) : (u32, u32), format : wgpu::TextureFormat) -> wgpu::TextureView {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("SSAO Target"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+    }).create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+impl SsaoTargets {
+    fn new(device : &wgpu::Device, queue : &wgpu::Queue, size : (u32, u32)) -> Self {
+        // The scene depth may be multisampled, the prepass renders its own single sampled copy
+        let depth_buffer = texture_builder::TextureBuilder::new("", texture_builder::TextureType::TextureDepth)
+            .set_dimensions(2)
+            .set_extent(size.0, size.1, 1)
+            .set_format(wgpu::TextureFormat::Depth32Float)
+            .set_usage(wgpu::TextureUsages::RENDER_ATTACHMENT)
+            .build(device, queue);
+
+        Self {
+            size,
+            depth_buffer,
+            depth : create_target(device, size, DEPTH_FORMAT),
+            occlusion : create_target(device, size, OCCLUSION_FORMAT),
+            blurred : create_target(device, size, OCCLUSION_FORMAT),
+        }
+    }
+}
+
+fn fullscreen_pipeline(device : &wgpu::Device, shader_path : &str, layouts : &[&wgpu::BindGroupLayout]) -> wgpu::RenderPipeline {
+    let mut pipeline_layout = PipelineLayoutBuilder::new();
+    for layout in layouts {
+        pipeline_layout = pipeline_layout.add_bind_group_layout(layout);
+    }
+
+    PipelineBuilder::new()
+        .set_primitive_state(None)
+        .set_depth_test(false)
+        .set_vertex_shader(device, shader_path, VertexType::Fullscreen)
+        .set_fragment_shader(device, shader_path, &OCCLUSION_FORMAT)
+        .set_pipeline_layout(pipeline_layout.build(device))
+        .build(device)
+}
+
+fn begin_occlusion_pass<'a>(encoder : &'a mut wgpu::CommandEncoder, target : &'a wgpu::TextureView) -> wgpu::RenderPass<'a> {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("SSAO Pass"),
+        color_attachments: &[
+            Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                    store: true,
+                }
+            })
+        ],
+        depth_stencil_attachment: None,
+    })
+}
+
+// Screen-space ambient occlusion of the camera view: a depth prepass of the voxel faces, an
+// occlusion pass over it and a depth aware blur. The main pass scales its ambient light by the result,
+// so the depth cannot come from the main pass itself, whose depth buffer is also not bindable with MSAA.
+pub struct Ssao {
+    kernel : Vec<[f32; 4]>,
+    noise : Vec<[f32; 4]>,
+    targets : SsaoTargets,
+    prepass_pipeline : wgpu::RenderPipeline,
+    occlusion_pipeline : wgpu::RenderPipeline,
+    blur_pipeline : wgpu::RenderPipeline,
+    depth_layout : wgpu::BindGroupLayout,
+    blur_layout : wgpu::BindGroupLayout,
+    // Inputs of the passes, recreated on every update
+    camera_uniform : UniformBuffer,
+    ssao_uniform : UniformBuffer,
+    blur_uniform : UniformBuffer,
+    depth_bind_group : wgpu::BindGroup,
+    blur_bind_group : wgpu::BindGroup,
+    // Blurred occlusion read by the main pass
+    pub bind_group_layout : wgpu::BindGroupLayout,
+    pub bind_group : wgpu::BindGroup,
+    pub settings : SsaoSettings,
+}
+
+impl Ssao {
+    pub fn new(device : &wgpu::Device, queue : &wgpu::Queue) -> Self {
+        let mut random = Random(0x9e37_79b9);
+        let kernel = hemisphere_kernel(&mut random);
+        let noise = rotation_noise(&mut random);
+
+        let identity : [[f32; 4]; 4] = Matrix4::identity().into();
+        let camera_uniform = UniformBuffer::new(device, &identity, CAMERA_DATA_SIZE);
+        let prepass_layout = PipelineLayoutBuilder::new()
+            .add_bind_group_layout(&camera_uniform.bind_group_layout)
+            .build(device);
+        let prepass_pipeline = PipelineBuilder::new()
+            .add_vertex_buffer_layout(VoxelVertex::desc())
+            .add_vertex_buffer_layout(InstanceData::desc())
+            .set_primitive_state(Some(wgpu::Face::Back))
+            .set_vertex_shader(device, "./shaders/ssao_depth.wgsl", VertexType::InstancedVertex)
+            .set_fragment_shader(device, "./shaders/ssao_depth.wgsl", &DEPTH_FORMAT)
+            .set_pipeline_layout(prepass_layout)
+            .build(device);
+
+        let depth_layout = BindGroupLayoutBuilder::new()
+            .add_entry(LayoutEntryType::UnfilterableFloatTexture2D, EntryVisibility::Fragment, 0)
+            .build(device);
+        let ssao_uniform = UniformBuffer::new(device, &[[0.0f32; 4]; 8 + MAX_SSAO_SAMPLES + NOISE_SIZE + 1], SSAO_DATA_SIZE);
+        let occlusion_pipeline = fullscreen_pipeline(device, "./shaders/ssao.wgsl", &[&depth_layout, &ssao_uniform.bind_group_layout]);
+
+        let blur_layout = BindGroupLayoutBuilder::new()
+            .add_entry(LayoutEntryType::FloatTexture2D, EntryVisibility::Fragment, 0)
+            .add_entry(LayoutEntryType::UnfilterableFloatTexture2D, EntryVisibility::Fragment, 0)
+            .build(device);
+        let blur_uniform = UniformBuffer::new(device, &[BLUR_DEPTH_THRESHOLD, 0.0, 0.0, 0.0], BLUR_DATA_SIZE);
+        let blur_pipeline = fullscreen_pipeline(device, "./shaders/ssao_blur.wgsl", &[&blur_layout, &blur_uniform.bind_group_layout]);
+
+        let bind_group_layout = BindGroupLayoutBuilder::new()
+            .add_entry(LayoutEntryType::FloatTexture2D, EntryVisibility::Fragment, 0)
+            .build(device);
+
+        // Resized to the frame on the first update
+        let targets = SsaoTargets::new(device, queue, (1, 1));
+        let (depth_bind_group, blur_bind_group, bind_group) = Self::create_bind_groups(device, &targets, &depth_layout, &blur_layout, &bind_group_layout);
+
+        Self {
+            kernel,
+            noise,
+            targets,
+            prepass_pipeline,
+            occlusion_pipeline,
+            blur_pipeline,
+            depth_layout,
+            blur_layout,
+            camera_uniform,
+            ssao_uniform,
+            blur_uniform,
+            depth_bind_group,
+            blur_bind_group,
+            bind_group_layout,
+            bind_group,
+            settings : SsaoSettings::new(),
+        }
+    }
+
+    fn create_bind_groups(
+        device : &wgpu::Device,
+        targets : &SsaoTargets,
+        depth_layout : &wgpu::BindGroupLayout,
+        blur_layout : &wgpu::BindGroupLayout,
+        occlusion_layout : &wgpu::BindGroupLayout,
+    ) -> (wgpu::BindGroup, wgpu::BindGroup, wgpu::BindGroup) {
+        let depth_bind_group = BindGroupBuilder::new()
+            .add_texture_entry(&targets.depth)
+            .build(device, depth_layout);
+        let blur_bind_group = BindGroupBuilder::new()
+            .add_texture_entry(&targets.occlusion)
+            .add_texture_entry(&targets.depth)
+            .build(device, blur_layout);
+        let occlusion_bind_group = BindGroupBuilder::new()
+            .add_texture_entry(&targets.blurred)
+            .build(device, occlusion_layout);
+        (depth_bind_group, blur_bind_group, occlusion_bind_group)
+    }
+
+    fn ssao_data(&self, camera : &CameraView) -> SsaoData {
+        let projection = OPENGL_TO_WGPU_MATRIX * camera.projection;
+        let inverse_projection = projection.invert().expect("Projection matrix is not invertible");
+
+        let mut data : SsaoData = [[0.0; 4]; 8 + MAX_SSAO_SAMPLES + NOISE_SIZE + 1];
+        let projection : [[f32; 4]; 4] = projection.into();
+        let inverse_projection : [[f32; 4]; 4] = inverse_projection.into();
+        data[0..4].copy_from_slice(&projection);
+        data[4..8].copy_from_slice(&inverse_projection);
+        data[8..8 + MAX_SSAO_SAMPLES].copy_from_slice(&self.kernel);
+        data[8 + MAX_SSAO_SAMPLES..8 + MAX_SSAO_SAMPLES + NOISE_SIZE].copy_from_slice(&self.noise);
+        data[8 + MAX_SSAO_SAMPLES + NOISE_SIZE] = [
+            self.settings.radius,
+            self.settings.strength,
+            self.settings.sample_count.clamp(1, MAX_SSAO_SAMPLES as u32) as f32,
+            self.settings.bias,
+        ];
+        data
+    }
+
+    // Prepares the passes for a frame of `size` pixels seen from the camera
+    pub fn update(&mut self, device : &wgpu::Device, queue : &wgpu::Queue, camera : &CameraView, size : (u32, u32)) {
+        if self.targets.size != size {
+            self.targets = SsaoTargets::new(device, queue, size);
+            let (depth_bind_group, blur_bind_group, bind_group) = Self::create_bind_groups(device, &self.targets, &self.depth_layout, &self.blur_layout, &self.bind_group_layout);
+            self.depth_bind_group = depth_bind_group;
+            self.blur_bind_group = blur_bind_group;
+            self.bind_group = bind_group;
+        }
+
+        let view_projection : [[f32; 4]; 4] = camera.get_view_projection_matrix().into();
+        self.camera_uniform = UniformBuffer::new(device, &view_projection, CAMERA_DATA_SIZE);
+        self.ssao_uniform = UniformBuffer::new(device, &self.ssao_data(camera), SSAO_DATA_SIZE);
+    }
+
+    // Renders the occlusion, fully visible when disabled
+    pub fn render(&self, encoder : &mut wgpu::CommandEncoder, models : &[&VoxelFaceModel]) {
+        if !self.settings.enabled {
+            begin_occlusion_pass(encoder, &self.targets.blurred);
+            return;
+        }
+
+        {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Depth Prepass"),
+                // Pixels without a surface keep a depth of 0
+                color_attachments: &[
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: &self.targets.depth,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: true,
+                        }
+                    })
+                ],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.targets.depth_buffer,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        // Only the linear depth is read afterwards
+                        store: false,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            rpass.set_pipeline(&self.prepass_pipeline);
+            rpass.set_uniform_buffer(0, &self.camera_uniform);
+            for model in models.iter() {
+                rpass.draw_voxel_instanced(1, model);
+            }
+        }
+
+        {
+            let mut rpass = begin_occlusion_pass(encoder, &self.targets.occlusion);
+            rpass.set_pipeline(&self.occlusion_pipeline);
+            rpass.set_bind_group(0, &self.depth_bind_group, &[]);
+            rpass.set_uniform_buffer(1, &self.ssao_uniform);
+            rpass.draw(0..3, 0..1);
+        }
+
+        let mut rpass = begin_occlusion_pass(encoder, &self.targets.blurred);
+        rpass.set_pipeline(&self.blur_pipeline);
+        rpass.set_bind_group(0, &self.blur_bind_group, &[]);
+        rpass.set_uniform_buffer(1, &self.blur_uniform);
+        rpass.draw(0..3, 0..1);
+    }
+}
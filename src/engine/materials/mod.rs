@@ -3,6 +3,14 @@ use bytemuck::{Pod, Zeroable};
 
 use super::buffers::traits::AsStorageBuffer;
 use super::buffers::storage_buffer::StorageBuffer; 
+use super::light::light_map::MAX_LIGHT;
+
+pub mod voxel_textures;
+
+use voxel_textures::{DIRT, GRASS_SIDE, GRASS_TOP, STONE};
+
+// Texture layers of untextured materials
+const NO_TEXTURES : [f32; 3] = [-1.0; 3];
 
 #[repr(C)]
 #[derive(Clone, Copy, Pod, Zeroable, Debug)]
@@ -13,9 +21,17 @@ pub struct VoxelMaterial {
     pub metallic: f32,             // PBR metallic
     pub roughness: f32,            // PBR roughness
     pub _padding: [f32; 2],        // Align to 16 bytes (wgpu requirement)
+    pub emissive: [f32; 4],        // RGB emitted colour, intensity in w
+    pub texture_layers: [f32; 3],  // Layers of the top, side and bottom faces, -1 for none
+    pub normal_strength: f32,      // Scale of the normal map tilt, 0 for the flat face normal
 }
 
 impl VoxelMaterial {
+    // Block light emitted into the voxel light map, an emissive intensity of 3 reaches the maximum
+    pub fn light_level(&self) -> u8 {
+        (self.emissive[3] * MAX_LIGHT as f32 / 3.0).round().clamp(0.0, MAX_LIGHT as f32) as u8
+    }
+
     pub const fn green() -> VoxelMaterial {
         VoxelMaterial { 
             diffuse_color: [0.1, 0.8, 0.1, 1.0], // Green
@@ -24,6 +40,9 @@ impl VoxelMaterial {
             metallic: 0.0,
             roughness: 1.0,
             _padding: [1.0, 1.0], 
+            emissive: [0.0, 0.0, 0.0, 0.0],
+            texture_layers: NO_TEXTURES,
+            normal_strength: 0.0,
         }
     }
 
@@ -35,6 +54,9 @@ impl VoxelMaterial {
             metallic: 0.0,
             roughness: 1.0,
             _padding: [1.0, 1.0], 
+            emissive: [0.0, 0.0, 0.0, 0.0],
+            texture_layers: NO_TEXTURES,
+            normal_strength: 0.0,
         }
     }
 
@@ -46,6 +68,9 @@ impl VoxelMaterial {
             metallic: 0.0,
             roughness: 1.0,
             _padding: [1.0, 1.0], 
+            emissive: [0.0, 0.0, 0.0, 0.0],
+            texture_layers: NO_TEXTURES,
+            normal_strength: 0.0,
         }
     }
 
@@ -57,6 +82,9 @@ impl VoxelMaterial {
             metallic: 0.0,
             roughness: 1.0,
             _padding: [1.0, 1.0], 
+            emissive: [0.0, 0.0, 0.0, 0.0],
+            texture_layers: NO_TEXTURES,
+            normal_strength: 0.0,
         }
     }
 
@@ -68,6 +96,9 @@ impl VoxelMaterial {
             metallic: 0.0,
             roughness: 1.0,
             _padding: [1.0, 1.0], 
+            emissive: [0.0, 0.0, 0.0, 0.0],
+            texture_layers: NO_TEXTURES,
+            normal_strength: 0.0,
         }
     }
 
@@ -79,6 +110,9 @@ impl VoxelMaterial {
             metallic: 0.0,
             roughness: 1.0,
             _padding: [1.0, 1.0], 
+            emissive: [0.0, 0.0, 0.0, 0.0],
+            texture_layers: NO_TEXTURES,
+            normal_strength: 0.0,
         }
     }
 
@@ -90,6 +124,9 @@ impl VoxelMaterial {
             metallic: 0.0,
             roughness: 0.5,
             _padding: [1.0, 1.0], 
+            emissive: [0.0, 0.0, 0.0, 0.0],
+            texture_layers: NO_TEXTURES,
+            normal_strength: 0.0,
         }
     }
 
@@ -101,11 +138,70 @@ impl VoxelMaterial {
             metallic: 0.0,
             roughness: 1.0,
             _padding: [1.0, 1.0], 
+            emissive: [0.0, 0.0, 0.0, 0.0],
+            texture_layers: NO_TEXTURES,
+            normal_strength: 0.0,
+        }
+    }
+
+    pub const fn lava() -> VoxelMaterial {
+        VoxelMaterial { 
+            diffuse_color: [0.3, 0.05, 0.0, 1.0], // Dark red crust
+            specular_color: [0.0, 0.0, 0.0], 
+            shininess: 8.0,
+            metallic: 0.0,
+            roughness: 1.0,
+            _padding: [1.0, 1.0], 
+            emissive: [1.0, 0.15, 0.02, 2.5],
+            texture_layers: NO_TEXTURES,
+            normal_strength: 0.0,
+        }
+    }
+
+    pub const fn crystal() -> VoxelMaterial {
+        VoxelMaterial { 
+            diffuse_color: [0.4, 0.2, 0.8, 1.0], // Violet
+            specular_color: [0.8, 0.6, 1.0], 
+            shininess: 64.0,
+            metallic: 0.0,
+            roughness: 0.2,
+            _padding: [1.0, 1.0], 
+            emissive: [0.6, 0.3, 1.0, 3.0],
+            texture_layers: NO_TEXTURES,
+            normal_strength: 0.0,
+        }
+    }
+
+    pub const fn grass() -> VoxelMaterial {
+        VoxelMaterial { 
+            diffuse_color: [1.0, 1.0, 1.0, 1.0], // Textured
+            specular_color: [0.2, 0.3, 0.1], 
+            shininess: 16.0,
+            metallic: 0.0,
+            roughness: 0.9,
+            _padding: [1.0, 1.0], 
+            emissive: [0.0, 0.0, 0.0, 0.0],
+            texture_layers: [GRASS_TOP as f32, GRASS_SIDE as f32, DIRT as f32],
+            normal_strength: 0.6,
+        }
+    }
+
+    pub const fn stone() -> VoxelMaterial {
+        VoxelMaterial { 
+            diffuse_color: [1.0, 1.0, 1.0, 1.0], // Textured
+            specular_color: [0.3, 0.3, 0.3], 
+            shininess: 32.0,
+            metallic: 0.0,
+            roughness: 0.7,
+            _padding: [1.0, 1.0], 
+            emissive: [0.0, 0.0, 0.0, 0.0],
+            texture_layers: [STONE as f32; 3],
+            normal_strength: 1.0,
         }
     }
 }
 
-pub const MATERIAL_PALETTE : [VoxelMaterial; 8] = [
+pub const MATERIAL_PALETTE : [VoxelMaterial; 12] = [
     VoxelMaterial::black(), 
     VoxelMaterial::blue(), 
     VoxelMaterial::cyan(), 
@@ -113,7 +209,11 @@ pub const MATERIAL_PALETTE : [VoxelMaterial; 8] = [
     VoxelMaterial::magenta(), 
     VoxelMaterial::red(), 
     VoxelMaterial::white(), 
-    VoxelMaterial::yellow()
+    VoxelMaterial::yellow(),
+    VoxelMaterial::lava(),
+    VoxelMaterial::crystal(),
+    VoxelMaterial::grass(),
+    VoxelMaterial::stone(),
 ]; 
 
 
@@ -122,9 +222,12 @@ fn fill_material_data(material_data : &mut Vec::<f32>, vm : &VoxelMaterial) {
     material_data.extend(vm.specular_color);
     material_data.extend([vm.shininess, vm.metallic, vm.roughness]);
     material_data.extend(vm._padding);
+    material_data.extend(vm.emissive);
+    material_data.extend(vm.texture_layers);
+    material_data.push(vm.normal_strength);
 }
 
-impl AsStorageBuffer for [VoxelMaterial; 8] {
+impl<const N: usize> AsStorageBuffer for [VoxelMaterial; N] {
     fn as_storage_buffer(&self, device : &wgpu::Device) -> StorageBuffer {
         let mut material_data = Vec::<f32>::new();
         
@@ -0,0 +1,72 @@
+use crate::engine::builders::pipeline_bind_group_builder::BindGroupBuilder;
+use crate::engine::builders::pipeline_bind_group_layout_builder::{BindGroupLayoutBuilder, EntryVisibility, LayoutEntryType};
+use crate::engine::builders::texture_builder::TextureBuilder;
+
+// Layers of the voxel texture arrays, in the order of TEXTURE_PATHS
+pub const GRASS_TOP : u32 = 0;
+pub const GRASS_SIDE : u32 = 1;
+pub const DIRT : u32 = 2;
+pub const STONE : u32 = 3;
+
+const TEXTURE_PATHS : [&str; 4] = [
+    "./assets/grass-top.png",
+    "./assets/grass-side.png",
+    "./assets/dirt.png",
+    "./assets/cube-diffuse.jpg",
+];
+
+// Tangent-space normal maps of the same layers, in the OpenGL convention with green pointing up
+const NORMAL_MAP_PATHS : [&str; 4] = [
+    "./assets/grass-top-normal.png",
+    "./assets/grass-side-normal.png",
+    "./assets/dirt-normal.png",
+    "./assets/cube-normal.png",
+];
+
+// Colour textures and normal maps of the voxel faces, one layer of a mipmapped array per texture
+// so that the smaller levels never mix neighbouring textures
+pub struct VoxelTextures {
+    pub bind_group_layout : wgpu::BindGroupLayout,
+    pub bind_group : wgpu::BindGroup,
+}
+
+impl VoxelTextures {
+    pub fn new(device : &wgpu::Device, queue : &wgpu::Queue) -> Self {
+        let textures = TextureBuilder::new_array(&TEXTURE_PATHS)
+            .generate_mipmaps()
+            .build(device, queue);
+        // Normal maps hold directions, not colours
+        let normal_maps = TextureBuilder::new_array(&NORMAL_MAP_PATHS)
+            .set_format(wgpu::TextureFormat::Rgba8Unorm)
+            .generate_mipmaps()
+            .build(device, queue);
+
+        // Faces span several voxels, the texture repeats once per voxel
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Voxel Texture Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bind_group_layout = BindGroupLayoutBuilder::new()
+            .add_entry(LayoutEntryType::FloatTexture2DArray, EntryVisibility::Fragment, 0)
+            .add_entry(LayoutEntryType::FloatTexture2DArray, EntryVisibility::Fragment, 0)
+            .add_entry(LayoutEntryType::FilteringSampler, EntryVisibility::Fragment, 0)
+            .build(device);
+        let bind_group = BindGroupBuilder::new()
+            .add_texture_entry(&textures)
+            .add_texture_entry(&normal_maps)
+            .add_sampler_entry(&sampler)
+            .build(device, &bind_group_layout);
+
+        Self {
+            bind_group_layout,
+            bind_group,
+        }
+    }
+}
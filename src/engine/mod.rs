@@ -16,3 +16,9 @@ pub mod camera;
 pub mod light;
 pub mod voxel_engine;
 pub mod compute_engine;
+pub mod headless_engine;
+pub mod capture;
+pub mod post;
+
+#[cfg(test)]
+mod visual_tests;
@@ -7,6 +7,12 @@ use super::VertexData;
 pub struct InstanceData {
     pub position: [f32; 4], 
     pub size : [f32; 4], 
+    // Index into the material palette
+    pub material : u32,
+    // Ambient occlusion of the face corners in vertex order, from 0 (fully occluded) to 255
+    pub occlusion : [u8; 4],
+    // Sky and block light in front of the face, the last two bytes are unused
+    pub light : [u8; 4],
 }
 
 impl VertexData for InstanceData {
@@ -17,14 +23,29 @@ impl VertexData for InstanceData {
             attributes: &[
                 wgpu::VertexAttribute {
                     offset: 0,
-                    shader_location: 1,
+                    shader_location: 4,
                     format: wgpu::VertexFormat::Float32x4,
                 },
                 wgpu::VertexAttribute {
                     offset: 16,
-                    shader_location: 2,
+                    shader_location: 5,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                wgpu::VertexAttribute {
+                    offset: 32,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+                wgpu::VertexAttribute {
+                    offset: 36,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Unorm8x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: 40,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Unorm8x4,
+                },
             ]
         }
     }
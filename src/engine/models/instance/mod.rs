@@ -5,6 +5,8 @@ pub mod instance_data;
 
 pub enum VertexType {
     InstancedVertex,
+    // No vertex buffers, positions come from the vertex index
+    Fullscreen,
 }
 
 pub trait VertexData {
@@ -6,6 +6,10 @@ use super::VertexData;
 #[derive(Clone, Copy, Pod, Zeroable, Debug)]
 pub struct VoxelVertex {
     pub _pos: [f32; 4],
+    pub normal: [f32; 3],
+    // Direction of increasing u, the bitangent is cross(normal, tangent) * w
+    pub tangent: [f32; 4],
+    pub uv: [f32; 2],
 }
 
 impl VertexData for VoxelVertex {
@@ -20,6 +24,21 @@ impl VertexData for VoxelVertex {
                     shader_location: 0,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                wgpu::VertexAttribute {
+                    offset: 16,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: 28,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: 44,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
             ]
         }
     }
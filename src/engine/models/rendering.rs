@@ -16,6 +16,9 @@ impl<'a, 'b> DrawModel<'b> for wgpu::RenderPass<'a>
         self.set_vertex_buffer(0, model.mesh.vertex_data.slice(..));
         self.set_index_buffer(model.mesh.index_data.slice(..), wgpu::IndexFormat::Uint32);
         self.set_vertex_buffer(1, model.instance_buffer.slice(..));
-        self.draw_indexed(0..model.mesh.num_elements, 0, 0..model.instance_count);
+        let flipped_start = model.instance_count - model.flipped_instance_count;
+        self.draw_indexed(0..model.mesh.num_elements, 0, 0..flipped_start);
+        self.set_index_buffer(model.flipped_index_data.slice(..), wgpu::IndexFormat::Uint32);
+        self.draw_indexed(0..model.mesh.num_elements, 0, flipped_start..model.instance_count);
     }
 }
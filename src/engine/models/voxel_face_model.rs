@@ -3,10 +3,15 @@ use super::{mesh::Mesh, instance::{VertexData, voxel_vertex::VoxelVertex}};
 
 pub struct VoxelFaceModel {
     pub mesh: Mesh,
+    // Same quad split along the other diagonal
+    pub flipped_index_data: wgpu::Buffer,
     pub instance_buffer: wgpu::Buffer,
     pub instance_count: u32,
+    // The last instances of the buffer are drawn with the flipped quad
+    pub flipped_instance_count: u32,
 }
 
+#[derive(Clone, Copy)]
 pub enum VoxelFace {
     Top,
     Bottom,
@@ -16,68 +21,143 @@ pub enum VoxelFace {
     Back,
 }
 
+impl VoxelFace {
+    pub fn normal(&self) -> [i32; 3] {
+        match self {
+            VoxelFace::Top => [0, 1, 0],
+            VoxelFace::Bottom => [0, -1, 0],
+            VoxelFace::Left => [-1, 0, 0],
+            VoxelFace::Right => [1, 0, 0],
+            VoxelFace::Front => [0, 0, -1],
+            VoxelFace::Back => [0, 0, 1],
+        }
+    }
+
+    // Corners of the face in a unit cube centered on the origin
+    pub fn vertices(&self) -> &'static [VoxelVertex; 4] {
+        match self {
+            VoxelFace::Top => &VERTEX_FACE_UP,
+            VoxelFace::Bottom => &VERTEX_FACE_DOWN,
+            VoxelFace::Left => &VERTEX_FACE_LEFT,
+            VoxelFace::Right => &VERTEX_FACE_RIGHT,
+            VoxelFace::Front => &VERTEX_FACE_FRONT,
+            VoxelFace::Back => &VERTEX_FACE_BACK,
+        }
+    }
+
+    // Triangles of the quad, the first two indices are the diagonal both triangles share
+    fn indices(&self) -> &'static [u32; 6] {
+        match self {
+            VoxelFace::Bottom => &[0, 3, 2, 0, 1, 3],
+            VoxelFace::Top => &[0, 3, 1, 0, 2, 3],
+            VoxelFace::Left => &[0, 2, 1, 0, 3, 2],
+            VoxelFace::Right => &[0, 2, 3, 0, 1, 2],
+            VoxelFace::Front => &[0, 2, 1, 0, 3, 2],
+            VoxelFace::Back => &[0, 2, 3, 0, 1, 2],
+        }
+    }
+
+    // Triangles sharing the other diagonal, the corners rotated by one step around the quad
+    fn flipped_indices(&self) -> &'static [u32; 6] {
+        match self {
+            VoxelFace::Bottom => &[1, 2, 0, 1, 3, 2],
+            VoxelFace::Top => &[1, 2, 3, 1, 0, 2],
+            VoxelFace::Left => &[1, 3, 2, 1, 0, 3],
+            VoxelFace::Right => &[1, 3, 0, 1, 2, 3],
+            VoxelFace::Front => &[1, 3, 2, 1, 0, 3],
+            VoxelFace::Back => &[1, 3, 0, 1, 2, 3],
+        }
+    }
+
+    // Corners on the shared diagonal of the unflipped quad, then the two others
+    pub fn diagonals(&self) -> ([usize; 2], [usize; 2]) {
+        let indices = self.indices();
+        let flipped = self.flipped_indices();
+        ([indices[0] as usize, indices[1] as usize], [flipped[0] as usize, flipped[1] as usize])
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            VoxelFace::Top => "Top",
+            VoxelFace::Bottom => "Bottom",
+            VoxelFace::Left => "Left",
+            VoxelFace::Right => "Right",
+            VoxelFace::Front => "Front",
+            VoxelFace::Back => "Back",
+        }
+    }
+}
+
+// Seen from outside every face has u pointing right and v pointing down, which makes the
+// bitangent the opposite of cross(normal, tangent)
+const fn face_vertex(position : [f32; 3], normal : [f32; 3], tangent : [f32; 3], uv : [f32; 2]) -> VoxelVertex {
+    VoxelVertex {
+        _pos: [position[0], position[1], position[2], 0.5],
+        normal,
+        tangent: [tangent[0], tangent[1], tangent[2], -1.0],
+        uv,
+    }
+}
+
 const VERTEX_FACE_UP : [VoxelVertex; 4] = [
-    VoxelVertex { _pos: [-0.5,  0.5, -0.5,  0.5] },
-    VoxelVertex { _pos: [ 0.5,  0.5, -0.5,  0.5] },
-    VoxelVertex { _pos: [-0.5,  0.5,  0.5,  0.5] },
-    VoxelVertex { _pos: [ 0.5,  0.5,  0.5,  0.5] },
+    face_vertex([-0.5,  0.5, -0.5], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0]),
+    face_vertex([ 0.5,  0.5, -0.5], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [1.0, 0.0]),
+    face_vertex([-0.5,  0.5,  0.5], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0]),
+    face_vertex([ 0.5,  0.5,  0.5], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0]),
 ];
 
 const VERTEX_FACE_DOWN : [VoxelVertex; 4] = [
-    VoxelVertex { _pos: [-0.5, -0.5, -0.5,  0.5] },
-    VoxelVertex { _pos: [ 0.5, -0.5, -0.5,  0.5] },
-    VoxelVertex { _pos: [-0.5, -0.5,  0.5,  0.5] },
-    VoxelVertex { _pos: [ 0.5, -0.5,  0.5,  0.5] },
+    face_vertex([-0.5, -0.5, -0.5], [0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0]),
+    face_vertex([ 0.5, -0.5, -0.5], [0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0]),
+    face_vertex([-0.5, -0.5,  0.5], [0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0]),
+    face_vertex([ 0.5, -0.5,  0.5], [0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [1.0, 0.0]),
 ];
 
 const VERTEX_FACE_LEFT : [VoxelVertex; 4] = [
-    VoxelVertex { _pos: [-0.5, -0.5, -0.5,  0.5] },
-    VoxelVertex { _pos: [-0.5,  0.5, -0.5,  0.5] },
-    VoxelVertex { _pos: [-0.5,  0.5,  0.5,  0.5] },
-    VoxelVertex { _pos: [-0.5, -0.5,  0.5,  0.5] },
+    face_vertex([-0.5, -0.5, -0.5], [-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0]),
+    face_vertex([-0.5,  0.5, -0.5], [-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 0.0]),
+    face_vertex([-0.5,  0.5,  0.5], [-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0]),
+    face_vertex([-0.5, -0.5,  0.5], [-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [1.0, 1.0]),
 ];
 
 const VERTEX_FACE_RIGHT : [VoxelVertex; 4] = [
-    VoxelVertex { _pos: [ 0.5, -0.5, -0.5,  0.5] },
-    VoxelVertex { _pos: [ 0.5,  0.5, -0.5,  0.5] },
-    VoxelVertex { _pos: [ 0.5,  0.5,  0.5,  0.5] },
-    VoxelVertex { _pos: [ 0.5, -0.5,  0.5,  0.5] },
+    face_vertex([ 0.5, -0.5, -0.5], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [1.0, 1.0]),
+    face_vertex([ 0.5,  0.5, -0.5], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [1.0, 0.0]),
+    face_vertex([ 0.5,  0.5,  0.5], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 0.0]),
+    face_vertex([ 0.5, -0.5,  0.5], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0]),
 ];
 
 const VERTEX_FACE_FRONT : [VoxelVertex; 4] = [
-    VoxelVertex { _pos: [-0.5, -0.5, -0.5,  0.5] },
-    VoxelVertex { _pos: [ 0.5, -0.5, -0.5,  0.5] },
-    VoxelVertex { _pos: [ 0.5,  0.5, -0.5,  0.5] },
-    VoxelVertex { _pos: [-0.5,  0.5, -0.5,  0.5] },
+    face_vertex([-0.5, -0.5, -0.5], [0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [1.0, 1.0]),
+    face_vertex([ 0.5, -0.5, -0.5], [0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0]),
+    face_vertex([ 0.5,  0.5, -0.5], [0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 0.0]),
+    face_vertex([-0.5,  0.5, -0.5], [0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [1.0, 0.0]),
 ];
 
 const VERTEX_FACE_BACK : [VoxelVertex; 4] = [
-    VoxelVertex { _pos: [-0.5, -0.5,  0.5,  0.5] },
-    VoxelVertex { _pos: [ 0.5, -0.5,  0.5,  0.5] },
-    VoxelVertex { _pos: [ 0.5,  0.5,  0.5,  0.5] },
-    VoxelVertex { _pos: [-0.5,  0.5,  0.5,  0.5] },
+    face_vertex([-0.5, -0.5,  0.5], [0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0]),
+    face_vertex([ 0.5, -0.5,  0.5], [0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [1.0, 1.0]),
+    face_vertex([ 0.5,  0.5,  0.5], [0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [1.0, 0.0]),
+    face_vertex([-0.5,  0.5,  0.5], [0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 0.0]),
 ];
 
 impl VoxelFaceModel {
+    // `flipped_instances` are drawn with the quad split along its other diagonal
     pub fn new<T>(
         device: &wgpu::Device,
         voxel_face: VoxelFace,
-        instances: Vec<T>,
+        mut instances: Vec<T>,
+        flipped_instances: Vec<T>,
     ) -> Self 
         where T : VertexData + bytemuck::Pod + bytemuck::Zeroable 
     {
         
-        let (vertices, indices) = match voxel_face {
-            VoxelFace::Bottom => (&VERTEX_FACE_DOWN, &[0, 1, 3, 0, 3, 2]),
-            VoxelFace::Top => (&VERTEX_FACE_UP, &[0, 3, 1, 0, 2, 3]),
-            VoxelFace::Left => (&VERTEX_FACE_LEFT, &[0, 2, 1, 0, 3, 2]),
-            VoxelFace::Right => (&VERTEX_FACE_RIGHT, &[0, 1, 2, 0, 2, 3]),
-            VoxelFace::Front => (&VERTEX_FACE_FRONT, &[0, 2, 1, 0, 3, 2]),
-            VoxelFace::Back => (&VERTEX_FACE_BACK, &[0, 1, 2, 0, 2, 3]),
-        };
+        let vertices = voxel_face.vertices();
+        let indices = voxel_face.indices();
 
         let vertex_buffer = buffers::create_buffer(device, buffers::BufferType::Vertex, vertices);
         let index_buffer = buffers::create_buffer(device, buffers::BufferType::Index, indices);
+        let flipped_index_buffer = buffers::create_buffer(device, buffers::BufferType::Index, voxel_face.flipped_indices());
         
         let mesh = Mesh {
             vertex_data: vertex_buffer,
@@ -85,12 +165,82 @@ impl VoxelFaceModel {
             num_elements: indices.len() as u32,
         };
 
+        let flipped_instance_count = flipped_instances.len() as u32;
+        instances.extend(flipped_instances);
         let instance_buffer = buffers::create_buffer(device, buffers::BufferType::Instance, &instances); 
 
         VoxelFaceModel { 
             mesh, 
+            flipped_index_data: flipped_index_buffer,
             instance_buffer,
             instance_count: instances.len() as u32,
+            flipped_instance_count,
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::VoxelFace;
+
+    const FACES : [VoxelFace; 6] = [
+        VoxelFace::Top,
+        VoxelFace::Bottom,
+        VoxelFace::Left,
+        VoxelFace::Right,
+        VoxelFace::Front,
+        VoxelFace::Back,
+    ];
+
+    fn sub(a : [f32; 3], b : [f32; 3]) -> [f32; 3] {
+        [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+    }
+
+    fn dot(a : [f32; 3], b : [f32; 3]) -> f32 {
+        a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+    }
+
+    fn cross(a : [f32; 3], b : [f32; 3]) -> [f32; 3] {
+        [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+    }
+
+    fn position(face : VoxelFace, index : u32) -> [f32; 3] {
+        let pos = face.vertices()[index as usize]._pos;
+        [pos[0], pos[1], pos[2]]
+    }
+
+    #[test]
+    fn triangles_wind_counter_clockwise_around_the_normal() {
+        for face in FACES {
+            let normal = face.normal().map(|n| n as f32);
+            for indices in [face.indices(), face.flipped_indices()] {
+                for triangle in indices.chunks(3) {
+                    let [a, b, c] = [0, 1, 2].map(|i| position(face, triangle[i]));
+                    let winding = dot(cross(sub(b, a), sub(c, a)), normal);
+                    assert!(winding > 0.0, "{} face triangle {:?} winds clockwise", face.name(), triangle);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn vertices_carry_the_face_frame() {
+        for face in FACES {
+            let normal = face.normal().map(|n| n as f32);
+            let vertices = face.vertices();
+            for (i, vertex) in vertices.iter().enumerate() {
+                let position = position(face, i as u32);
+                assert_eq!(vertex.normal, normal, "{} face normal", face.name());
+                assert!(dot(position, normal) == 0.5, "{} face corner off the face", face.name());
+
+                let tangent = [vertex.tangent[0], vertex.tangent[1], vertex.tangent[2]];
+                let bitangent = cross(normal, tangent).map(|b| b * vertex.tangent[3]);
+                assert_eq!(dot(tangent, normal), 0.0, "{} face tangent", face.name());
+                assert_eq!(dot(tangent, tangent), 1.0, "{} face tangent", face.name());
+
+                // The UVs follow the tangent and the bitangent across the face
+                let uv = [dot(position, tangent) + 0.5, dot(position, bitangent) + 0.5];
+                assert_eq!(vertex.uv, uv, "{} face corner {} UV", face.name(), i);
+            }
         }
     }
-}
\ No newline at end of file
+}
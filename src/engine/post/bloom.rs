@@ -0,0 +1,76 @@
+use super::{create_hdr_texture, create_texture_layout, FullscreenPass, HDR_FORMAT, PostEffect, PostSettings};
+
+// Levels of the mip chain, the first one at half the frame size
+const MAX_LEVELS : u32 = 6;
+// Fraction of the threshold below which the bloom fades in
+const SOFT_KNEE : f32 = 0.5;
+
+// Bloom over a progressively downsampled then upsampled mip chain of the bright parts of the scene
+pub struct Bloom {
+    downsample : FullscreenPass,
+    upsample : FullscreenPass,
+    composite : FullscreenPass,
+    // Downsampled levels, from half size to the coarsest
+    down_levels : Vec<wgpu::TextureView>,
+    // Upsampled levels, each one the blend of the level below and the downsampled level of its size
+    up_levels : Vec<wgpu::TextureView>,
+}
+
+impl Bloom {
+    pub fn new(device : &wgpu::Device) -> Self {
+        let texture_layout = create_texture_layout(device);
+        Self {
+            downsample : FullscreenPass::new(device, "./shaders/post/bloom_downsample.wgsl", HDR_FORMAT, &[]),
+            upsample : FullscreenPass::new(device, "./shaders/post/bloom_upsample.wgsl", HDR_FORMAT, &[&texture_layout]),
+            composite : FullscreenPass::new(device, "./shaders/post/bloom_composite.wgsl", HDR_FORMAT, &[&texture_layout]),
+            down_levels : Vec::new(),
+            up_levels : Vec::new(),
+        }
+    }
+}
+
+impl PostEffect for Bloom {
+    fn is_enabled(&self, settings : &PostSettings) -> bool {
+        settings.bloom_enabled
+    }
+
+    fn resize(&mut self, device : &wgpu::Device, width : u32, height : u32) {
+        let sizes : Vec<(u32, u32)> = (1..=MAX_LEVELS)
+            .map(|level| (width >> level, height >> level))
+            .take_while(|(width, height)| *width > 0 && *height > 0)
+            .collect();
+
+        self.down_levels = sizes.iter().map(|(width, height)| create_hdr_texture(device, *width, *height, 1)).collect();
+        // The coarsest level is not upsampled into
+        self.up_levels = sizes.iter()
+            .take(sizes.len().saturating_sub(1))
+            .map(|(width, height)| create_hdr_texture(device, *width, *height, 1))
+            .collect();
+    }
+
+    fn render(
+        &mut self,
+        device : &wgpu::Device,
+        _queue : &wgpu::Queue,
+        encoder : &mut wgpu::CommandEncoder,
+        input : &wgpu::TextureView,
+        output : &wgpu::TextureView,
+        settings : &PostSettings,
+    ) {
+        let mut source = input;
+        for (level, target) in self.down_levels.iter().enumerate() {
+            let first = if level == 0 { 1.0 } else { 0.0 };
+            self.downsample.render(device, encoder, source, target, [settings.bloom_threshold, SOFT_KNEE, first, 0.0], &[]);
+            source = target;
+        }
+
+        for level in (0..self.up_levels.len()).rev() {
+            let downsampled = self.upsample.bind_texture(device, &self.down_levels[level]);
+            self.upsample.render(device, encoder, source, &self.up_levels[level], [settings.bloom_radius, 0.0, 0.0, 0.0], &[&downsampled]);
+            source = &self.up_levels[level];
+        }
+
+        let bloom = self.composite.bind_texture(device, source);
+        self.composite.render(device, encoder, input, output, [settings.bloom_intensity, 0.0, 0.0, 0.0], &[&bloom]);
+    }
+}
@@ -0,0 +1,158 @@
+use std::path::{Path, PathBuf};
+
+use crate::engine::builders::pipeline_bind_group_builder::BindGroupBuilder;
+use crate::engine::builders::pipeline_bind_group_layout_builder::{BindGroupLayoutBuilder, EntryVisibility, LayoutEntryType};
+
+use super::{FullscreenPass, HDR_FORMAT, PostEffect, PostSettings};
+
+const IDENTITY_LUT_SIZE : u32 = 16;
+
+// Texels of a LUT of `size`^3 entries, red varying fastest and blue slowest
+fn identity_lut(size : u32) -> Vec<u8> {
+    let level = |i : u32| (i * 255 / (size - 1)) as u8;
+    let mut texels = Vec::with_capacity((size * size * size * 4) as usize);
+    for b in 0..size {
+        for g in 0..size {
+            for r in 0..size {
+                texels.extend_from_slice(&[level(r), level(g), level(b), 255]);
+            }
+        }
+    }
+    texels
+}
+
+// Reads a strip LUT, N slices of NxN texels side by side with blue selecting the slice
+fn load_strip_lut(path : &Path) -> Result<(u32, Vec<u8>), String> {
+    let image = image::open(path).map_err(|e| e.to_string())?.to_rgba8();
+    let size = image.height();
+    if size < 2 || image.width() != size * size {
+        return Err(format!("expected a {}x{} strip, got {}x{}", size * size, size, image.width(), image.height()));
+    }
+
+    let mut texels = Vec::with_capacity((size * size * size * 4) as usize);
+    for b in 0..size {
+        for g in 0..size {
+            for r in 0..size {
+                texels.extend_from_slice(&image.get_pixel(b * size + r, g).0);
+            }
+        }
+    }
+    Ok((size, texels))
+}
+
+fn create_lut_texture(device : &wgpu::Device, queue : &wgpu::Queue, size : u32, texels : &[u8]) -> wgpu::TextureView {
+    let extent = wgpu::Extent3d {
+        width: size,
+        height: size,
+        depth_or_array_layers: size,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Colour Grading LUT"),
+        size: extent,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+    });
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        texels,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: std::num::NonZeroU32::new(4 * size),
+            rows_per_image: std::num::NonZeroU32::new(size),
+        },
+        extent,
+    );
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+// Remaps the tonemapped colours through a 3D lookup table
+pub struct ColorGrading {
+    pass : FullscreenPass,
+    lut_layout : wgpu::BindGroupLayout,
+    lut_bind_group : wgpu::BindGroup,
+    lut_size : u32,
+    // LUT file currently uploaded, None for the identity
+    loaded_lut : Option<PathBuf>,
+}
+
+impl ColorGrading {
+    pub fn new(device : &wgpu::Device, queue : &wgpu::Queue) -> Self {
+        let lut_layout = BindGroupLayoutBuilder::new()
+            .add_entry(LayoutEntryType::FloatTexture3D, EntryVisibility::Fragment, 0)
+            .add_entry(LayoutEntryType::FilteringSampler, EntryVisibility::Fragment, 0)
+            .build(device);
+        let pass = FullscreenPass::new(device, "./shaders/post/color_grading.wgsl", HDR_FORMAT, &[&lut_layout]);
+        let lut_bind_group = Self::create_lut_bind_group(device, queue, &lut_layout, IDENTITY_LUT_SIZE, &identity_lut(IDENTITY_LUT_SIZE));
+
+        Self {
+            pass,
+            lut_layout,
+            lut_bind_group,
+            lut_size : IDENTITY_LUT_SIZE,
+            loaded_lut : None,
+        }
+    }
+
+    fn create_lut_bind_group(device : &wgpu::Device, queue : &wgpu::Queue, layout : &wgpu::BindGroupLayout, size : u32, texels : &[u8]) -> wgpu::BindGroup {
+        let lut = create_lut_texture(device, queue, size, texels);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Colour Grading Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        BindGroupBuilder::new()
+            .add_texture_entry(&lut)
+            .add_sampler_entry(&sampler)
+            .build(device, layout)
+    }
+
+    // Uploads the LUT selected in the settings, falling back to the identity when it cannot be read
+    fn update_lut(&mut self, device : &wgpu::Device, queue : &wgpu::Queue, lut : Option<&PathBuf>) {
+        self.loaded_lut = lut.cloned();
+        let (size, texels) = match lut.map(|path| (path, load_strip_lut(path))) {
+            Some((_, Ok(lut))) => lut,
+            Some((path, Err(e))) => {
+                eprintln!("failed to load LUT {:?}: {}", path, e);
+                (IDENTITY_LUT_SIZE, identity_lut(IDENTITY_LUT_SIZE))
+            }
+            None => (IDENTITY_LUT_SIZE, identity_lut(IDENTITY_LUT_SIZE)),
+        };
+        self.lut_size = size;
+        self.lut_bind_group = Self::create_lut_bind_group(device, queue, &self.lut_layout, size, &texels);
+    }
+}
+
+impl PostEffect for ColorGrading {
+    fn is_enabled(&self, settings : &PostSettings) -> bool {
+        settings.grading_enabled
+    }
+
+    fn render(
+        &mut self,
+        device : &wgpu::Device,
+        queue : &wgpu::Queue,
+        encoder : &mut wgpu::CommandEncoder,
+        input : &wgpu::TextureView,
+        output : &wgpu::TextureView,
+        settings : &PostSettings,
+    ) {
+        if settings.grading_lut != self.loaded_lut {
+            self.update_lut(device, queue, settings.grading_lut.as_ref());
+        }
+        let params = [settings.grading_strength, self.lut_size as f32, 0.0, 0.0];
+        self.pass.render(device, encoder, input, output, params, &[&self.lut_bind_group]);
+    }
+}
@@ -0,0 +1,288 @@
+use std::path::PathBuf;
+
+use crate::engine::buffers::uniform_buffer::UniformBuffer;
+use crate::engine::builders::pipeline_bind_group_builder::BindGroupBuilder;
+use crate::engine::builders::pipeline_bind_group_layout_builder::{BindGroupLayoutBuilder, EntryVisibility, LayoutEntryType};
+use crate::engine::builders::pipeline_builder::PipelineBuilder;
+use crate::engine::builders::pipeline_layout_builder::PipelineLayoutBuilder;
+use crate::engine::models::instance::VertexType;
+
+pub mod tonemapping;
+pub mod color_grading;
+pub mod bloom;
+
+// Format the scene and the intermediate results of the chain are rendered in
+pub const HDR_FORMAT : wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+const PARAMS_SIZE : u64 = std::mem::size_of::<[f32; 4]>() as u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tonemapper {
+    Reinhard,
+    Aces,
+}
+
+pub struct PostSettings {
+    pub bloom_enabled : bool,
+    // Brightness above which the scene starts to bloom
+    pub bloom_threshold : f32,
+    pub bloom_intensity : f32,
+    // How far the glow spreads, from the finest (0) to the coarsest (1) levels of the mip chain
+    pub bloom_radius : f32,
+    pub exposure_enabled : bool,
+    // In stops, every stop doubles the brightness
+    pub exposure : f32,
+    pub tonemapping_enabled : bool,
+    pub tonemapper : Tonemapper,
+    pub grading_enabled : bool,
+    // Blend between the tonemapped (0) and the fully graded (1) colours
+    pub grading_strength : f32,
+    // Strip image of N slices of NxN texels, an identity LUT is used until one is set
+    pub grading_lut : Option<PathBuf>,
+    pub gamma_enabled : bool,
+    pub gamma : f32,
+}
+
+impl PostSettings {
+    pub fn new() -> Self {
+        Self {
+            bloom_enabled : true,
+            bloom_threshold : 1.0,
+            bloom_intensity : 0.3,
+            bloom_radius : 0.7,
+            exposure_enabled : true,
+            exposure : 0.0,
+            tonemapping_enabled : true,
+            tonemapper : Tonemapper::Aces,
+            grading_enabled : false,
+            grading_strength : 1.0,
+            grading_lut : None,
+            gamma_enabled : true,
+            gamma : 2.2,
+        }
+    }
+}
+
+// One step of the post-processing chain. Effects read the result of the previous step and
+// write a frame of the same size in HDR_FORMAT.
+pub trait PostEffect {
+    fn is_enabled(&self, settings : &PostSettings) -> bool;
+
+    // Called before the first frame and whenever the frame size changes
+    fn resize(&mut self, _device : &wgpu::Device, _width : u32, _height : u32) {}
+
+    fn render(
+        &mut self,
+        device : &wgpu::Device,
+        queue : &wgpu::Queue,
+        encoder : &mut wgpu::CommandEncoder,
+        input : &wgpu::TextureView,
+        output : &wgpu::TextureView,
+        settings : &PostSettings,
+    );
+}
+
+// Layout of a filterable texture and its sampler, used for the input of every pass
+pub fn create_texture_layout(device : &wgpu::Device) -> wgpu::BindGroupLayout {
+    BindGroupLayoutBuilder::new()
+        .add_entry(LayoutEntryType::FloatTexture2D, EntryVisibility::Fragment, 0)
+        .add_entry(LayoutEntryType::FilteringSampler, EntryVisibility::Fragment, 0)
+        .build(device)
+}
+
+// Fullscreen triangle running a fragment shader over the input, the common part of most effects.
+// The shader gets the input at group 0, a vec4 of parameters at group 1 and `extra_layouts` after.
+pub struct FullscreenPass {
+    pipeline : wgpu::RenderPipeline,
+    input_layout : wgpu::BindGroupLayout,
+    sampler : wgpu::Sampler,
+}
+
+impl FullscreenPass {
+    pub fn new(device : &wgpu::Device, shader_path : &str, format : wgpu::TextureFormat, extra_layouts : &[&wgpu::BindGroupLayout]) -> Self {
+        let input_layout = create_texture_layout(device);
+        let params_uniform = UniformBuffer::new(device, &[0.0f32; 4], PARAMS_SIZE);
+
+        let mut pipeline_layout = PipelineLayoutBuilder::new()
+            .add_bind_group_layout(&input_layout)
+            .add_bind_group_layout(&params_uniform.bind_group_layout);
+        for layout in extra_layouts {
+            pipeline_layout = pipeline_layout.add_bind_group_layout(layout);
+        }
+
+        let pipeline = PipelineBuilder::new()
+            .set_primitive_state(None)
+            .set_depth_test(false)
+            .set_vertex_shader(device, shader_path, VertexType::Fullscreen)
+            .set_fragment_shader(device, shader_path, &format)
+            .set_pipeline_layout(pipeline_layout.build(device))
+            .build(device);
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            pipeline,
+            input_layout,
+            sampler,
+        }
+    }
+
+    // Binds a texture with the sampler of the pass, for extra groups laid out by create_texture_layout
+    pub fn bind_texture(&self, device : &wgpu::Device, texture : &wgpu::TextureView) -> wgpu::BindGroup {
+        BindGroupBuilder::new()
+            .add_texture_entry(texture)
+            .add_sampler_entry(&self.sampler)
+            .build(device, &self.input_layout)
+    }
+
+    pub fn render(
+        &self,
+        device : &wgpu::Device,
+        encoder : &mut wgpu::CommandEncoder,
+        input : &wgpu::TextureView,
+        output : &wgpu::TextureView,
+        params : [f32; 4],
+        extra_bind_groups : &[&wgpu::BindGroup],
+    ) {
+        let input_bind_group = self.bind_texture(device, input);
+        let params_uniform = UniformBuffer::new(device, &params, PARAMS_SIZE);
+
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Post Pass"),
+            color_attachments: &[
+                Some(wgpu::RenderPassColorAttachment {
+                    view: output,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    }
+                })
+            ],
+            depth_stencil_attachment: None,
+        });
+
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &input_bind_group, &[]);
+        rpass.set_bind_group(1, &params_uniform.bind_group, &[]);
+        for (i, bind_group) in extra_bind_groups.iter().enumerate() {
+            rpass.set_bind_group(2 + i as u32, bind_group, &[]);
+        }
+        rpass.draw(0..3, 0..1);
+    }
+}
+
+// Colour target the scene is rendered into before post-processing
+pub fn create_hdr_texture(device : &wgpu::Device, width : u32, height : u32, sample_count : u32) -> wgpu::TextureView {
+    let usage = if sample_count > 1 {
+        wgpu::TextureUsages::RENDER_ATTACHMENT
+    } else {
+        wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+    };
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("HDR Target"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format: HDR_FORMAT,
+        usage,
+    }).create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+// Runs the enabled effects in order over an HDR frame and writes the result to the output target
+pub struct PostProcessor {
+    effects : Vec<Box<dyn PostEffect>>,
+    output_pass : FullscreenPass,
+    output_format : wgpu::TextureFormat,
+    // Ping-pong targets holding the intermediate results
+    targets : Vec<wgpu::TextureView>,
+    size : (u32, u32),
+    pub settings : PostSettings,
+}
+
+impl PostProcessor {
+    // Chain of bloom, exposure, tonemapping, colour grading and gamma
+    pub fn new(device : &wgpu::Device, queue : &wgpu::Queue, output_format : wgpu::TextureFormat) -> Self {
+        let mut post = Self::empty(device, output_format);
+        post.add_effect(Box::new(bloom::Bloom::new(device)));
+        post.add_effect(Box::new(tonemapping::Exposure::new(device)));
+        post.add_effect(Box::new(tonemapping::Tonemapping::new(device)));
+        post.add_effect(Box::new(color_grading::ColorGrading::new(device, queue)));
+        post.add_effect(Box::new(tonemapping::Gamma::new(device)));
+        post
+    }
+
+    pub fn empty(device : &wgpu::Device, output_format : wgpu::TextureFormat) -> Self {
+        Self {
+            effects : Vec::new(),
+            output_pass : FullscreenPass::new(device, "./shaders/post/copy.wgsl", output_format, &[]),
+            output_format,
+            targets : Vec::new(),
+            size : (0, 0),
+            settings : PostSettings::new(),
+        }
+    }
+
+    // Effects run in the order they are added
+    pub fn add_effect(&mut self, effect : Box<dyn PostEffect>) {
+        self.effects.push(effect);
+        // Makes the next render size the new effect
+        self.size = (0, 0);
+    }
+
+    pub fn get_output_format(&self) -> wgpu::TextureFormat {
+        self.output_format
+    }
+
+    fn resize(&mut self, device : &wgpu::Device, width : u32, height : u32) {
+        self.targets = (0..2).map(|_| create_hdr_texture(device, width, height, 1)).collect();
+        for effect in self.effects.iter_mut() {
+            effect.resize(device, width, height);
+        }
+        self.size = (width, height);
+    }
+
+    // `input` is an HDR frame of the given size, `output` a target of the output format
+    pub fn render(
+        &mut self,
+        device : &wgpu::Device,
+        queue : &wgpu::Queue,
+        encoder : &mut wgpu::CommandEncoder,
+        input : &wgpu::TextureView,
+        outputs : &[&wgpu::TextureView],
+        (width, height) : (u32, u32),
+    ) {
+        if self.size != (width, height) {
+            self.resize(device, width, height);
+        }
+
+        let mut source = input;
+        let mut next = 0;
+        for effect in self.effects.iter_mut() {
+            if !effect.is_enabled(&self.settings) {
+                continue;
+            }
+            effect.render(device, queue, encoder, source, &self.targets[next], &self.settings);
+            source = &self.targets[next];
+            next = 1 - next;
+        }
+
+        for output in outputs {
+            self.output_pass.render(device, encoder, source, output, [0.0; 4], &[]);
+        }
+    }
+}
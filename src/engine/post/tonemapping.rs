@@ -0,0 +1,98 @@
+use super::{FullscreenPass, HDR_FORMAT, PostEffect, PostSettings, Tonemapper};
+
+// Scales the scene by a power of two before tonemapping
+pub struct Exposure {
+    pass : FullscreenPass,
+}
+
+impl Exposure {
+    pub fn new(device : &wgpu::Device) -> Self {
+        Self {
+            pass : FullscreenPass::new(device, "./shaders/post/exposure.wgsl", HDR_FORMAT, &[]),
+        }
+    }
+}
+
+impl PostEffect for Exposure {
+    fn is_enabled(&self, settings : &PostSettings) -> bool {
+        settings.exposure_enabled
+    }
+
+    fn render(
+        &mut self,
+        device : &wgpu::Device,
+        _queue : &wgpu::Queue,
+        encoder : &mut wgpu::CommandEncoder,
+        input : &wgpu::TextureView,
+        output : &wgpu::TextureView,
+        settings : &PostSettings,
+    ) {
+        self.pass.render(device, encoder, input, output, [settings.exposure, 0.0, 0.0, 0.0], &[]);
+    }
+}
+
+// Maps the unbounded scene colours into [0, 1]
+pub struct Tonemapping {
+    pass : FullscreenPass,
+}
+
+impl Tonemapping {
+    pub fn new(device : &wgpu::Device) -> Self {
+        Self {
+            pass : FullscreenPass::new(device, "./shaders/post/tonemap.wgsl", HDR_FORMAT, &[]),
+        }
+    }
+}
+
+impl PostEffect for Tonemapping {
+    fn is_enabled(&self, settings : &PostSettings) -> bool {
+        settings.tonemapping_enabled
+    }
+
+    fn render(
+        &mut self,
+        device : &wgpu::Device,
+        _queue : &wgpu::Queue,
+        encoder : &mut wgpu::CommandEncoder,
+        input : &wgpu::TextureView,
+        output : &wgpu::TextureView,
+        settings : &PostSettings,
+    ) {
+        let operator = match settings.tonemapper {
+            Tonemapper::Reinhard => 0.0,
+            Tonemapper::Aces => 1.0,
+        };
+        self.pass.render(device, encoder, input, output, [operator, 0.0, 0.0, 0.0], &[]);
+    }
+}
+
+// Encodes with a power curve instead of the sRGB curve of the output target
+pub struct Gamma {
+    pass : FullscreenPass,
+}
+
+impl Gamma {
+    pub fn new(device : &wgpu::Device) -> Self {
+        Self {
+            pass : FullscreenPass::new(device, "./shaders/post/gamma.wgsl", HDR_FORMAT, &[]),
+        }
+    }
+}
+
+impl PostEffect for Gamma {
+    fn is_enabled(&self, settings : &PostSettings) -> bool {
+        settings.gamma_enabled
+    }
+
+    fn render(
+        &mut self,
+        device : &wgpu::Device,
+        _queue : &wgpu::Queue,
+        encoder : &mut wgpu::CommandEncoder,
+        input : &wgpu::TextureView,
+        output : &wgpu::TextureView,
+        settings : &PostSettings,
+    ) {
+        self.pass.render(device, encoder, input, output, [settings.gamma.max(0.1), 0.0, 0.0, 0.0], &[]);
+    }
+}
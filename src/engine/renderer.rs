@@ -4,6 +4,12 @@ use winit::window::Fullscreen;
 
 use crate::engine::surface_engine;
 use crate::engine::imgui_engine;
+use crate::engine::capture;
+use crate::engine::capture::frame_capture::FrameCapture;
+use crate::engine::capture::panorama;
+use crate::engine::light::DirectionalLight;
+use crate::engine::post::{self, PostProcessor};
+use crate::engine::voxel_engine::VoxelEngine;
 
 use super::builders::texture_builder;
 use super::time::TimeUtils;
@@ -17,6 +23,37 @@ const EXCLUSIVE_KEYS : [(winit::event::VirtualKeyCode, winit::event::VirtualKeyC
     (winit::event::VirtualKeyCode::LShift, winit::event::VirtualKeyCode::Space),
 ];
 
+const SAMPLE_COUNTS : [u32; 4] = [1, 2, 4, 8];
+const DEPTH_FORMAT : wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+// Depth target, HDR scene target, and the multisampled target resolved into it when MSAA is on
+fn create_render_targets(
+    device : &wgpu::Device, 
+    queue : &wgpu::Queue, 
+    config : &wgpu::SurfaceConfiguration, 
+    sample_count : u32,
+) -> (wgpu::TextureView, wgpu::TextureView, Option<wgpu::TextureView>) {
+    // Multisampled depth cannot be bound as a regular texture, and the GL backend fails to
+    // render into it at all when asked to
+    let depth_usage = if sample_count > 1 {
+        wgpu::TextureUsages::RENDER_ATTACHMENT
+    } else {
+        wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+    };
+    let depth_texture = texture_builder::TextureBuilder::new("", texture_builder::TextureType::TextureDepth)
+        .set_dimensions(2)
+        .set_extent(config.width, config.height, 1)
+        .set_sample_count(sample_count)
+        .set_format(DEPTH_FORMAT)
+        .set_usage(depth_usage)
+        .build(device, queue);
+
+    let scene_texture = post::create_hdr_texture(device, config.width, config.height, 1);
+    let multisampled_texture = (sample_count > 1).then(|| post::create_hdr_texture(device, config.width, config.height, sample_count));
+
+    (depth_texture, scene_texture, multisampled_texture)
+}
+
 pub struct EngineData {
     pub surface_engine : surface_engine::SurfaceEngine,
     device : wgpu::Device,
@@ -25,7 +62,15 @@ pub struct EngineData {
     pub clock : TimeUtils,
     mouse_position : (f32, f32),
     keys_pressed : Vec<winit::event::VirtualKeyCode>,
+    // Keys pressed since the last frame, for the hotkeys handled once per frame
+    new_keys : Vec<winit::event::VirtualKeyCode>,
     pub depth_texture : wgpu::TextureView,
+    scene_texture : wgpu::TextureView,
+    multisampled_texture : Option<wgpu::TextureView>,
+    capture_texture : wgpu::Texture,
+    sample_count : u32,
+    pub capture : FrameCapture,
+    pub post : PostProcessor,
 
 }
 
@@ -40,12 +85,9 @@ impl EngineData {
         let clock = TimeUtils::new();
 
         let config = surface_engine.get_surface_desc();     
-        let depth_texture = texture_builder::TextureBuilder::new("", texture_builder::TextureType::TextureDepth)
-            .set_dimensions(2)
-            .set_extent(config.width, config.height, 1)
-            .set_format(wgpu::TextureFormat::Depth32Float)
-            .set_usage(wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING)
-            .build(&device, &queue);
+        let (depth_texture, scene_texture, multisampled_texture) = create_render_targets(&device, &queue, &config, 1);
+        let post = PostProcessor::new(&device, &queue, config.format);
+        let capture_texture = capture::create_capture_texture(&device, config.format, config.width, config.height);
 
         Self {
             surface_engine,
@@ -55,7 +97,14 @@ impl EngineData {
             clock,
             mouse_position : (0.0, 0.0),
             keys_pressed : Vec::new(),
+            new_keys : Vec::new(),
             depth_texture,
+            scene_texture,
+            multisampled_texture,
+            capture_texture,
+            sample_count : 1,
+            capture : FrameCapture::new(),
+            post,
         }
     }
 
@@ -73,7 +122,6 @@ impl EngineData {
         &self.device
     }
 
-    #[allow(dead_code)]
     pub fn get_queue(&self) -> &wgpu::Queue {
         &self.queue
     }
@@ -86,11 +134,19 @@ impl EngineData {
         self.keys_pressed.contains(&keycode)
     }
 
+    // Whether the UI takes the keyboard, as it does while a text field is being edited
+    pub fn ui_wants_keyboard(&self) -> bool {
+        self.imgui_engine.imgui_context.io().want_capture_keyboard
+    }
+
     pub fn get_keys_pressed(&self) -> &Vec<winit::event::VirtualKeyCode> {
         &self.keys_pressed
     }
 
     pub fn delta_time(&self) -> f32 {
+        if self.capture.is_recording() {
+            return self.capture.timestep;
+        }
         self.clock.frame_duration().as_secs_f32()
     }
 
@@ -101,14 +157,55 @@ impl EngineData {
     pub fn resize_surface(&mut self){
         self.surface_engine.update_surface(&self.device);
 
+        self.recreate_render_targets();
+    }
+
+    fn recreate_render_targets(&mut self){
         let config = self.surface_engine.get_surface_desc();     
-        let depth_texture = texture_builder::TextureBuilder::new("", texture_builder::TextureType::TextureDepth)
-            .set_dimensions(2)
-            .set_extent(config.width, config.height, 1)
-            .set_format(wgpu::TextureFormat::Depth32Float)
-            .set_usage(wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING)
-            .build(&self.device, &self.queue);
+        let (depth_texture, scene_texture, multisampled_texture) = create_render_targets(&self.device, &self.queue, &config, self.sample_count);
         self.depth_texture = depth_texture;
+        self.scene_texture = scene_texture;
+        self.multisampled_texture = multisampled_texture;
+        self.capture_texture = capture::create_capture_texture(&self.device, config.format, config.width, config.height);
+    }
+
+    // Sample counts usable with the HDR and depth formats. Counts other than 1 and 4 are
+    // only allowed with adapter specific format features.
+    pub fn supported_sample_counts(&self) -> Vec<u32> {
+        let adapter_specific = self.device.features().contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+        let color_flags = self.surface_engine.get_format_features(post::HDR_FORMAT).flags;
+        let depth_flags = self.surface_engine.get_format_features(DEPTH_FORMAT).flags;
+        let multisampled = color_flags.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE | wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE)
+            && depth_flags.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE);
+
+        SAMPLE_COUNTS.iter()
+            .copied()
+            .filter(|count| *count == 1 || (multisampled && (*count == 4 || adapter_specific)))
+            .collect()
+    }
+
+    pub fn get_sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    pub fn set_sample_count(&mut self, sample_count : u32){
+        assert!(self.supported_sample_counts().contains(&sample_count), "Unsupported sample count: {}", sample_count);
+        self.sample_count = sample_count;
+        self.recreate_render_targets();
+    }
+
+    // Target to render the scene into, and the HDR view it resolves to when multisampled
+    pub fn get_color_targets(&self) -> (&wgpu::TextureView, Option<&wgpu::TextureView>) {
+        match &self.multisampled_texture {
+            Some(multisampled_texture) => (multisampled_texture, Some(&self.scene_texture)),
+            None => (&self.scene_texture, None),
+        }
+    }
+
+    // Renders the cube faces around `position` through the post-processing chain
+    pub fn capture_cubemap(&mut self, voxel_engine : &mut VoxelEngine, light : &DirectionalLight, position : cgmath::Vector3<f32>) -> Vec<image::RgbaImage> {
+        let face_size = self.capture.panorama_face_size;
+        panorama::capture_cubemap(&self.device, &self.queue, voxel_engine, &mut self.post, light, position, face_size)
     }
 
     pub fn request_redraw(&self){
@@ -117,6 +214,7 @@ impl EngineData {
 
     pub fn update(&mut self){
         self.clock.update();
+        self.handle_hotkeys();
         self.imgui_engine.begin_update(&self.surface_engine.window, self.clock.frame_duration());
     }
 
@@ -137,6 +235,7 @@ impl EngineData {
                 if keycode == winit::event::VirtualKeyCode::F {
                     self.set_fullscreen(true);
                 }
+                self.new_keys.push(keycode);
             }
         } else if let Some(index) = self.keys_pressed.iter().position(|&k| k == keycode) {
             self.keys_pressed.remove(index);
@@ -147,12 +246,59 @@ impl EngineData {
         }
     }
 
+    // Capture hotkeys pressed since the last frame, unless they were typed into the UI
+    fn handle_hotkeys(&mut self){
+        let new_keys = std::mem::take(&mut self.new_keys);
+        if self.ui_wants_keyboard() {
+            return;
+        }
+        if new_keys.contains(&winit::event::VirtualKeyCode::P) {
+            self.capture.request_screenshot();
+        }
+        if new_keys.contains(&winit::event::VirtualKeyCode::R) {
+            self.capture.toggle_recording();
+        }
+    }
+
     pub fn end_frame(&mut self, mut encoder : wgpu::CommandEncoder){
-        self.imgui_engine.end_update(&self.device, &self.queue, self.surface_engine.get_view(), &mut encoder);
+        let config = self.surface_engine.get_surface_desc();
+        // Frames are read back from a copy drawn next to the one presented
+        let capture_view = self.capture_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let capturing = self.capture.wants_frame(false) || self.capture.wants_frame(true);
+        let outputs = if capturing { vec![self.surface_engine.get_view(), &capture_view] } else { vec![self.surface_engine.get_view()] };
+        self.post.render(&self.device, &self.queue, &mut encoder, &self.scene_texture, &outputs, (config.width, config.height));
+
+        if self.capture.wants_frame(false) {
+            // The scene has to reach the frame before it is read back, the overlay goes in a second submission
+            self.queue.submit(Some(encoder.finish()));
+            self.capture_frame();
+            encoder = self.get_encoder();
+        }
+
+        let outputs = if self.capture.wants_frame(true) { vec![self.surface_engine.get_view(), &capture_view] } else { vec![self.surface_engine.get_view()] };
+        self.imgui_engine.end_update(&self.device, &self.queue, &outputs, &mut encoder);
         self.queue.submit(Some(encoder.finish()));
+
+        if self.capture.wants_frame(true) {
+            self.capture_frame();
+        }
+
         self.surface_engine.end_frame();
     }
 
+    fn capture_frame(&mut self){
+        let config = self.surface_engine.get_surface_desc();
+        let image = capture::read_texture(
+            &self.device, 
+            &self.queue, 
+            &self.capture_texture, 
+            config.format, 
+            config.width, 
+            config.height,
+        );
+        self.capture.store_frame(image);
+    }
+
     fn set_fullscreen(&mut self, fullscreen : bool){
         if fullscreen {
             self.surface_engine.window.set_fullscreen(Some(Fullscreen::Borderless(None)));
@@ -49,10 +49,15 @@ impl SurfaceEngine {
         block_on(self.adapter.request_device(&wgpu::DeviceDescriptor {
             label: Some("Device Descriptor"),
             features: wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
-            limits: wgpu::Limits::default(),
+            // The voxel pipeline uses more bind groups than the default limit of four
+            limits: self.adapter.limits(),
         }, None)).unwrap()
     }
 
+    pub fn get_format_features(&self, format : wgpu::TextureFormat) -> wgpu::TextureFormatFeatures {
+        self.adapter.get_texture_format_features(format)
+    }
+
     pub fn get_window_size(&self) -> (u32, u32) {
         (self.size.width, self.size.height)
     }
@@ -0,0 +1,239 @@
+// Golden-image regression tests: fixed scenes are rendered on a software adapter and
+// compared against the references in tests/golden. Run with UPDATE_GOLDEN=1 to write
+// new references after an intended visual change. Failing comparisons leave the
+// rendered frame and a diff image in target/visual-diffs. Without an adapter the tests
+// fail, unless SKIP_VISUAL_TESTS=1 is set to skip them deliberately.
+
+use std::path::{Path, PathBuf};
+
+use cgmath::Vector3;
+use image::{Rgba, RgbaImage};
+
+use crate::engine::camera::fps_camera::FpsCamera;
+use crate::engine::data::QuadtreeNode;
+use crate::engine::headless_engine::HeadlessEngine;
+use crate::engine::post::HDR_FORMAT;
+use crate::engine::light::DirectionalLight;
+use crate::engine::light::clustered_lights::LocalLight;
+use crate::engine::light::shadow_map::ShadowSettings;
+use crate::engine::voxel_engine::{self, VoxelEngine};
+
+const WIDTH : u32 = 320;
+const HEIGHT : u32 = 240;
+
+// Per pixel colour distance (YIQ, as in pixelmatch) above which two pixels are considered different
+const PIXEL_THRESHOLD : f32 = 0.1;
+// Fraction of different pixels tolerated, to absorb rasterisation differences between drivers
+const MAX_DIFFERENT_PIXELS : f32 = 0.005;
+
+fn golden_path(name : &str) -> PathBuf {
+    Path::new("tests/golden").join(format!("{}.png", name))
+}
+
+fn diff_dir() -> PathBuf {
+    Path::new("target/visual-diffs").to_path_buf()
+}
+
+fn to_yiq(pixel : &Rgba<u8>) -> [f32; 3] {
+    let [r, g, b] = [pixel[0] as f32 / 255.0, pixel[1] as f32 / 255.0, pixel[2] as f32 / 255.0];
+    [
+        0.298_895_3 * r + 0.586_622_5 * g + 0.114_482_2 * b,
+        0.595_978 * r - 0.274_176_1 * g - 0.321_801_9 * b,
+        0.211_470_2 * r - 0.522_617_1 * g + 0.311_146_9 * b,
+    ]
+}
+
+// Weighted YIQ distance normalised to [0, 1]
+fn perceptual_distance(a : &Rgba<u8>, b : &Rgba<u8>) -> f32 {
+    let [ya, ia, qa] = to_yiq(a);
+    let [yb, ib, qb] = to_yiq(b);
+    let (dy, di, dq) = (ya - yb, ia - ib, qa - qb);
+    let delta = 0.5053 * dy * dy + 0.299 * di * di + 0.1957 * dq * dq;
+    (delta / 35215.0 * 255.0 * 255.0).sqrt()
+}
+
+// Returns the fraction of pixels that differ and an image highlighting them in red
+fn compare(expected : &RgbaImage, actual : &RgbaImage) -> (f32, RgbaImage) {
+    let mut diff = RgbaImage::new(actual.width(), actual.height());
+    let mut different = 0;
+
+    for (x, y, pixel) in actual.enumerate_pixels() {
+        let reference = expected.get_pixel(x, y);
+        if perceptual_distance(reference, pixel) > PIXEL_THRESHOLD {
+            different += 1;
+            diff.put_pixel(x, y, Rgba([255, 0, 0, 255]));
+        } else {
+            // Faded copy of the reference so the differences can be located
+            let luma = (to_yiq(reference)[0] * 255.0 * 0.3) as u8;
+            diff.put_pixel(x, y, Rgba([luma, luma, luma, 255]));
+        }
+    }
+
+    (different as f32 / (actual.width() * actual.height()) as f32, diff)
+}
+
+fn assert_matches_golden(name : &str, actual : &RgbaImage) {
+    let path = golden_path(name);
+
+    if std::env::var("UPDATE_GOLDEN").is_ok() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        actual.save(&path).unwrap();
+        return;
+    }
+
+    let expected = image::open(&path)
+        .unwrap_or_else(|_| panic!("Missing reference {:?}, run with UPDATE_GOLDEN=1 to create it", path))
+        .to_rgba8();
+
+    assert_eq!(expected.dimensions(), actual.dimensions(), "{}: reference has a different size", name);
+
+    let (different, diff) = compare(&expected, actual);
+    if different > MAX_DIFFERENT_PIXELS {
+        std::fs::create_dir_all(diff_dir()).unwrap();
+        actual.save(diff_dir().join(format!("{}-actual.png", name))).unwrap();
+        diff.save(diff_dir().join(format!("{}-diff.png", name))).unwrap();
+        panic!(
+            "{}: {:.2}% of the pixels differ from {:?}, see {:?}",
+            name, different * 100.0, path, diff_dir()
+        );
+    }
+}
+
+fn render_scene(world : QuadtreeNode<u32>, camera_position : Vector3<f32>, yaw : f32, pitch : f32) -> Option<RgbaImage> {
+    render_scene_with(world, camera_position, yaw, pitch, |_, _| {})
+}
+
+// Same as render_scene, `setup` changes the lights before rendering
+fn render_scene_with<F>(world : QuadtreeNode<u32>, camera_position : Vector3<f32>, yaw : f32, pitch : f32, setup : F) -> Option<RgbaImage>
+    where F : FnOnce(&mut VoxelEngine, &mut DirectionalLight)
+{
+    if std::env::var("SKIP_VISUAL_TESTS").is_ok() {
+        eprintln!("SKIP_VISUAL_TESTS is set, skipping visual test");
+        return None;
+    }
+    let mut engine = HeadlessEngine::try_new(WIDTH, HEIGHT)
+        .expect("No adapter available for the visual tests, set SKIP_VISUAL_TESTS=1 to skip them");
+
+    let camera = FpsCamera::with_pose(camera_position, yaw, pitch, engine.aspect_ratio());
+    let mut light = DirectionalLight::new();
+
+    let mut voxel_engine = VoxelEngine::with_world(engine.get_device(), engine.get_queue(), &HDR_FORMAT, &camera, &light, world);
+    setup(&mut voxel_engine, &mut light);
+    Some(engine.render(&mut voxel_engine, &camera, &light))
+}
+
+// A row of pillars of increasing height on a flat floor, the last one emissive
+fn pillars_world() -> QuadtreeNode<u32> {
+    let mut world = QuadtreeNode::new(32);
+    for x in 0..32 {
+        for z in 0..32 {
+            world.insert_cell(x, 0, z, 3);
+        }
+    }
+    for i in 0..8 {
+        let material = if i == 7 { 9 } else { 6 };
+        for y in 1..=(i + 1) * 2 {
+            world.insert_cell(4 + i * 3, y, 12, material);
+            world.insert_cell(4 + i * 3, y, 13, material);
+        }
+    }
+    world
+}
+
+#[test]
+fn terrain_matches_golden() {
+    let world = voxel_engine::generate_world(64, 42);
+    if let Some(image) = render_scene(world, Vector3::new(-10.0, 45.0, -10.0), 45.0, -30.0) {
+        assert_matches_golden("terrain", &image);
+    }
+}
+
+#[test]
+fn pillars_match_golden() {
+    if let Some(image) = render_scene(pillars_world(), Vector3::new(16.0, 14.0, -14.0), 90.0, -20.0) {
+        assert_matches_golden("pillars", &image);
+    }
+}
+
+#[test]
+fn local_lights_match_golden() {
+    // A dim sun so that a torch between the pillars and a lamp above the floor stand out
+    let setup = |voxel_engine : &mut VoxelEngine, light : &mut DirectionalLight| {
+        light.color = Vector3::new(0.05, 0.05, 0.08);
+        let lights = voxel_engine.get_local_lights();
+        lights.push(LocalLight::point(Vector3::new(7.0, 2.0, 7.5), Vector3::new(1.0, 0.5, 0.2), 20.0, 10.0));
+        lights.push(LocalLight::spot(Vector3::new(14.0, 8.0, 4.0), Vector3::new(0.0, -1.0, 0.2), Vector3::new(0.3, 0.6, 1.0), 60.0, 20.0));
+    };
+    if let Some(image) = render_scene_with(pillars_world(), Vector3::new(16.0, 14.0, -14.0), 90.0, -20.0, setup) {
+        assert_matches_golden("local_lights", &image);
+    }
+}
+
+#[test]
+fn underwater_matches_golden() {
+    // The water rises above the camera, which sees the pillars through it
+    let setup = |voxel_engine : &mut VoxelEngine, _ : &mut DirectionalLight| {
+        voxel_engine.get_fog_settings().water_level = 20.0;
+    };
+    if let Some(image) = render_scene_with(pillars_world(), Vector3::new(16.0, 14.0, -14.0), 90.0, -20.0, setup) {
+        assert_matches_golden("underwater", &image);
+    }
+}
+
+// Colour of the middle of a white floor seen from straight above at a view distance of `depth`,
+// tinted by the shadow cascade it falls in
+fn cascade_tint_at(depth : f32, shadows : ShadowSettings) -> Option<Rgba<u8>> {
+    let mut world = QuadtreeNode::new(256);
+    for x in 0..256 {
+        for z in 0..256 {
+            world.insert_cell(x, 0, z, 6);
+        }
+    }
+    // Voxel vertices have a w of 1.5, which scales the drawn world down by as much
+    let floor = 1.0 / 1.5;
+    let camera_position = Vector3::new(128.0 / 1.5, floor + depth, 128.0 / 1.5);
+    let setup = |_ : &mut VoxelEngine, light : &mut DirectionalLight| {
+        light.shadows = shadows;
+        light.shadows.debug_cascades = true;
+    };
+    render_scene_with(world, camera_position, 0.0, -89.9, setup).map(|image| *image.get_pixel(WIDTH / 2, HEIGHT / 2))
+}
+
+#[test]
+fn cascades_are_picked_by_view_distance() {
+    // Two even cascades, the first one ending halfway through the shadow distance
+    let mut shadows = ShadowSettings::new();
+    shadows.cascade_count = 2;
+    shadows.split_lambda = 0.0;
+    shadows.distance = 100.0;
+    let split = shadows.cascade_splits()[0];
+
+    // The first cascade is tinted red and the second one green
+    if let Some(tint) = cascade_tint_at(split * 0.8, shadows) {
+        assert!(tint[0] > tint[1], "floor at {} in front of the split at {} is tinted {:?}", split * 0.8, split, tint);
+    }
+    if let Some(tint) = cascade_tint_at(split * 1.2, shadows) {
+        assert!(tint[1] > tint[0], "floor at {} behind the split at {} is tinted {:?}", split * 1.2, split, tint);
+    }
+}
+
+#[test]
+fn identical_images_have_no_difference() {
+    let image = RgbaImage::from_pixel(4, 4, Rgba([120, 30, 200, 255]));
+    let (different, _) = compare(&image, &image);
+    assert_eq!(different, 0.0);
+}
+
+#[test]
+fn changed_pixels_are_detected() {
+    let expected = RgbaImage::from_pixel(10, 10, Rgba([0, 0, 0, 255]));
+    let mut actual = expected.clone();
+    actual.put_pixel(3, 3, Rgba([255, 255, 255, 255]));
+    actual.put_pixel(4, 4, Rgba([1, 1, 1, 255]));
+
+    let (different, diff) = compare(&expected, &actual);
+    assert_eq!(different, 0.01);
+    assert_eq!(*diff.get_pixel(3, 3), Rgba([255, 0, 0, 255]));
+    assert_ne!(*diff.get_pixel(4, 4), Rgba([255, 0, 0, 255]));
+}
+
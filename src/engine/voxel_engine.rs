@@ -1,10 +1,12 @@
+use std::collections::{HashMap, HashSet};
+
 use cgmath::{vec3, InnerSpace, Vector3};
 use noise::{Perlin, NoiseFn};
 
 use crate::engine::builders::pipeline_layout_builder::PipelineLayoutBuilder;
 use crate::engine::buffers::{uniform_buffer::{UniformBuffer, SetUniformBuffer}, storage_buffer::{StorageBuffer, SetStorageBuffer}};
 use super::buffers::traits::{AsStorageBuffer, AsUniformBuffer};
-use super::camera::fps_camera::FpsCamera;
+use super::camera::camera_view::CameraView;
 use super::models::instance::instance_data::InstanceData;
 use super::models::instance::{VertexData, VertexType};
 use super::models::instance::voxel_vertex::VoxelVertex;
@@ -12,10 +14,27 @@ use crate::engine::builders::pipeline_builder::PipelineBuilder;
 use crate::engine::builders;
 use crate::engine::models::rendering::DrawModel;
 use super::models::voxel_face_model::{VoxelFaceModel, VoxelFace};
-use crate::engine::data::QuadtreeNode; 
+use crate::engine::data::{Aabb, QuadtreeNode, OctreeStats}; 
+use crate::engine::data::chunk::{ChunkMap, CHUNK_SIZE};
 use crate::engine::materials::MATERIAL_PALETTE; 
+use crate::engine::materials::voxel_textures::VoxelTextures;
+use crate::engine::light::DirectionalLight;
+use crate::engine::light::shadow_map::ShadowMap;
+use crate::engine::light::ssao::{Ssao, SsaoSettings};
+use crate::engine::light::light_map::LightMap;
+use crate::engine::light::clustered_lights::{ClusteredLights, LocalLight};
+use crate::engine::light::atmosphere::{Atmosphere, AtmosphereSettings, Sun};
+use crate::engine::light::fog::FogSettings;
+use crate::engine::light::skybox::{Skybox, SkyboxSettings};
 
 const BACKGROUND_COLOR: [f32; 4] = [ 0.0, 0.0, 0.0, 1.0 ];
+const WORLD_SIZE : u32 = 1024; 
+const WORLD_HEIGHT : f32 = 200.0; 
+const WORLD_SCALE : f64 = 0.01; 
+// Relative height below which the surface of a column is lava
+const LAVA_LEVEL : f32 = 0.27;
+// Furthest voxel the camera can place or dig, in cells
+const EDIT_REACH : f32 = 64.0;
 
 const DIRECTION_VECTORS : [Vector3<f32>; 6] = [
     vec3(0.0, 1.0, 0.0), // TOP
@@ -26,15 +45,53 @@ const DIRECTION_VECTORS : [Vector3<f32>; 6] = [
     vec3(0.0, 0.0, -1.0), // FRONT
 ];
 
+// Same order as DIRECTION_VECTORS, each face pointing against its vector
+const VOXEL_FACES : [VoxelFace; 6] = [
+    VoxelFace::Bottom,
+    VoxelFace::Top,
+    VoxelFace::Left,
+    VoxelFace::Right,
+    VoxelFace::Front,
+    VoxelFace::Back,
+];
+
 pub struct VoxelEngine {
     uniform_buffers: Vec<UniformBuffer>,
     storage_buffers: Vec<StorageBuffer>,
     pipelines: Vec<wgpu::RenderPipeline>,
     voxel_models: Vec<VoxelFaceModel>,
+    world: QuadtreeNode<u32>,
+    // Occupancy and light of the world, kept to update them incrementally
+    chunks: ChunkMap<u32>,
+    light_map: LightMap,
+    faces: ChunkFaces,
+    world_stats: WorldStats,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+    shadow_map: ShadowMap,
+    ssao: Ssao,
+    textures: VoxelTextures,
+    local_lights: ClusteredLights,
+    atmosphere: Atmosphere,
+    skybox: Skybox,
+    // Clear colour behind the terrain
+    background_color: [f32; 4],
 }
 
-fn generate_terrain(quadtree: &mut QuadtreeNode, size: u32, max_height: f32, scale: f64) {
-    let perlin = Perlin::new(42); // Create a new Perlin noise generator
+pub struct WorldStats {
+    pub octree: OctreeStats,
+    // Size of the instance buffer uploaded for each face model
+    pub face_instance_bytes: Vec<(&'static str, u64)>,
+}
+
+impl WorldStats {
+    pub fn gpu_bytes(&self) -> u64 {
+        self.face_instance_bytes.iter().map(|(_, bytes)| bytes).sum()
+    }
+}
+
+fn generate_terrain(quadtree: &mut QuadtreeNode<u32>, size: u32, max_height: f32, scale: f64, seed: u32) {
+    let perlin = Perlin::new(seed); // Create a new Perlin noise generator
 
     for i in 1..=size {
         for z in 1..=size {
@@ -45,82 +102,545 @@ fn generate_terrain(quadtree: &mut QuadtreeNode, size: u32, max_height: f32, sca
             let noise_value = perlin.get([x as f64 * scale, z as f64 * scale]); // Get noise value
             let normalized_height = ((noise_value + 1.0) / 2.0) as f32 * max_height/5.0; // Normalize to range [0, max_height]
             
+            let top = normalized_height as i32 - 1;
             for j in 1..normalized_height as i32{
                 let pos = Vector3::new(x, j as f32, z); 
-                quadtree.insert_voxel(pos);    
+                let relative_height = j as f32 / (max_height / 5.0);
+                // The deepest pools are filled with lava instead of water
+                let material = if j == top && relative_height < LAVA_LEVEL { 8 } else { terrain_material(relative_height) };
+                quadtree.insert_voxel(pos, material);    
+            }
+        }
+    }
+}
+
+// Picks a palette index from the relative height of a voxel in the terrain column
+fn terrain_material(relative_height: f32) -> u32 {
+    match relative_height {
+        h if h < 0.35 => 1, // blue
+        h if h < 0.45 => 7, // yellow
+        h if h < 0.7 => 10, // grass
+        h if h < 0.8 => 11, // stone
+        _ => 6, // white
+    }
+}
+
+// Corner occlusion and light of a face, baked into its instance
+#[derive(Clone, Copy, PartialEq)]
+struct FaceShading {
+    occlusion: [u8; 4],
+    light: [u8; 2],
+}
+
+fn face_shading(chunks: &ChunkMap<u32>, light_map: &LightMap, bounds: &Aabb, face: VoxelFace) -> FaceShading {
+    let occlusion = face.vertices().map(|vertex| {
+        let corner = [vertex._pos[0].signum() as i32, vertex._pos[1].signum() as i32, vertex._pos[2].signum() as i32];
+        chunks.corner_light(bounds, face.normal(), corner)
+    });
+    let light = light_map.face_light(chunks, bounds, face.normal());
+    FaceShading {
+        occlusion,
+        light: light.map(|level| (level * 255.0) as u8),
+    }
+}
+
+// The four quarters of the box across the side facing along `normal`, each as deep as the box
+fn split_side(bounds: &Aabb, normal: [i32; 3]) -> [Aabb; 4] {
+    let center = bounds.get_center();
+    let across : Vec<usize> = (0..3).filter(|axis| normal[*axis] == 0).collect();
+    std::array::from_fn(|quarter| {
+        let mut part = *bounds;
+        for (bit, axis) in across.iter().enumerate() {
+            if quarter >> bit & 1 == 0 {
+                part.max[*axis] = center[*axis];
+            } else {
+                part.min[*axis] = center[*axis];
             }
         }
+        part
+    })
+}
+
+// Shading shared by the exposed unit faces of the side, if they all match and have no
+// gradient, so that the side can be drawn as a single quad without changing the result
+fn uniform_shading(chunks: &ChunkMap<u32>, light_map: &LightMap, bounds: &Aabb, face: VoxelFace) -> Option<FaceShading> {
+    let normal = face.normal();
+    let (min, max) = (bounds.min.map(|v| v as i32), bounds.max.map(|v| v as i32));
+    let mut to = max;
+    for axis in 0..3 {
+        if normal[axis] != 0 {
+            to[axis] = min[axis] + 1;
+        }
     }
+
+    let mut shared = None;
+    for z in min.z..to[2] {
+        for y in min.y..to[1] {
+            for x in min.x..to[0] {
+                let mut unit = Aabb {
+                    min: Vector3::new(x as f32, y as f32, z as f32),
+                    max: Vector3::new(x as f32 + 1.0, y as f32 + 1.0, z as f32 + 1.0),
+                };
+                for (axis, direction) in normal.iter().enumerate() {
+                    if *direction != 0 {
+                        unit.min[axis] = bounds.min[axis];
+                        unit.max[axis] = bounds.max[axis];
+                    }
+                }
+                if !chunks.is_face_exposed(&unit, normal) {
+                    continue;
+                }
+                let shading = face_shading(chunks, light_map, &unit, face);
+                if shading.occlusion.iter().any(|level| *level != shading.occlusion[0]) || shared.is_some_and(|shared| shared != shading) {
+                    return None;
+                }
+                shared = Some(shading);
+            }
+        }
+    }
+    shared
+}
+
+// Adds the visible part of the box side facing along the face normal, split into quarters until
+// each one is shaded evenly, or down to unit faces
+fn push_side_instances(
+    chunks: &ChunkMap<u32>,
+    light_map: &LightMap,
+    bounds: &Aabb,
+    face: VoxelFace,
+    material: u32,
+    instances: &mut (Vec<InstanceData>, Vec<InstanceData>),
+) {
+    let normal = face.normal();
+    if !chunks.is_face_exposed(bounds, normal) {
+        return;
+    }
+
+    let size = bounds.get_size();
+    let width = (0..3).filter(|axis| normal[*axis] == 0).map(|axis| size[axis]).fold(f32::MAX, f32::min);
+    let shading = if width <= 1.0 {
+        face_shading(chunks, light_map, bounds, face)
+    } else if let Some(shading) = uniform_shading(chunks, light_map, bounds, face) {
+        shading
+    } else {
+        for part in split_side(bounds, normal) {
+            push_side_instances(chunks, light_map, &part, face, material, instances);
+        }
+        return;
+    };
+
+    let instance = InstanceData {
+        position: bounds.get_center().extend(1.0).into(), 
+        size: size.extend(1.0).into(), 
+        material,
+        occlusion: shading.occlusion.map(|level| level * 85),
+        light: [shading.light[0], shading.light[1], 0, 0],
+    };
+    // Splitting along the darker diagonal keeps the shading of a single occluded corner symmetric
+    let ([a, b], [c, d]) = face.diagonals();
+    let light = shading.occlusion;
+    if light[a] + light[b] > light[c] + light[d] {
+        instances.1.push(instance);
+    } else {
+        instances.0.push(instance);
+    }
+}
+
+// Instances drawn with the default quad and those drawn with the flipped one
+type FaceInstances = (Vec<InstanceData>, Vec<InstanceData>);
+
+fn chunk_bounds([x, y, z]: [i32; 3]) -> Aabb {
+    let min = Vector3::new(x, y, z).map(|k| (k * CHUNK_SIZE) as f32);
+    Aabb {
+        min,
+        max: min + Vector3::new(1.0, 1.0, 1.0) * CHUNK_SIZE as f32,
+    }
+}
+
+// Visible sides of the leaves grouped by the chunk their cells are in, for each face in the order
+// of VOXEL_FACES. Sides are merged wherever the shading of their unit faces allows it, but never
+// across chunks, so that an edit only collects the chunks it changed again.
+struct ChunkFaces {
+    chunks: HashMap<[i32; 3], [FaceInstances; 6]>,
+}
+
+impl ChunkFaces {
+    fn new(world: &QuadtreeNode<u32>, chunks: &ChunkMap<u32>, light_map: &LightMap) -> Self {
+        let mut faces = Self { chunks: HashMap::new() };
+        world.visit(|bounds, material| faces.add_leaf(chunks, light_map, bounds, *material, None));
+        faces
+    }
+
+    // Collects the faces of the given chunks again
+    fn update(&mut self, world: &QuadtreeNode<u32>, chunks: &ChunkMap<u32>, light_map: &LightMap, keys: &HashSet<[i32; 3]>) {
+        for key in keys {
+            self.chunks.remove(key);
+            world.visit_region(&chunk_bounds(*key), |bounds, material| self.add_leaf(chunks, light_map, bounds, *material, Some(*key)));
+        }
+    }
+
+    // Adds the sides of a leaf to the chunks they are in, or only to chunk `only`
+    fn add_leaf(&mut self, chunks: &ChunkMap<u32>, light_map: &LightMap, bounds: &Aabb, material: u32, only: Option<[i32; 3]>) {
+        let (min, max) = (bounds.min.map(|v| v as i32), bounds.max.map(|v| v as i32));
+        for (index, face) in VOXEL_FACES.iter().enumerate() {
+            let normal = face.normal();
+            // Chunk range covered by the layer of cells on the side
+            let mut first = min.map(|v| v.div_euclid(CHUNK_SIZE));
+            let mut last = max.map(|v| (v - 1).div_euclid(CHUNK_SIZE));
+            for axis in 0..3 {
+                if normal[axis] > 0 {
+                    first[axis] = last[axis];
+                } else if normal[axis] < 0 {
+                    last[axis] = first[axis];
+                }
+            }
+
+            for kz in first.z..=last.z {
+                for ky in first.y..=last.y {
+                    for kx in first.x..=last.x {
+                        let key = [kx, ky, kz];
+                        if only.is_some_and(|only| only != key) {
+                            continue;
+                        }
+                        // The part of the side in the chunk, as deep as the leaf
+                        let region = chunk_bounds(key);
+                        let mut part = *bounds;
+                        for axis in (0..3).filter(|axis| normal[*axis] == 0) {
+                            part.min[axis] = part.min[axis].max(region.min[axis]);
+                            part.max[axis] = part.max[axis].min(region.max[axis]);
+                        }
+                        let instances = &mut self.chunks.entry(key).or_default()[index];
+                        push_side_instances(chunks, light_map, &part, *face, material, instances);
+                    }
+                }
+            }
+        }
+    }
+
+    // All instances of a face, in chunk order
+    fn instances(&self, index: usize) -> FaceInstances {
+        let mut keys : Vec<&[i32; 3]> = self.chunks.keys().collect();
+        keys.sort();
+        let mut instances = FaceInstances::default();
+        for key in keys {
+            let (default, flipped) = &self.chunks[key][index];
+            instances.0.extend_from_slice(default);
+            instances.1.extend_from_slice(flipped);
+        }
+        instances
+    }
+}
+
+pub fn generate_world(size: u32, seed: u32) -> QuadtreeNode<u32> {
+    let mut world : QuadtreeNode<u32> = QuadtreeNode::new(size); 
+    generate_terrain(&mut world, size, WORLD_HEIGHT, WORLD_SCALE, seed);
+    world
+}
+
+fn material_light_level(material: &u32) -> u8 {
+    MATERIAL_PALETTE[*material as usize].light_level()
+}
+
+// Fills or empties one cell of the world, relights the cells its light reaches and collects the
+// faces of the chunks whose geometry or light changed again. Returns the keys of those chunks,
+// none when the cell is outside the world.
+fn edit_cell(
+    world: &mut QuadtreeNode<u32>,
+    chunks: &mut ChunkMap<u32>,
+    light_map: &mut LightMap,
+    faces: &mut ChunkFaces,
+    cell: [i32; 3],
+    material: Option<u32>,
+) -> HashSet<[i32; 3]> {
+    let side = world.cells_per_side() as i32;
+    if cell.iter().any(|c| *c < 0 || *c >= side) {
+        return HashSet::new();
+    }
+
+    let [x, y, z] = cell;
+    match material {
+        Some(material) => {
+            world.insert_cell(x as u32, y as u32, z as u32, material);
+            chunks.insert_cell(x, y, z, &material);
+            light_map.add_voxel(chunks, cell, material_light_level(&material));
+        }
+        None => {
+            world.remove_cell(x as u32, y as u32, z as u32);
+            chunks.remove_cell(x, y, z);
+            light_map.remove_voxel(chunks, cell);
+        }
+    }
+    let changed = light_map.take_changed_chunks();
+    faces.update(world, chunks, light_map, &changed);
+    changed
+}
+
+// First solid cell crossed by a ray within `max_distance`, and the cell the ray crossed just
+// before it, if it did not start inside the solid one
+fn raycast_cells(chunks: &ChunkMap<u32>, origin: Vector3<f32>, direction: Vector3<f32>, max_distance: f32) -> Option<([i32; 3], Option<[i32; 3]>)> {
+    let direction = direction.normalize();
+    let mut cell = [origin.x.floor() as i32, origin.y.floor() as i32, origin.z.floor() as i32];
+    // Distance along the ray to the next cell border on each axis, and between two borders
+    let mut border = [f32::INFINITY; 3];
+    let mut spacing = [f32::INFINITY; 3];
+    for axis in 0..3 {
+        if direction[axis] != 0.0 {
+            let next = if direction[axis] > 0.0 { cell[axis] as f32 + 1.0 } else { cell[axis] as f32 };
+            border[axis] = (next - origin[axis]) / direction[axis];
+            spacing[axis] = 1.0 / direction[axis].abs();
+        }
+    }
+
+    let mut previous = None;
+    loop {
+        if chunks.is_cell_solid(cell[0], cell[1], cell[2]) {
+            return Some((cell, previous));
+        }
+        let axis = (0..3).fold(0, |closest, axis| if border[axis] < border[closest] { axis } else { closest });
+        if border[axis] > max_distance {
+            return None;
+        }
+        previous = Some(cell);
+        cell[axis] += if direction[axis] > 0.0 { 1 } else { -1 };
+        border[axis] += spacing[axis];
+    }
+}
+
+fn light_world(world: &QuadtreeNode<u32>) -> (ChunkMap<u32>, LightMap) {
+    let chunks = ChunkMap::from_octree(world);
+    let light_map = LightMap::new(world, &chunks, material_light_level);
+    (chunks, light_map)
+}
+
+fn build_models(device: &wgpu::Device, world: &QuadtreeNode<u32>, faces: &ChunkFaces) -> (Vec<VoxelFaceModel>, WorldStats) {
+    let voxel_models : Vec<VoxelFaceModel> = VOXEL_FACES.iter()
+        .enumerate()
+        .map(|(index, face)| {
+            let (instances, flipped_instances) = faces.instances(index);
+            VoxelFaceModel::new(device, *face, instances, flipped_instances)
+        })
+        .collect();
+
+    let stats = WorldStats {
+        octree: world.stats(),
+        face_instance_bytes: VOXEL_FACES.iter()
+            .zip(voxel_models.iter())
+            .map(|(face, model)| (face.name(), model.instance_buffer.size()))
+            .collect(),
+    };
+
+    (voxel_models, stats)
 }
 
 impl VoxelEngine {
     pub fn init(
         device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
+        queue: &wgpu::Queue,
+        format: &wgpu::TextureFormat,
         camera: &dyn AsUniformBuffer,
-        light : &dyn AsUniformBuffer
+        light : &DirectionalLight
+    ) -> Self {
+        VoxelEngine::with_world(device, queue, format, camera, light, generate_world(WORLD_SIZE, 42))
+    }
+
+    pub fn with_world(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: &wgpu::TextureFormat,
+        camera: &dyn AsUniformBuffer,
+        light : &DirectionalLight,
+        world: QuadtreeNode<u32>,
     ) -> Self {
         let camera_uniform = camera.as_uniform_buffer(device);
         let light_uniform = light.as_uniform_buffer(device); 
         let material_buffers : StorageBuffer = MATERIAL_PALETTE.as_storage_buffer(device);
+        let shadow_map = ShadowMap::new(device, queue, &light.shadows);
+        let ssao = Ssao::new(device, queue);
+        let textures = VoxelTextures::new(device, queue);
+        let local_lights = ClusteredLights::new(device);
+        let atmosphere = Atmosphere::new(device, queue, format);
+        let skybox = Skybox::new(device, format);
 
-        let pipeline_layout_builder = PipelineLayoutBuilder::new()
-            .add_bind_group_layout(&camera_uniform.bind_group_layout)
-            .add_bind_group_layout(&light_uniform.bind_group_layout)
-            .add_bind_group_layout(&material_buffers.bind_group_layout); 
+        let (chunks, light_map) = light_world(&world);
+        let faces = ChunkFaces::new(&world, &chunks, &light_map);
+        let (voxel_models, world_stats) = build_models(device, &world, &faces);
 
-        let size = 1024; 
-        let height = 200; 
-        let mut quadtree : QuadtreeNode = QuadtreeNode::new(size); 
-        generate_terrain(&mut quadtree, size, height as f32, 0.01);
-        let voxels : Vec<InstanceData> = quadtree.get_data(); 
-
-        
-        let model1 = VoxelFaceModel::new(device, VoxelFace::Bottom, voxels.clone());
-        let model2 = VoxelFaceModel::new(device, VoxelFace::Top, voxels.clone());
-        let model3 = VoxelFaceModel::new(device, VoxelFace::Left, voxels.clone());
-        let model4 = VoxelFaceModel::new(device, VoxelFace::Right, voxels.clone());
-        let model5 = VoxelFaceModel::new(device, VoxelFace::Front, voxels.clone());
-        let model6 = VoxelFaceModel::new(device, VoxelFace::Back, voxels.clone());
+        let mut engine = VoxelEngine {
+            pipelines: Vec::new(),
+            uniform_buffers: vec![camera_uniform, light_uniform],
+            storage_buffers: vec![material_buffers],
+            voxel_models,
+            world,
+            chunks,
+            light_map,
+            faces,
+            world_stats,
+            format: *format,
+            sample_count: 1,
+            shadow_map,
+            ssao,
+            textures,
+            local_lights,
+            atmosphere,
+            skybox,
+            background_color: BACKGROUND_COLOR,
+        };
+        engine.pipelines = vec![engine.create_instanced_pipeline(device)];
+        engine
+    }
 
-        let pipeline_layout = pipeline_layout_builder.build(device);
+    fn create_instanced_pipeline(&self, device: &wgpu::Device) -> wgpu::RenderPipeline {
+        let pipeline_layout = PipelineLayoutBuilder::new()
+            .add_bind_group_layout(&self.uniform_buffers[0].bind_group_layout)
+            .add_bind_group_layout(&self.uniform_buffers[1].bind_group_layout)
+            .add_bind_group_layout(&self.storage_buffers[0].bind_group_layout)
+            .add_bind_group_layout(&self.shadow_map.bind_group_layout)
+            .add_bind_group_layout(&self.ssao.bind_group_layout)
+            .add_bind_group_layout(&self.textures.bind_group_layout)
+            .add_bind_group_layout(&self.local_lights.bind_group_layout)
+            .add_bind_group_layout(&self.atmosphere.bind_group_layout)
+            .build(device); 
 
-        let instanced_pipeline = PipelineBuilder::new()
+        PipelineBuilder::new()
             .add_vertex_buffer_layout(VoxelVertex::desc())
             .add_vertex_buffer_layout(InstanceData::desc())
             .set_primitive_state(Some(wgpu::Face::Back))
             .set_wireframe_mode(false)  
+            .set_sample_count(self.sample_count)
             .set_vertex_shader(device, "./shaders/c_main.wgsl", VertexType::InstancedVertex)
-            .set_fragment_shader(device, "./shaders/c_main.wgsl", &config.format)
+            .set_fragment_shader(device, "./shaders/c_main.wgsl", &self.format)
             .set_pipeline_layout(pipeline_layout)
-            .build(device);
+            .build(device)
+    }
 
-        VoxelEngine {
-            pipelines: vec![instanced_pipeline],
-            uniform_buffers: vec![camera_uniform, light_uniform],
-            storage_buffers: vec![material_buffers],
-            voxel_models: vec![model1, model2, model3, model4, model5, model6],
+    // Rebuilds the pipelines for targets with a different number of samples per pixel
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        if sample_count != self.sample_count {
+            self.sample_count = sample_count;
+            self.pipelines = vec![self.create_instanced_pipeline(device)];
+            self.atmosphere.set_sample_count(device, sample_count);
+            self.skybox.set_sample_count(device, sample_count);
+        }
+    }
+
+    pub fn get_sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    pub fn regenerate(&mut self, device: &wgpu::Device, seed: u32) {
+        self.world = generate_world(WORLD_SIZE, seed);
+        self.refresh_world(device);
+    }
+
+    // Relights the whole world and re-uploads the face models and statistics, to be called after
+    // every change made directly to the world
+    pub fn refresh_world(&mut self, device: &wgpu::Device) {
+        (self.chunks, self.light_map) = light_world(&self.world);
+        self.faces = ChunkFaces::new(&self.world, &self.chunks, &self.light_map);
+        self.upload_models(device);
+    }
+
+    fn upload_models(&mut self, device: &wgpu::Device) {
+        let (voxel_models, world_stats) = build_models(device, &self.world, &self.faces);
+        self.voxel_models = voxel_models;
+        self.world_stats = world_stats;
+    }
+
+    // Fills or empties one cell and re-uploads the face models
+    pub fn set_voxel(&mut self, device: &wgpu::Device, cell: [i32; 3], material: Option<u32>) {
+        let changed = edit_cell(&mut self.world, &mut self.chunks, &mut self.light_map, &mut self.faces, cell, material);
+        if !changed.is_empty() {
+            self.upload_models(device);
         }
     }
 
+    // Fills the empty cell in front of the first voxel within reach along a ray, or empties that
+    // voxel when `material` is None
+    pub fn edit_target(&mut self, device: &wgpu::Device, origin: Vector3<f32>, direction: Vector3<f32>, material: Option<u32>) {
+        match (raycast_cells(&self.chunks, origin, direction, EDIT_REACH), material) {
+            (Some((_, Some(empty))), Some(material)) => self.set_voxel(device, empty, Some(material)),
+            (Some((solid, _)), None) => self.set_voxel(device, solid, None),
+            _ => (),
+        }
+    }
+
+    pub fn get_material_count(&self) -> u32 {
+        MATERIAL_PALETTE.len() as u32
+    }
+
+    pub fn get_world_stats(&self) -> &WorldStats {
+        &self.world_stats
+    }
+
+    pub fn get_ssao_settings(&mut self) -> &mut SsaoSettings {
+        &mut self.ssao.settings
+    }
+
+    // Point and spot lights of the scene, uploaded on every update
+    pub fn get_local_lights(&mut self) -> &mut Vec<LocalLight> {
+        &mut self.local_lights.lights
+    }
+
+    pub fn get_atmosphere_settings(&mut self) -> &mut AtmosphereSettings {
+        &mut self.atmosphere.settings
+    }
+
+    pub fn get_fog_settings(&mut self) -> &mut FogSettings {
+        &mut self.atmosphere.fog
+    }
+
+    pub fn get_skybox_settings(&mut self) -> &mut SkyboxSettings {
+        &mut self.skybox.settings
+    }
+
+    pub fn set_background_color(&mut self, color: Vector3<f32>) {
+        self.background_color = color.extend(1.0).into();
+    }
+
+    // Sun of the sky, lit by the directional light when None
+    pub fn set_sun(&mut self, sun: Option<Sun>) {
+        self.atmosphere.sun = sun;
+    }
+
     pub fn update(
         &mut self, 
         device: &wgpu::Device, 
-        camera: &dyn AsUniformBuffer, 
-        light : &dyn AsUniformBuffer
+        queue: &wgpu::Queue,
+        camera: &CameraView, 
+        light : &DirectionalLight,
+        size : (u32, u32),
     ) {
         self.uniform_buffers[0] = camera.as_uniform_buffer(device);
         self.uniform_buffers[1] = light.as_uniform_buffer(device);
+        self.shadow_map.update(device, queue, camera, light);
+        self.ssao.update(device, queue, camera, size);
+        self.local_lights.update(queue, camera, size);
+        self.atmosphere.update(queue, camera, light);
+        self.skybox.update(device, queue, camera);
     }
 
     pub fn render(
         &mut self, 
         view : &wgpu::TextureView,
+        resolve_target : Option<&wgpu::TextureView>,
         depth_texture: &wgpu::TextureView,
         encoder: &mut wgpu::CommandEncoder, 
-        camera: &FpsCamera,
+        camera: &CameraView,
     ) {
-        let mut rpass = builders::pipeline_builder::create_render_pass(view, depth_texture, encoder, BACKGROUND_COLOR);
+        // Face groups pointing away from the camera cannot be seen
+        let camera_dir = camera.forward; 
+        let visible_models : Vec<&VoxelFaceModel> = DIRECTION_VECTORS.iter()
+            .zip(self.voxel_models.iter())
+            .filter(|(direction, _)| direction.dot(camera_dir) >= -0.5)
+            .map(|(_, model)| model)
+            .collect();
+
+        self.shadow_map.render(encoder, &self.voxel_models);
+        self.ssao.render(encoder, &visible_models);
+        self.local_lights.assign(encoder);
+        self.atmosphere.render_sky_view(encoder);
+
+        let mut rpass = builders::pipeline_builder::create_render_pass(view, resolve_target, depth_texture, encoder, self.background_color);
 
         let mut bind_index_offset = 0;
         rpass.set_pipeline(&self.pipelines[0]);
@@ -132,13 +652,184 @@ impl VoxelEngine {
             rpass.set_storage_buffer((bind_index_offset + i) as u32, &self.storage_buffers[i]);
         }
         bind_index_offset += self.storage_buffers.len();
+        rpass.set_bind_group(bind_index_offset as u32, &self.shadow_map.bind_group, &[]);
+        bind_index_offset += 1;
+        rpass.set_bind_group(bind_index_offset as u32, &self.ssao.bind_group, &[]);
+        bind_index_offset += 1;
+        rpass.set_bind_group(bind_index_offset as u32, &self.textures.bind_group, &[]);
+        bind_index_offset += 1;
+        rpass.set_bind_group(bind_index_offset as u32, &self.local_lights.bind_group, &[]);
+        bind_index_offset += 1;
+        rpass.set_bind_group(bind_index_offset as u32, &self.atmosphere.bind_group, &[]);
+        bind_index_offset += 1;
 
-        let camera_dir = camera.forward; 
-        for (i, direction) in DIRECTION_VECTORS.iter().enumerate() {
-            if direction.dot(camera_dir) >= -0.5 {
-                rpass.draw_voxel_instanced(bind_index_offset as u32, &self.voxel_models[i]);
+        for model in visible_models {
+            rpass.draw_voxel_instanced(bind_index_offset as u32, model);
+        }
+
+        // After the terrain, so that the depth test skips the covered pixels
+        if self.skybox.is_active() {
+            self.skybox.render(&mut rpass);
+        } else {
+            self.atmosphere.render_sky(&mut rpass);
+        }
+    }
+}
+ 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An 8^3 block, collapsed into a single leaf, with a one voxel wall across the middle of its top
+    fn creased_block() -> QuadtreeNode<u32> {
+        let mut world = QuadtreeNode::new(16);
+        for z in 0..8 {
+            for y in 0..8 {
+                for x in 0..8 {
+                    world.insert_cell(x, y, z, 3);
+                }
             }
         }
+        for z in 0..8 {
+            world.insert_cell(4, 8, z, 3);
+        }
+        world
+    }
+
+    fn top_instances(world : &QuadtreeNode<u32>) -> Vec<InstanceData> {
+        let (chunks, light_map) = light_world(world);
+        let (instances, flipped_instances) = ChunkFaces::new(world, &chunks, &light_map).instances(1);
+        instances.into_iter().chain(flipped_instances).filter(|instance| instance.position[1] < 8.0).collect()
+    }
+
+    #[test]
+    fn large_faces_are_split_where_their_occlusion_changes() {
+        let instances = top_instances(&creased_block());
+
+        // The top of the block is drawn once, without the cells under the wall
+        let area : f32 = instances.iter().map(|instance| instance.size[0] * instance.size[2]).sum();
+        assert_eq!(area, 56.0);
+
+        // Faces next to the wall are darkened on the wall side only, the rest stay open
+        for instance in &instances {
+            let [x, z] = [instance.position[0], instance.position[2]];
+            let size = instance.size[0];
+            let touches_wall = x + size / 2.0 == 4.0 || x - size / 2.0 == 5.0;
+            let occluded = instance.occlusion.iter().filter(|level| **level < 255).count();
+            if touches_wall {
+                assert_eq!(size, 1.0, "face at ({}, {}) touching the wall is not split", x, z);
+                assert_eq!(occluded, 2, "face at ({}, {})", x, z);
+            } else {
+                assert_eq!(occluded, 0, "face at ({}, {})", x, z);
+            }
+        }
+        // Away from the wall the side stays merged
+        assert!(instances.iter().any(|instance| instance.size[0] > 1.0));
+    }
+
+    #[test]
+    fn evenly_shaded_faces_stay_merged() {
+        let mut world = creased_block();
+        for z in 0..8 {
+            world.remove_cell(4, 8, z);
+        }
+        let instances = top_instances(&world);
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].size, [8.0, 8.0, 8.0, 1.0]);
+    }
+
+    // Cell, face index, material, occlusion and light of a visible unit face
+    type UnitFace = ([i32; 3], usize, u32, [u8; 4], [u8; 4]);
+
+    // Every visible unit face, however the sides were merged
+    fn unit_faces(faces : &ChunkFaces) -> Vec<UnitFace> {
+        let mut unit_faces = Vec::new();
+        for (index, face) in VOXEL_FACES.iter().enumerate() {
+            let normal = face.normal();
+            let (instances, flipped_instances) = faces.instances(index);
+            for instance in instances.iter().chain(&flipped_instances) {
+                let mut min = [0; 3];
+                let mut max = [0; 3];
+                for axis in 0..3 {
+                    min[axis] = (instance.position[axis] - instance.size[axis] / 2.0) as i32;
+                    max[axis] = (instance.position[axis] + instance.size[axis] / 2.0) as i32;
+                    if normal[axis] > 0 {
+                        min[axis] = max[axis] - 1;
+                    } else if normal[axis] < 0 {
+                        max[axis] = min[axis] + 1;
+                    }
+                }
+                for z in min[2]..max[2] {
+                    for y in min[1]..max[1] {
+                        for x in min[0]..max[0] {
+                            unit_faces.push(([x, y, z], index, instance.material, instance.occlusion, instance.light));
+                        }
+                    }
+                }
+            }
+        }
+        unit_faces.sort();
+        unit_faces
+    }
+
+    #[test]
+    fn edits_only_collect_the_faces_of_the_chunks_they_change() {
+        // Ground across eight chunks
+        let mut world = QuadtreeNode::new(64);
+        for z in 0..64 {
+            for y in 0..24 {
+                for x in 0..64 {
+                    world.insert_cell(x, y, z, 3);
+                }
+            }
+        }
+        let (mut chunks, mut light_map) = light_world(&world);
+        let mut faces = ChunkFaces::new(&world, &chunks, &light_map);
+
+        // Edits next to the chunk borders, filling and digging cells and placing a block of lava
+        let edits : [([i32; 3], Option<u32>); 6] = [
+            ([31, 24, 31], Some(3)),
+            ([32, 23, 32], None),
+            ([32, 22, 32], None),
+            ([31, 25, 32], Some(8)),
+            ([40, 24, 8], Some(5)),
+            ([31, 24, 31], None),
+        ];
+        for (cell, material) in edits {
+            let changed = edit_cell(&mut world, &mut chunks, &mut light_map, &mut faces, cell, material);
+            assert!(!changed.is_empty() && changed.len() < 27);
+
+            let (fresh_chunks, fresh_light_map) = light_world(&world);
+            let fresh_faces = ChunkFaces::new(&world, &fresh_chunks, &fresh_light_map);
+            assert!(unit_faces(&faces) == unit_faces(&fresh_faces), "faces differ after editing {:?}", cell);
+        }
+    }
+
+    #[test]
+    fn edits_outside_the_world_change_nothing() {
+        let mut world = creased_block();
+        let (mut chunks, mut light_map) = light_world(&world);
+        let mut faces = ChunkFaces::new(&world, &chunks, &light_map);
+        let before = unit_faces(&faces);
+        for cell in [[-1, 0, 0], [0, 16, 0], [4, 8, 20]] {
+            assert!(edit_cell(&mut world, &mut chunks, &mut light_map, &mut faces, cell, Some(3)).is_empty());
+        }
+        assert!(unit_faces(&faces) == before);
+    }
+
+    #[test]
+    fn rays_stop_at_the_first_solid_cell() {
+        let (chunks, _) = light_world(&creased_block());
+        let down = Vector3::new(0.0, -1.0, 0.0);
+
+        // Onto the top of the block, and onto the wall standing on it
+        assert_eq!(raycast_cells(&chunks, Vector3::new(2.5, 12.5, 2.5), down, 10.0), Some(([2, 7, 2], Some([2, 8, 2]))));
+        assert_eq!(raycast_cells(&chunks, Vector3::new(4.5, 12.5, 2.5), down, 10.0), Some(([4, 8, 2], Some([4, 9, 2]))));
+        // Sideways into the wall
+        assert_eq!(raycast_cells(&chunks, Vector3::new(0.5, 8.5, 6.5), Vector3::new(1.0, 0.0, 0.0), 10.0), Some(([4, 8, 6], Some([3, 8, 6]))));
+        // Out of reach, away from the block and from inside it
+        assert_eq!(raycast_cells(&chunks, Vector3::new(2.5, 12.5, 2.5), down, 3.0), None);
+        assert_eq!(raycast_cells(&chunks, Vector3::new(2.5, 12.5, 2.5), Vector3::new(0.0, 1.0, 0.0), 10.0), None);
+        assert_eq!(raycast_cells(&chunks, Vector3::new(2.5, 2.5, 2.5), down, 10.0), Some(([2, 2, 2], None)));
     }
 }
- 
\ No newline at end of file
@@ -1,4 +1,4 @@
-use engine::{light::DirectionalLight, utils, voxel_engine::VoxelEngine};
+use engine::{capture::panorama::{self, PanoramaFormat}, headless_engine, light::{DirectionalLight, ShadingModel, day_cycle::DayCycle, clustered_lights::{LocalLight, LocalLightKind, MAX_LOCAL_LIGHTS}, shadow_map::{MAX_CASCADES, SHADOW_MAP_RESOLUTIONS}, ssao::MAX_SSAO_SAMPLES}, post::{self, Tonemapper}, utils, voxel_engine::VoxelEngine};
 use imgui::*;
 use winit::{
     event::{ElementState, Event, KeyboardInput, WindowEvent},
@@ -11,13 +11,24 @@ use engine::camera::fps_camera::FpsCamera;
 fn main() {
     env_logger::init();
 
+    let args : Vec<String> = std::env::args().collect();
+    if let Some(options) = headless_engine::HeadlessOptions::from_args(&args) {
+        headless_engine::render_to_png(&options);
+        return;
+    }
+
     let event_loop = EventLoop::new();
     let mut engine = EngineData::new(&event_loop);
     
     let mut player = FpsCamera::new(&engine);
     let mut light = DirectionalLight::new(); 
+    let mut day_cycle = DayCycle::new();
 
-    let mut mesh_engine = VoxelEngine::init(engine.get_device(), &engine.surface_engine.get_surface_desc(), &player, &light);
+    let mut mesh_engine = VoxelEngine::init(engine.get_device(), engine.get_queue(), &post::HDR_FORMAT, &player, &light);
+    let mut world_seed : i32 = 42;
+    let mut edit_material : u32 = 8;
+    let mut lut_path = String::new();
+    let mut skybox_path = String::new();
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = utils::get_control_flow_status();
@@ -38,6 +49,7 @@ fn main() {
                 event: WindowEvent::Resized(_),
                 ..
             } => { engine.resize_surface(); }
+            // Keys typed into a text field are not meant for the camera or the hotkeys
             | Event::WindowEvent {
                 event:
                     WindowEvent::KeyboardInput {
@@ -50,7 +62,7 @@ fn main() {
                         ..
                     },
                 ..
-            } => { engine.update_key_state(keycode, true); }
+            } if !engine.ui_wants_keyboard() => { engine.update_key_state(keycode, true); }
             | Event::WindowEvent {
                 event:
                     WindowEvent::KeyboardInput {
@@ -81,17 +93,26 @@ fn main() {
                 let delta_time = engine.delta_time(); 
                 player.update(delta_time, &engine);
 
-                mesh_engine.update(engine.get_device(), &player, &light); 
+                day_cycle.update(delta_time);
+                day_cycle.apply(&mut light);
+                mesh_engine.set_background_color(day_cycle.sky_color());
+                mesh_engine.set_sun(day_cycle.sun());
+
+                mesh_engine.update(engine.get_device(), engine.get_queue(), &player.get_camera_view(), &light, engine.get_window_size()); 
 
                 let mut encoder = engine.get_encoder();
-                let ui = engine.imgui_engine.imgui_context.frame();
 
                 engine.surface_engine.begin_frame();
 
-                mesh_engine.render(engine.surface_engine.get_view(), &engine.depth_texture, &mut encoder, &player);
+                let (color_target, resolve_target) = engine.get_color_targets();
+                mesh_engine.render(color_target, resolve_target, &engine.depth_texture, &mut encoder, &player.get_camera_view());
+
+                let sample_counts = engine.supported_sample_counts();
+                let mut sample_count = engine.get_sample_count();
+                let ui = engine.imgui_engine.imgui_context.frame();
 
                 ui.window("Utils")
-                    .size([400.0, 300.0], Condition::FirstUseEver)
+                    .size([400.0, 920.0], Condition::FirstUseEver)
                     .build(||{
                         if ui.button("Quit"){
                             *control_flow = ControlFlow::Exit;
@@ -109,21 +130,281 @@ fn main() {
 
                         ui.text(format!("FPS: {:.0}", 1.0/delta_time));
 
+                        let mut sample_index = sample_counts.iter().position(|count| *count == sample_count).unwrap_or(0);
+                        let sample_names : Vec<String> = sample_counts.iter().map(|count| format!("{}x", count)).collect();
+                        if ui.combo_simple_string("MSAA", &mut sample_index, &sample_names) {
+                            sample_count = sample_counts[sample_index];
+                        }
+
+                        ui.separator();
+
+                        ui.checkbox("Day cycle", &mut day_cycle.enabled);
+                        if day_cycle.enabled {
+                            ui.same_line();
+                            ui.checkbox("Pause", &mut day_cycle.paused);
+                            ui.slider("Day length (s)", 10.0, 1200.0, &mut day_cycle.day_length);
+                            let mut hours = day_cycle.time_of_day * 24.0;
+                            ui.slider_config("Time of day", 0.0, 24.0).display_format("%.1f h").build(&mut hours);
+                            day_cycle.time_of_day = (hours / 24.0).rem_euclid(1.0);
+                            ui.slider("Sunrise azimuth", 0.0, 360.0, &mut day_cycle.sunrise_azimuth);
+                            ui.slider("Noon elevation", 0.0, 90.0, &mut day_cycle.noon_elevation);
+                        } else {
+                            let mut light_direction : [f32; 3] = light.direction.into();
+                            ui.input_float3("Light Direction", &mut light_direction).build();
+                            let mut light_color : [f32; 3] = light.color.into();
+                            ui.input_float3("Light Color", &mut light_color).build();  
+                            let mut light_ambient : [f32; 3] = light.ambient.into();
+                            ui.input_float3("Ambient", &mut light_ambient).build();
+
+                            light.direction = light_direction.into(); 
+                            light.color = light_color.into(); 
+                            light.ambient = light_ambient.into();
+                        }
+
+                        ui.text("Shading");
+                        ui.same_line();
+                        ui.radio_button("PBR", &mut light.shading, ShadingModel::Pbr);
+                        ui.same_line();
+                        ui.radio_button("Phong", &mut light.shading, ShadingModel::Phong);
+                        ui.same_line();
+                        ui.radio_button("Normals", &mut light.shading, ShadingModel::Normals);
+
+                        ui.checkbox("Shadows", &mut light.shadows.enabled);
+                        ui.slider("Shadow bias", 0.0, 2.0, &mut light.shadows.bias);
+                        let mut resolution_index = SHADOW_MAP_RESOLUTIONS.iter().position(|r| *r == light.shadows.resolution).unwrap_or(0);
+                        let resolution_names = SHADOW_MAP_RESOLUTIONS.map(|r| r.to_string());
+                        if ui.combo_simple_string("Shadow resolution", &mut resolution_index, &resolution_names) {
+                            light.shadows.resolution = SHADOW_MAP_RESOLUTIONS[resolution_index];
+                        }
+                        ui.slider("Cascades", 1, MAX_CASCADES as u32, &mut light.shadows.cascade_count);
+                        ui.slider("Shadow distance", 50.0, 1000.0, &mut light.shadows.distance);
+                        ui.slider("Split lambda", 0.0, 1.0, &mut light.shadows.split_lambda);
+                        ui.slider("Cascade blend", 0.0, 0.5, &mut light.shadows.blend_width);
+                        ui.checkbox("Show cascades", &mut light.shadows.debug_cascades);
+
+                        ui.separator();
+
+                        let ssao = mesh_engine.get_ssao_settings();
+                        ui.checkbox("SSAO", &mut ssao.enabled);
+                        ui.slider("SSAO radius", 0.1, 5.0, &mut ssao.radius);
+                        ui.slider("SSAO strength", 0.0, 2.0, &mut ssao.strength);
+                        ui.slider("SSAO samples", 1, MAX_SSAO_SAMPLES as u32, &mut ssao.sample_count);
+                        ui.slider("SSAO bias", 0.0, 0.2, &mut ssao.bias);
+
                         ui.separator();
 
-                        let mut light_direction : [f32; 3] = light.direction.into();
-                        ui.input_float3("Light Direction", &mut light_direction).build();
-                        let mut light_color : [f32; 3] = light.color.into();
-                        ui.input_float3("Light Color", &mut light_color).build();  
+                        let atmosphere = mesh_engine.get_atmosphere_settings();
+                        ui.checkbox("Atmosphere", &mut atmosphere.enabled);
+                        ui.slider("Aerial perspective", 0.0, 4.0, &mut atmosphere.aerial_perspective);
+                        ui.slider_config("Km per unit", 0.001, 0.2).display_format("%.3f").build(&mut atmosphere.scale);
 
-                        light.direction = light_direction.into(); 
-                        light.color = light_color.into(); 
+                        // A directory of px, nx, py, ny, pz and nz images or an equirectangular panorama
+                        let skybox = mesh_engine.get_skybox_settings();
+                        ui.input_text("Skybox", &mut skybox_path).build();
+                        ui.same_line();
+                        if ui.button("Load##skybox") {
+                            skybox.texture = (!skybox_path.is_empty()).then(|| skybox_path.clone().into());
+                        }
+                        if skybox.texture.is_some() {
+                            ui.same_line();
+                            if ui.button("Atmosphere##skybox") {
+                                skybox.texture = None;
+                            }
+                        }
+                        ui.slider("Skybox intensity", 0.0, 4.0, &mut skybox.intensity);
+
+                        let fog = mesh_engine.get_fog_settings();
+                        ui.checkbox("Fog", &mut fog.enabled);
+                        let mut fog_color : [f32; 3] = fog.color.into();
+                        ui.color_edit3("Fog color", &mut fog_color);
+                        fog.color = fog_color.into();
+                        ui.slider_config("Fog density", 0.0, 0.02).display_format("%.4f").build(&mut fog.density);
+                        ui.slider_config("Height fog density", 0.0, 0.2).display_format("%.3f").build(&mut fog.height_density);
+                        ui.slider("Height fog falloff", 0.01, 1.0, &mut fog.height_falloff);
+                        ui.slider("Height fog base", -20.0, 100.0, &mut fog.base_height);
+                        ui.checkbox("Underwater", &mut fog.underwater_enabled);
+                        if fog.is_underwater(player.position) {
+                            ui.same_line();
+                            ui.text("(diving)");
+                        }
+                        ui.slider("Water level", 0.0, 100.0, &mut fog.water_level);
+                        let mut water_color : [f32; 3] = fog.water_color.into();
+                        ui.color_edit3("Water color", &mut water_color);
+                        fog.water_color = water_color.into();
+                        ui.slider("Water visibility", 1.0, 200.0, &mut fog.water_visibility);
+
+                        ui.separator();
+
+                        let post = &mut engine.post.settings;
+                        ui.checkbox("Bloom", &mut post.bloom_enabled);
+                        ui.slider("Bloom threshold", 0.0, 4.0, &mut post.bloom_threshold);
+                        ui.slider("Bloom intensity", 0.0, 2.0, &mut post.bloom_intensity);
+                        ui.slider("Bloom radius", 0.0, 1.0, &mut post.bloom_radius);
+                        ui.checkbox("Exposure", &mut post.exposure_enabled);
+                        ui.same_line();
+                        ui.slider("Stops", -4.0, 4.0, &mut post.exposure);
+                        ui.checkbox("Tonemapping", &mut post.tonemapping_enabled);
+                        ui.same_line();
+                        ui.radio_button("ACES", &mut post.tonemapper, Tonemapper::Aces);
+                        ui.same_line();
+                        ui.radio_button("Reinhard", &mut post.tonemapper, Tonemapper::Reinhard);
+                        ui.checkbox("Colour grading", &mut post.grading_enabled);
+                        ui.same_line();
+                        ui.slider("Strength", 0.0, 1.0, &mut post.grading_strength);
+                        ui.input_text("LUT", &mut lut_path).build();
+                        ui.same_line();
+                        if ui.button("Load") {
+                            post.grading_lut = (!lut_path.is_empty()).then(|| lut_path.clone().into());
+                        }
+                        ui.checkbox("Gamma", &mut post.gamma_enabled);
+                        ui.same_line();
+                        ui.slider("Value", 1.0, 3.0, &mut post.gamma);
 
                         ui.separator();
+
+                        let capture = &mut engine.capture;
+                        ui.checkbox("Capture UI", &mut capture.include_ui);
+                        if ui.button("Screenshot (P)") {
+                            capture.request_screenshot();
+                        }
+                        ui.same_line();
+                        let record_label = if capture.is_recording() { "Stop recording (R)" } else { "Record (R)" };
+                        if ui.button(record_label) {
+                            capture.toggle_recording();
+                        }
+                        if capture.is_recording() {
+                            ui.text(format!("Recorded frames: {}", capture.recorded_frames()));
+                        } else {
+                            ui.input_float("Record timestep", &mut capture.timestep).build();
+                            capture.timestep = capture.timestep.max(0.001);
+                        }
+
+                        ui.radio_button("Cube faces", &mut capture.panorama_format, PanoramaFormat::CubeFaces);
+                        ui.same_line();
+                        ui.radio_button("Equirectangular", &mut capture.panorama_format, PanoramaFormat::Equirectangular);
+                        if ui.button("Capture panorama") {
+                            capture.request_panorama();
+                        }
                     }
                 );                 
-                
+
+                ui.window("Lights")
+                    .size([400.0, 500.0], Condition::FirstUseEver)
+                    .build(||{
+                        let lights = mesh_engine.get_local_lights();
+                        ui.text(format!("Lights: {} / {}", lights.len(), MAX_LOCAL_LIGHTS));
+                        // New lights are placed at the camera, spot lights shining where it looks
+                        if lights.len() < MAX_LOCAL_LIGHTS {
+                            if ui.button("Add torch") {
+                                lights.push(LocalLight::point(player.position, [1.0, 0.6, 0.3].into(), 20.0, 15.0));
+                            }
+                            ui.same_line();
+                            if ui.button("Add lamp") {
+                                lights.push(LocalLight::spot(player.position, player.forward, [1.0, 0.95, 0.85].into(), 60.0, 40.0));
+                            }
+                        }
+
+                        let mut removed = None;
+                        for (i, light) in lights.iter_mut().enumerate() {
+                            let _id = ui.push_id_usize(i);
+                            let mut open = true;
+                            let kind = if light.kind == LocalLightKind::Spot { "Spot" } else { "Point" };
+                            if ui.collapsing_header_with_close_button(format!("{} light {}", kind, i), TreeNodeFlags::empty(), &mut open) {
+                                let mut position : [f32; 3] = light.position.into();
+                                ui.input_float3("Position", &mut position).build();
+                                light.position = position.into();
+                                let mut color : [f32; 3] = light.color.into();
+                                ui.color_edit3("Color", &mut color);
+                                light.color = color.into();
+                                ui.slider("Intensity", 0.0, 200.0, &mut light.intensity);
+                                ui.slider("Range", 1.0, 100.0, &mut light.range);
+                                ui.radio_button("Point", &mut light.kind, LocalLightKind::Point);
+                                ui.same_line();
+                                ui.radio_button("Spot", &mut light.kind, LocalLightKind::Spot);
+                                if light.kind == LocalLightKind::Spot {
+                                    let mut direction : [f32; 3] = light.direction.into();
+                                    ui.input_float3("Direction", &mut direction).build();
+                                    light.direction = direction.into();
+                                    ui.slider("Inner angle", 0.0, 90.0, &mut light.inner_angle);
+                                    ui.slider("Outer angle", 0.0, 90.0, &mut light.outer_angle);
+                                }
+                            }
+                            if !open {
+                                removed = Some(i);
+                            }
+                        }
+                        if let Some(i) = removed {
+                            lights.remove(i);
+                        }
+                    }
+                );
+
+                let mut regenerate_world = false;
+                // Voxel to place, or None to dig, at the cell the camera looks at
+                let mut voxel_edit = None;
+                let material_count = mesh_engine.get_material_count();
+                let world_stats = mesh_engine.get_world_stats();
+                ui.window("World")
+                    .size([400.0, 380.0], Condition::FirstUseEver)
+                    .build(||{
+                        let to_mib = |bytes : f64| bytes / (1024.0 * 1024.0);
+
+                        ui.text(format!("Nodes: {}", world_stats.octree.node_count));
+                        ui.text(format!("Leaves: {} ({} collapsed)", world_stats.octree.leaf_count, world_stats.octree.collapsed_count()));
+                        ui.text(format!("CPU: {:.2} MiB", to_mib(world_stats.octree.cpu_bytes as f64)));
+                        ui.text(format!("GPU instances: {:.2} MiB", to_mib(world_stats.gpu_bytes() as f64)));
+                        for (face, bytes) in world_stats.face_instance_bytes.iter() {
+                            ui.text(format!("    {}: {:.2} MiB", face, to_mib(*bytes as f64)));
+                        }
+
+                        ui.separator();
+
+                        let leaves : Vec<f32> = world_stats.octree.leaves_per_depth.iter().map(|count| *count as f32).collect();
+                        ui.plot_histogram("Leaves per depth", &leaves).graph_size([0.0, 60.0]).build();
+                        let collapsed : Vec<f32> = world_stats.octree.collapsed_per_depth.iter().map(|count| *count as f32).collect();
+                        ui.plot_histogram("Collapsed per depth", &collapsed).graph_size([0.0, 60.0]).build();
+
+                        ui.separator();
+
+                        ui.input_int("Seed", &mut world_seed).build();
+                        if ui.button("Regenerate") {
+                            regenerate_world = true;
+                        }
+
+                        ui.separator();
+
+                        ui.slider("Material", 0, material_count - 1, &mut edit_material);
+                        if ui.button("Place voxel") {
+                            voxel_edit = Some(Some(edit_material));
+                        }
+                        ui.same_line();
+                        if ui.button("Dig voxel") {
+                            voxel_edit = Some(None);
+                        }
+                    }
+                );
+
                 engine.end_frame(encoder);
+
+                if let Some(output) = engine.capture.take_panorama_request() {
+                    let faces = engine.capture_cubemap(&mut mesh_engine, &light, player.position);
+                    if let Err(e) = panorama::save_panorama(&faces, engine.capture.panorama_format, &output) {
+                        eprintln!("failed to save panorama {:?}: {}", output, e);
+                    }
+                }
+
+                if sample_count != engine.get_sample_count() {
+                    engine.set_sample_count(sample_count);
+                    mesh_engine.set_sample_count(engine.get_device(), sample_count);
+                }
+
+                if regenerate_world {
+                    mesh_engine.regenerate(engine.get_device(), world_seed as u32);
+                }
+
+                if let Some(material) = voxel_edit {
+                    mesh_engine.edit_target(engine.get_device(), player.position, player.forward, material);
+                }
             }
             _ => (),
         }